@@ -1,10 +1,35 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup};
+
+use std::path::Path;
 
 pub struct CommandLineConfig {
     pub rom_path: String,
     pub sram_path: String,
+    pub symbols_path: String,
+    pub symbol_file_paths: Vec<String>,
+    pub record_movie_path: Option<String>,
+    pub play_movie_path: Option<String>,
+    pub headless: Option<HeadlessConfig>,
+    pub gdb_port: Option<u16>,
+    pub trace_path: Option<String>,
+    pub coverage_prefix: Option<String>,
+    pub cheats_path: Option<String>,
+}
+
+pub enum RunLength {
+    Frames(u64),
+    Cycles(u64),
 }
 
+pub struct HeadlessConfig {
+    pub run_length: RunLength,
+    pub input_script_path: Option<String>,
+    pub dump_frame_prefix: Option<String>,
+    pub dump_audio_path: Option<String>,
+}
+
+// crate_authors! still expands to the deprecated ONCE_INIT
+#[allow(deprecated)]
 pub fn parse_args() -> CommandLineConfig {
     let app = App::new("Rustual Boy")
         .version("0.2.0")
@@ -18,6 +43,84 @@ pub fn parse_args() -> CommandLineConfig {
               .help("Path to an SRAM")
               .short("s")
               .long("sram")
+        ).arg(Arg::with_name("RECORD_MOVIE")
+              .help("Record game pad input into a movie file (starts with blank SRAM)")
+              .long("record-movie")
+              .takes_value(true)
+              .value_name("FILE")
+              .conflicts_with("PLAY_MOVIE")
+        ).arg(Arg::with_name("PLAY_MOVIE")
+              .help("Play back game pad input from a movie file (starts with blank SRAM)")
+              .long("play-movie")
+              .takes_value(true)
+              .value_name("FILE")
+        ).arg(Arg::with_name("HEADLESS")
+              .help("Run without a window or audio device, then print a hash of WRAM and SRAM")
+              .long("headless")
+              .requires("RUN_LENGTH")
+              .conflicts_with("RECORD_MOVIE")
+        ).arg(Arg::with_name("FRAMES")
+              .help("Number of frames to run in headless mode")
+              .long("frames")
+              .takes_value(true)
+              .value_name("COUNT")
+              .requires("HEADLESS")
+        ).arg(Arg::with_name("CYCLES")
+              .help("Number of CPU cycles to run in headless mode")
+              .long("cycles")
+              .takes_value(true)
+              .value_name("COUNT")
+              .requires("HEADLESS")
+        ).group(ArgGroup::with_name("RUN_LENGTH")
+                .args(&["FRAMES", "CYCLES"])
+        ).arg(Arg::with_name("INPUT_SCRIPT")
+              .help("Input script to drive the game pad in headless mode")
+              .long("input-script")
+              .takes_value(true)
+              .value_name("FILE")
+              .requires("HEADLESS")
+        ).arg(Arg::with_name("DUMP_FRAME")
+              .help("Dump the last video frame in headless mode to PREFIX-left.png and PREFIX-right.png")
+              .long("dump-frame")
+              .takes_value(true)
+              .value_name("PREFIX")
+              .requires("HEADLESS")
+        ).arg(Arg::with_name("DUMP_AUDIO")
+              .help("Dump audio in headless mode to a WAV file")
+              .long("dump-audio")
+              .takes_value(true)
+              .value_name("FILE")
+              .requires("HEADLESS")
+        ).arg(Arg::with_name("SYMBOLS")
+              .help("Load debugger labels from an ELF or symbol map file (labels in ROM.sym are loaded automatically)")
+              .long("symbols")
+              .takes_value(true)
+              .multiple(true)
+              .number_of_values(1)
+              .value_name("FILE")
+        ).arg(Arg::with_name("GDB")
+              .help("Wait for gdb to connect on a local TCP port before starting (gdb: target remote localhost:PORT)")
+              .long("gdb")
+              .takes_value(true)
+              .value_name("PORT")
+              .conflicts_with("HEADLESS")
+        ).arg(Arg::with_name("TRACE")
+              .help("Write every executed instruction to a trace file from power-on (also controlled by the debugger's trace command)")
+              .long("trace")
+              .takes_value(true)
+              .value_name("FILE")
+              .conflicts_with("HEADLESS")
+        ).arg(Arg::with_name("CHEATS")
+              .help("Load cheats from a cheat file (by default, the ROM's game and maker codes followed by .cht, next to the ROM)")
+              .long("cheats")
+              .takes_value(true)
+              .value_name("FILE")
+              .conflicts_with("HEADLESS")
+        ).arg(Arg::with_name("COVERAGE")
+              .help("Record which ROM bytes were executed or read as data, then save PREFIX.cov (one flag byte per ROM byte) and an annotated disassembly in PREFIX.lst")
+              .long("coverage")
+              .takes_value(true)
+              .value_name("PREFIX")
         );
 
     let matches = app.get_matches();
@@ -31,5 +134,31 @@ pub fn parse_args() -> CommandLineConfig {
             Some(v) => v.into(),
             None => rom_path.replace(".vb", ".srm")
         },
+        symbols_path: Path::new(rom_path).with_extension("sym").to_string_lossy().into_owned(),
+        symbol_file_paths: matches.values_of("SYMBOLS").map_or(Vec::new(), |values| values.map(|v| v.into()).collect()),
+        record_movie_path: matches.value_of("RECORD_MOVIE").map(|v| v.into()),
+        play_movie_path: matches.value_of("PLAY_MOVIE").map(|v| v.into()),
+        headless: if matches.is_present("HEADLESS") {
+            Some(HeadlessConfig {
+                run_length: if matches.is_present("FRAMES") {
+                    RunLength::Frames(value_t!(matches, "FRAMES", u64).unwrap_or_else(|e| e.exit()))
+                } else {
+                    RunLength::Cycles(value_t!(matches, "CYCLES", u64).unwrap_or_else(|e| e.exit()))
+                },
+                input_script_path: matches.value_of("INPUT_SCRIPT").map(|v| v.into()),
+                dump_frame_prefix: matches.value_of("DUMP_FRAME").map(|v| v.into()),
+                dump_audio_path: matches.value_of("DUMP_AUDIO").map(|v| v.into()),
+            })
+        } else {
+            None
+        },
+        gdb_port: if matches.is_present("GDB") {
+            Some(value_t!(matches, "GDB", u16).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        },
+        trace_path: matches.value_of("TRACE").map(|v| v.into()),
+        coverage_prefix: matches.value_of("COVERAGE").map(|v| v.into()),
+        cheats_path: matches.value_of("CHEATS").map(|v| v.into()),
     }
 }
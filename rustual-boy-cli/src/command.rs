@@ -1,7 +1,12 @@
-use combine::{choice, eof, many1, optional, Parser, parser, try, value};
-use combine::char::{alpha_num, digit, hex_digit, space, spaces, string};
+use combine::{between, chainl1, choice, eof, many, many1, not_followed_by, one_of, optional, Parser, parser, satisfy, try, value};
+use combine::char::{char, digit, hex_digit, space, spaces, string};
 use combine::primitives::{ParseResult, Stream};
 
+use expression::*;
+
+use rustual_boy_core::hooks::AccessSize;
+use rustual_boy_middleware::{CheatCodeKind, SearchFilter, SearchRegion, VramPalette};
+
 use std::str::{self, FromStr};
 use std::borrow::Cow;
 
@@ -10,19 +15,70 @@ pub enum Command {
     ShowCpuCache,
     ShowRegs,
     Step(u32),
+    Next,
+    Finish,
+    Until(Expr),
+    RunToFrame,
+    RunToInterrupt,
     Continue,
-    Goto(u32),
-    ShowMem(Option<u32>),
+    Goto(Expr),
+    ShowMem(Option<Expr>),
+    SetMem(Expr, Expr, AccessSize),
+    Fill(Expr, u32, Expr, AccessSize),
+    LoadMem(String, Expr),
+    SetReg(String, Expr),
     Disassemble(u32),
     Label,
     AddLabel(String, u32),
     RemoveLabel(String),
+    LoadLabels(String),
+    SaveLabels(Option<String>),
     Breakpoint,
-    AddBreakpoint(u32),
-    RemoveBreakpoint(u32),
+    AddBreakpoint(Expr, Option<Expr>),
+    RemoveBreakpoint(Expr),
+    SetBreakpointCondition(Expr, Option<Expr>),
+    IgnoreBreakpoint(Expr, u32),
     Watchpoint,
-    AddWatchpoint(u32),
-    RemoveWatchpoint(u32),
+    AddWatchpoint(Expr),
+    RemoveWatchpoint(Expr),
+    Print(Expr),
+    Backtrace,
+    ShowTrace,
+    StartTrace(String),
+    StopTrace,
+    SetTraceAddrRange(Option<(Expr, Expr)>),
+    SetTraceFrameRange(Option<(u64, u64)>),
+    ShowProfile,
+    ShowCallGraph,
+    StartProfile,
+    StopProfile,
+    SaveProfile(String),
+    SaveFoldedProfile(String),
+    ShowCoverage,
+    StartCoverage,
+    StopCoverage,
+    ClearCoverage,
+    SaveCoverageMap(String),
+    SaveCoverageListing(String),
+    ShowSearch,
+    StartSearch(SearchRegion, AccessSize),
+    FilterSearch(SearchFilter),
+    FilterSearchValue(Expr),
+    ShowCheats,
+    EnableCheat(usize, bool),
+    AddCheatCode(CheatCodeKind, Expr, AccessSize, Expr),
+    RemoveCheat(usize),
+    LoadCheats(String),
+    SaveCheats(Option<String>),
+    ShowWorlds,
+    ShowObjs(u32, u32),
+    ShowChar(u32),
+    SaveChars(String, VramPalette),
+    SaveBgMap(u32, String),
+    SaveObjs(String),
+    EnableWorld(u32, bool),
+    EnableObjGroup(u32, bool),
+    HighlightWorld(Option<u32>),
     Exit,
     Repeat,
 }
@@ -55,22 +111,74 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|(_, count)| Command::Step(count.unwrap_or(1)))
         .boxed();
 
+    let next =
+        choice([try(string("next")), try(string("n"))])
+        .map(|_| Command::Next)
+        .boxed();
+
+    let finish =
+        choice([try(string("finish")), try(string("f"))])
+        .map(|_| Command::Finish)
+        .boxed();
+
+    let until =
+        (choice([try(string("until")), try(string("u"))]), space(), address())
+        .map(|(_, _, addr)| Command::Until(addr))
+        .boxed();
+
+    let run_to_frame =
+        choice([try(string("nextframe")), try(string("nf"))])
+        .map(|_| Command::RunToFrame)
+        .boxed();
+
+    let run_to_interrupt =
+        choice([try(string("nextinterrupt")), try(string("ni"))])
+        .map(|_| Command::RunToInterrupt)
+        .boxed();
+
     let continue_ =
         choice([try(string("continue")), try(string("c"))])
         .map(|_| Command::Continue)
         .boxed();
 
     let goto =
-        (choice([try(string("goto")), try(string("g"))]), spaces(), u32_hex())
+        (choice([try(string("goto")), try(string("g"))]), spaces(), address())
         .map(|(_, _, addr)| Command::Goto(addr))
         .boxed();
 
     let show_mem =
         (choice([try(string("showmem")), try(string("m"))]),
-            optional((spaces(), u32_hex()).map(|x| x.1)))
+            optional((spaces(), address()).map(|x| x.1)))
         .map(|(_, addr)| Command::ShowMem(addr))
         .boxed();
 
+    let set_mem =
+        (string("setmem"),
+            (space(), spaces(), address()).map(|x| x.2),
+            (space(), spaces(), parser(expression::<I>)).map(|x| x.2),
+            optional(access_size()))
+        .map(|(_, addr, value, size)| Command::SetMem(addr, value, size.unwrap_or(AccessSize::Byte)))
+        .boxed();
+
+    let fill =
+        (string("fill"),
+            (space(), spaces(), address()).map(|x| x.2),
+            (space(), spaces(), u32_()).map(|x| x.2),
+            (space(), spaces(), parser(expression::<I>)).map(|x| x.2),
+            optional(access_size()))
+        .map(|(_, addr, count, value, size)| Command::Fill(addr, count, value, size.unwrap_or(AccessSize::Byte)))
+        .boxed();
+
+    let load_mem =
+        (string("load"), space(), spaces(), file_name(), space(), spaces(), address())
+        .map(|(_, _, _, file_name, _, _, addr)| Command::LoadMem(file_name, addr))
+        .boxed();
+
+    let set_reg =
+        (string("setreg"), space(), spaces(), identifier(), space(), spaces(), parser(expression::<I>))
+        .map(|(_, _, _, name, _, _, value)| Command::SetReg(name, value))
+        .boxed();
+
     let disassemble =
         (choice([try(string("disassemble")), try(string("d"))]),
             optional((spaces(), u32_()).map(|x| x.1)))
@@ -98,6 +206,19 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|(_, _, name)| Command::RemoveLabel(name))
         .boxed();
 
+    let load_labels =
+        (choice([try(string("loadlabels")), try(string("ll"))]),
+            space(),
+            file_name())
+        .map(|(_, _, file_name)| Command::LoadLabels(file_name))
+        .boxed();
+
+    let save_labels =
+        (choice([try(string("savelabels")), try(string("sl"))]),
+            optional((space(), file_name()).map(|x| x.1)))
+        .map(|(_, file_name)| Command::SaveLabels(file_name))
+        .boxed();
+
     let breakpoint =
         choice([try(string("breakpoint")), try(string("b"))])
         .map(|_| Command::Breakpoint)
@@ -106,17 +227,35 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
     let add_breakpoint =
         (choice([try(string("addbreakpoint")), try(string("ab"))]),
             space(),
-            u32_hex())
-        .map(|(_, _, addr)| Command::AddBreakpoint(addr))
+            address(),
+            optional((try((spaces(), string("if"))), space(), spaces(), parser(expression::<I>)).map(|x| x.3)))
+        .map(|(_, _, addr, condition)| Command::AddBreakpoint(addr, condition))
         .boxed();
 
     let remove_breakpoint =
         (choice([try(string("removebreakpoint")), try(string("rb"))]),
             space(),
-            u32_hex())
+            address())
         .map(|(_, _, addr)| Command::RemoveBreakpoint(addr))
         .boxed();
 
+    let set_breakpoint_condition =
+        (choice([try(string("condition")), try(string("cb"))]),
+            space(),
+            address(),
+            optional((space(), spaces(), parser(expression::<I>)).map(|x| x.2)))
+        .map(|(_, _, addr, condition)| Command::SetBreakpointCondition(addr, condition))
+        .boxed();
+
+    let ignore_breakpoint =
+        (choice([try(string("ignore")), try(string("ib"))]),
+            space(),
+            address(),
+            space(),
+            u32_())
+        .map(|(_, _, addr, _, count)| Command::IgnoreBreakpoint(addr, count))
+        .boxed();
+
     let watchpoint =
         choice([try(string("watchpoint")), try(string("w"))])
         .map(|_| Command::Watchpoint)
@@ -125,17 +264,225 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
     let add_watchpoint =
         (choice([try(string("addwatchpoint")), try(string("aw"))]),
             space(),
-            u32_hex())
+            address())
         .map(|(_, _, addr)| Command::AddWatchpoint(addr))
         .boxed();
 
     let remove_watchpoint =
         (choice([try(string("removewatchpoint")), try(string("rw"))]),
             space(),
-            u32_hex())
+            address())
         .map(|(_, _, addr)| Command::RemoveWatchpoint(addr))
         .boxed();
 
+    let print =
+        (choice([try(string("print")), try(string("p"))]),
+            space(),
+            spaces(),
+            parser(expression::<I>))
+        .map(|(_, _, _, expr)| Command::Print(expr))
+        .boxed();
+
+    let backtrace =
+        choice([try(string("backtrace")), try(string("bt"))])
+        .map(|_| Command::Backtrace)
+        .boxed();
+
+    let show_trace =
+        string("trace")
+        .map(|_| Command::ShowTrace)
+        .boxed();
+
+    let start_trace =
+        (string("trace"), space(), spaces(), try(string("on")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::StartTrace(file_name))
+        .boxed();
+
+    let stop_trace =
+        (string("trace"), space(), spaces(), try(string("off")))
+        .map(|_| Command::StopTrace)
+        .boxed();
+
+    let set_trace_addr_range =
+        (string("trace"), space(), spaces(), try(string("addr")),
+            optional((space(), spaces(), address(), space(), spaces(), address()).map(|x| (x.2, x.5))))
+        .map(|(_, _, _, _, range)| Command::SetTraceAddrRange(range))
+        .boxed();
+
+    let set_trace_frame_range =
+        (string("trace"), space(), spaces(), try(string("frames")),
+            optional((space(), spaces(), u64_(), space(), spaces(), u64_()).map(|x| (x.2, x.5))))
+        .map(|(_, _, _, _, range)| Command::SetTraceFrameRange(range))
+        .boxed();
+
+    let show_profile =
+        string("profile")
+        .map(|_| Command::ShowProfile)
+        .boxed();
+
+    let show_call_graph =
+        (string("profile"), space(), spaces(), try(string("calls")))
+        .map(|_| Command::ShowCallGraph)
+        .boxed();
+
+    let start_profile =
+        (string("profile"), space(), spaces(), try(string("on")))
+        .map(|_| Command::StartProfile)
+        .boxed();
+
+    let stop_profile =
+        (string("profile"), space(), spaces(), try(string("off")))
+        .map(|_| Command::StopProfile)
+        .boxed();
+
+    let save_profile =
+        (string("profile"), space(), spaces(), try(string("save")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::SaveProfile(file_name))
+        .boxed();
+
+    let save_folded_profile =
+        (string("profile"), space(), spaces(), try(string("folded")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::SaveFoldedProfile(file_name))
+        .boxed();
+
+    let show_coverage =
+        string("coverage")
+        .map(|_| Command::ShowCoverage)
+        .boxed();
+
+    let start_coverage =
+        (string("coverage"), space(), spaces(), try(string("on")))
+        .map(|_| Command::StartCoverage)
+        .boxed();
+
+    let stop_coverage =
+        (string("coverage"), space(), spaces(), try(string("off")))
+        .map(|_| Command::StopCoverage)
+        .boxed();
+
+    let clear_coverage =
+        (string("coverage"), space(), spaces(), try(string("clear")))
+        .map(|_| Command::ClearCoverage)
+        .boxed();
+
+    let save_coverage_map =
+        (string("coverage"), space(), spaces(), try(string("save")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::SaveCoverageMap(file_name))
+        .boxed();
+
+    let save_coverage_listing =
+        (string("coverage"), space(), spaces(), try(string("listing")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::SaveCoverageListing(file_name))
+        .boxed();
+
+    let show_search =
+        string("search")
+        .map(|_| Command::ShowSearch)
+        .boxed();
+
+    let start_search =
+        (string("search"), space(), spaces(), try(string("new")),
+            optional(try((space(), spaces(), search_region())).map(|x| x.2)),
+            optional(try((space(), spaces(), access_size())).map(|x| x.2)))
+        .map(|(_, _, _, _, region, size)| Command::StartSearch(region.unwrap_or(SearchRegion::Wram), size.unwrap_or(AccessSize::Byte)))
+        .boxed();
+
+    let filter_search =
+        (string("search"), space(), spaces(), search_filter())
+        .map(|(_, _, _, filter)| Command::FilterSearch(filter))
+        .boxed();
+
+    let filter_search_value =
+        (string("search"), space(), spaces(), try(string("value")), space(), spaces(), parser(expression::<I>))
+        .map(|(_, _, _, _, _, _, expr)| Command::FilterSearchValue(expr))
+        .boxed();
+
+    let show_cheats =
+        string("cheats")
+        .map(|_| Command::ShowCheats)
+        .boxed();
+
+    let enable_cheat =
+        (string("cheat"), space(), spaces(), choice([try(string("on")), try(string("off"))]), space(), spaces(), u32_())
+        .map(|(_, _, _, state, _, _, index)| Command::EnableCheat(index as usize, state == "on"))
+        .boxed();
+
+    let add_cheat_code =
+        (string("cheat"), space(), spaces(), cheat_code_kind(),
+            (space(), spaces(), address()).map(|x| x.2),
+            (space(), spaces(), access_size()).map(|x| x.2),
+            (space(), spaces(), parser(expression::<I>)).map(|x| x.2))
+        .map(|(_, _, _, kind, addr, size, value)| Command::AddCheatCode(kind, addr, size, value))
+        .boxed();
+
+    let remove_cheat =
+        (string("cheat"), space(), spaces(), try(string("remove")), space(), spaces(), u32_())
+        .map(|(_, _, _, _, _, _, index)| Command::RemoveCheat(index as usize))
+        .boxed();
+
+    let load_cheats =
+        (string("cheat"), space(), spaces(), try(string("load")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::LoadCheats(file_name))
+        .boxed();
+
+    let save_cheats =
+        (string("cheat"), space(), spaces(), try(string("save")),
+            optional((space(), spaces(), file_name()).map(|x| x.2)))
+        .map(|(_, _, _, _, file_name)| Command::SaveCheats(file_name))
+        .boxed();
+
+    let show_worlds =
+        (string("vram"), space(), spaces(), try(string("worlds")))
+        .map(|_| Command::ShowWorlds)
+        .boxed();
+
+    let show_objs =
+        (string("vram"), space(), spaces(), try(string("obj")),
+            (space(), spaces(), u32_()).map(|x| x.2),
+            optional((space(), spaces(), u32_()).map(|x| x.2)))
+        .map(|(_, _, _, _, index, count)| Command::ShowObjs(index, count.unwrap_or(1)))
+        .boxed();
+
+    let show_char =
+        (string("vram"), space(), spaces(), try(string("char")), space(), spaces(), u32_())
+        .map(|(_, _, _, _, _, _, index)| Command::ShowChar(index))
+        .boxed();
+
+    let save_chars =
+        (string("vram"), space(), spaces(), try(string("chars")),
+            (space(), spaces(), file_name()).map(|x| x.2),
+            optional((space(), spaces(), vram_palette()).map(|x| x.2)))
+        .map(|(_, _, _, _, file_name, palette)| Command::SaveChars(file_name, palette.unwrap_or(VramPalette::Bg(0))))
+        .boxed();
+
+    let save_bgmap =
+        (string("vram"), space(), spaces(), try(string("bgmap")),
+            (space(), spaces(), u32_()).map(|x| x.2),
+            (space(), spaces(), file_name()).map(|x| x.2))
+        .map(|(_, _, _, _, index, file_name)| Command::SaveBgMap(index, file_name))
+        .boxed();
+
+    let save_objs =
+        (string("vram"), space(), spaces(), try(string("objs")), space(), spaces(), file_name())
+        .map(|(_, _, _, _, _, _, file_name)| Command::SaveObjs(file_name))
+        .boxed();
+
+    let enable_world =
+        (string("world"), space(), spaces(), choice([try(string("on")), try(string("off"))]), space(), spaces(), u32_())
+        .map(|(_, _, _, state, _, _, index)| Command::EnableWorld(index, state == "on"))
+        .boxed();
+
+    let enable_obj_group =
+        (string("objgroup"), space(), spaces(), choice([try(string("on")), try(string("off"))]), space(), spaces(), u32_())
+        .map(|(_, _, _, state, _, _, index)| Command::EnableObjGroup(index, state == "on"))
+        .boxed();
+
+    let highlight_world =
+        (string("world"), space(), spaces(), try(string("highlight")), space(), spaces(),
+            choice([try(string("off")).map(|_| None).boxed(), u32_().map(Some).boxed()]))
+        .map(|(_, _, _, _, _, _, index)| Command::HighlightWorld(index))
+        .boxed();
+
     let exit =
         choice([try(string("exit")), try(string("quit")), try(string("e")), try(string("x")), try(string("q"))])
         .map(|_| Command::Exit)
@@ -148,19 +495,70 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
             show_cpu_cache,
             show_regs,
             step,
+            next,
+            finish,
+            until,
+            run_to_frame,
+            run_to_interrupt,
             continue_,
             goto,
             show_mem,
+            set_mem,
+            fill,
+            load_mem,
+            set_reg,
             disassemble,
             label,
             add_label,
             remove_label,
+            load_labels,
+            save_labels,
             breakpoint,
             add_breakpoint,
             remove_breakpoint,
+            set_breakpoint_condition,
+            ignore_breakpoint,
             watchpoint,
             add_watchpoint,
             remove_watchpoint,
+            print,
+            backtrace,
+            show_trace,
+            start_trace,
+            stop_trace,
+            set_trace_addr_range,
+            set_trace_frame_range,
+            show_profile,
+            show_call_graph,
+            start_profile,
+            stop_profile,
+            save_profile,
+            save_folded_profile,
+            show_coverage,
+            start_coverage,
+            stop_coverage,
+            clear_coverage,
+            save_coverage_map,
+            save_coverage_listing,
+            show_search,
+            start_search,
+            filter_search,
+            filter_search_value,
+            show_cheats,
+            enable_cheat,
+            add_cheat_code,
+            remove_cheat,
+            load_cheats,
+            save_cheats,
+            show_worlds,
+            show_objs,
+            show_char,
+            save_chars,
+            save_bgmap,
+            save_objs,
+            enable_world,
+            enable_obj_group,
+            highlight_world,
             exit,
             repeat,
         ]
@@ -177,6 +575,12 @@ fn u32_<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=u32> + 'a
         .boxed()
 }
 
+fn u64_<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=u64> + 'a> {
+    many1(digit())
+        .and_then(|s: String| s.parse::<u64>())
+        .boxed()
+}
+
 fn u32_hex<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=u32> + 'a> {
     let hex_prefix = choice([try(string("0x")), try(string("$"))]);
     (optional(hex_prefix), many1(hex_digit()))
@@ -185,6 +589,235 @@ fn u32_hex<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=u32> +
         .boxed()
 }
 
+fn search_region<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=SearchRegion> + 'a> {
+    choice([
+        try(string("wram")).map(|_| SearchRegion::Wram).boxed(),
+        try(string("sram")).map(|_| SearchRegion::Sram).boxed(),
+    ]).boxed()
+}
+
+fn access_size<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=AccessSize> + 'a> {
+    choice([
+        try(string("b")).map(|_| AccessSize::Byte).boxed(),
+        try(string("h")).map(|_| AccessSize::Halfword).boxed(),
+        try(string("w")).map(|_| AccessSize::Word).boxed(),
+    ]).boxed()
+}
+
+fn cheat_code_kind<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=CheatCodeKind> + 'a> {
+    choice([
+        try(string("freeze")).map(|_| CheatCodeKind::Freeze).boxed(),
+        try(string("patch")).map(|_| CheatCodeKind::Patch).boxed(),
+    ]).boxed()
+}
+
+fn vram_palette<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=VramPalette> + 'a> {
+    (choice([try(string("gplt")), try(string("jplt"))]), one_of("0123".chars()))
+        .map(|(kind, index): (&str, char)| {
+            let index = index.to_digit(10).unwrap() as usize;
+            match kind {
+                "gplt" => VramPalette::Bg(index),
+                _ => VramPalette::Obj(index),
+            }
+        })
+        .boxed()
+}
+
+fn search_filter<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=SearchFilter> + 'a> {
+    choice([
+        choice([try(string("equal")), try(string("eq"))]).map(|_| SearchFilter::Equal).boxed(),
+        choice([try(string("changed")), try(string("ne"))]).map(|_| SearchFilter::Changed).boxed(),
+        choice([try(string("increased")), try(string("inc"))]).map(|_| SearchFilter::Increased).boxed(),
+        choice([try(string("decreased")), try(string("dec"))]).map(|_| SearchFilter::Decreased).boxed(),
+    ]).boxed()
+}
+
 fn label_name<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=String> + 'a> {
-    many1::<String, _>(alpha_num()).boxed()
+    identifier()
+}
+
+// Addresses are hex numbers (optionally prefixed with 0x or $) or label names.
+//  Labels that are also valid hex numbers (eg. add) need to be written .name.
+fn address<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=Expr> + 'a> {
+    let hex =
+        try(u32_hex().skip(not_followed_by(satisfy(|c: char| c.is_alphanumeric() || c == '_'))))
+        .map(Expr::Constant)
+        .boxed();
+
+    let label =
+        (optional(char('.')), identifier())
+        .map(|(_, name)| Expr::Label(name))
+        .boxed();
+
+    choice([hex, label]).boxed()
+}
+
+fn file_name<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=String> + 'a> {
+    many1::<String, _>(satisfy(|c: char| !c.is_whitespace())).boxed()
+}
+
+fn identifier<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=String> + 'a> {
+    (satisfy(|c: char| c.is_alphabetic() || c == '_'), many::<String, _>(satisfy(|c: char| c.is_alphanumeric() || c == '_')))
+        .map(|(first, rest)| format!("{}{}", first, rest))
+        .boxed()
+}
+
+
+// Expressions use C operator precedence. Numbers are decimal unless prefixed
+//  with 0x or $, identifiers are registers or labels (which can also be
+//  written .name), and [addr].b/.h/.w reads memory (a word by default).
+fn expression<I: Stream<Item=char>>(input: I) -> ParseResult<Expr, I> {
+    let levels = [
+        &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)][..],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)][..],
+        &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)][..],
+        &[("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)][..],
+        &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)][..],
+        &[("&", BinaryOp::BitAnd)][..],
+        &[("^", BinaryOp::BitXor)][..],
+        &[("|", BinaryOp::BitOr)][..],
+        &[("&&", BinaryOp::And)][..],
+        &[("||", BinaryOp::Or)][..],
+    ];
+
+    let mut expr = parser(unary_expression::<I>).boxed();
+    for ops in levels.iter() {
+        let op =
+            choice(ops.iter().map(|&(symbol, op)| binary_op::<I>(symbol, op)).collect::<Vec<_>>())
+            .skip(spaces())
+            .map(|op| move |lhs: Expr, rhs: Expr| Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+        expr = chainl1(expr, op).boxed();
+    }
+
+    expr.parse_stream(input)
+}
+
+fn binary_op<'a, I: Stream<Item=char> + 'a>(symbol: &'static str, op: BinaryOp) -> Box<Parser<Input=I, Output=BinaryOp> + 'a> {
+    if symbol.len() == 1 {
+        // Don't match the start of a longer operator (eg. & in &&, < in <=)
+        try(string(symbol).skip(not_followed_by(one_of("&|<>=".chars()))))
+            .map(move |_| op)
+            .boxed()
+    } else {
+        try(string(symbol))
+            .map(move |_| op)
+            .boxed()
+    }
+}
+
+fn unary_expression<I: Stream<Item=char>>(input: I) -> ParseResult<Expr, I> {
+    let unary =
+        (one_of("-!~".chars()).skip(spaces()), parser(unary_expression::<I>))
+        .map(|(symbol, expr)| {
+            let op = match symbol {
+                '-' => UnaryOp::Negate,
+                '!' => UnaryOp::Not,
+                _ => UnaryOp::BitNot,
+            };
+            Expr::Unary(op, Box::new(expr))
+        })
+        .boxed();
+
+    choice([unary, parser(primary_expression::<I>).boxed()])
+        .parse_stream(input)
+}
+
+fn primary_expression<I: Stream<Item=char>>(input: I) -> ParseResult<Expr, I> {
+    let deref =
+        (between(char('[').skip(spaces()), char(']'), parser(expression::<I>)),
+            optional((char('.'), one_of("bhw".chars())).map(|x| x.1)))
+        .map(|(addr, size)| {
+            let size = match size {
+                Some('b') => DerefSize::Byte,
+                Some('h') => DerefSize::Halfword,
+                _ => DerefSize::Word,
+            };
+            Expr::Deref(Box::new(addr), size)
+        })
+        .boxed();
+
+    let parens =
+        between(char('(').skip(spaces()), char(')'), parser(expression::<I>))
+        .boxed();
+
+    let constant =
+        choice([
+            (choice([try(string("0x")), try(string("$"))]), many1(hex_digit()))
+                .map(|x| x.1)
+                .and_then(|s: String| u32::from_str_radix(&s, 16))
+                .boxed(),
+            u32_(),
+        ])
+        .map(Expr::Constant)
+        .boxed();
+
+    let label =
+        (char('.'), identifier())
+        .map(|(_, name)| Expr::Label(name))
+        .boxed();
+
+    let register_or_label =
+        identifier()
+        .map(|name| match Register::from_name(&name) {
+            Some(register) => Expr::Register(register),
+            _ => Expr::Label(name),
+        })
+        .boxed();
+
+    choice([deref, parens, constant, label, register_or_label])
+        .skip(spaces())
+        .parse_stream(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_expr(s: &str) -> Expr {
+        match format!("print {}", s).parse() {
+            Ok(Command::Print(expr)) => expr,
+            other => panic!("Unable to parse expression {:?}: {:?}", s, other),
+        }
+    }
+
+    #[test]
+    fn expressions_use_c_precedence() {
+        assert_eq!(parse_expr("1 + 2 * 3").to_string(), "0x1 + (0x2 * 0x3)");
+        assert_eq!(parse_expr("(1 + 2) * 3").to_string(), "(0x1 + 0x2) * 0x3");
+        assert_eq!(parse_expr("1 - 2 - 3").to_string(), "(0x1 - 0x2) - 0x3");
+        assert_eq!(parse_expr("1 << 2 + 3").to_string(), "0x1 << (0x2 + 0x3)");
+        assert_eq!(parse_expr("r1 & 3 == 1").to_string(), "r1 & (0x3 == 0x1)");
+        assert_eq!(parse_expr("r1 == 1 && r2 <= 2 || !r3").to_string(), "((r1 == 0x1) && (r2 <= 0x2)) || !r3");
+        assert_eq!(parse_expr("-~r4").to_string(), "-~r4");
+    }
+
+    #[test]
+    fn expression_operands() {
+        assert_eq!(parse_expr("0x10"), Expr::Constant(0x10));
+        assert_eq!(parse_expr("$ff"), Expr::Constant(0xff));
+        assert_eq!(parse_expr("16"), Expr::Constant(16));
+        assert_eq!(parse_expr("sp"), Expr::Register(Register::Gpr(3)));
+        assert_eq!(parse_expr("r31"), Expr::Register(Register::Gpr(31)));
+        assert_eq!(parse_expr("psw"), Expr::Register(Register::Psw));
+        assert_eq!(parse_expr("r32"), Expr::Label("r32".to_string()));
+        assert_eq!(parse_expr("main"), Expr::Label("main".to_string()));
+        assert_eq!(parse_expr(".pc"), Expr::Label("pc".to_string()));
+        assert_eq!(parse_expr("[sp + 4].h").to_string(), "[r3 + 0x4].h");
+        assert_eq!(parse_expr("[ 0x05000000 ]").to_string(), "[0x5000000].w");
+    }
+
+    #[test]
+    fn expression_display_round_trips() {
+        for s in &["1 + 2 * 3 % r7", "[[gp].w + 0x8].b != 0 || .lp", "~(r1 ^ r2) | r3 >> 1", "-(1 - 2)"] {
+            let expr = parse_expr(s);
+            assert_eq!(parse_expr(&expr.to_string()), expr);
+        }
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        for s in &["", "1 +", "(1", "[r1", "1 2", "[r1].q", "r1 === 2"] {
+            assert!(format!("print {}", s).parse::<Command>().is_err(), "{:?} should not parse", s);
+        }
+    }
 }
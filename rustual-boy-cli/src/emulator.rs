@@ -1,6 +1,7 @@
 use minifb::{WindowOptions, Window, Key, KeyRepeat, Scale};
 
 use command::*;
+use expression::*;
 
 use rustual_boy_core::sinks::{AudioFrame, Sink, SinkRef, VideoFrame};
 use rustual_boy_core::time_source::TimeSource;
@@ -8,18 +9,32 @@ use rustual_boy_core::rom::Rom;
 use rustual_boy_core::sram::Sram;
 use rustual_boy_core::instruction::*;
 use rustual_boy_core::game_pad::Button;
+use rustual_boy_core::hooks::{AccessKind, AccessSize, HookAction, HookId, MemoryHook};
+use rustual_boy_core::v810::CallFrameKind;
+use rustual_boy_core::vip::DISPLAY_FRAME_PERIOD;
 use rustual_boy_core::virtual_boy::VirtualBoy;
 
-use rustual_boy_middleware::{Anaglyphizer, GammaAdjustSink, MostRecentSink};
+use rustual_boy_middleware::{Anaglyphizer, Cheat, CheatCode, CheatCodeKind, CheatList, CoverageRecorder, GammaAdjustSink, GdbStub, InstructionTracer, MemorySearch, MostRecentSink, Movie, MoviePlayer, Profiler, RewindBuffer, SearchFilter, SymbolTable, VramImage, VramViewer, WorldMode, NUM_BGMAPS, NUM_CHARS, NUM_OBJS, NUM_WORLDS};
 
+use std::cmp;
 use std::time;
 use std::thread::{self, JoinHandle};
-use std::io::{stdin, stdout, Write};
-use std::collections::{HashSet, HashMap, VecDeque};
+use std::io::{self, stdin, stdout, BufWriter, Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{channel, Receiver};
 
 const CPU_CYCLE_TIME_NS: u64 = 50;
 
+const FRAME_CYCLES: u64 = DISPLAY_FRAME_PERIOD as u64;
+const FRAME_TIME_NS: u64 = FRAME_CYCLES * CPU_CYCLE_TIME_NS;
+
+const REWIND_SNAPSHOT_INTERVAL_FRAMES: u32 = 2;
+// 30 seconds of history
+const REWIND_BUFFER_CAPACITY: usize = 30 * 50 / (REWIND_SNAPSHOT_INTERVAL_FRAMES as usize);
+const REWIND_KEY: Key = Key::Backspace;
+
 struct SimpleAudioFrameSink {
     inner: VecDeque<AudioFrame>,
 }
@@ -30,6 +45,36 @@ impl Sink<AudioFrame> for SimpleAudioFrameSink {
     }
 }
 
+struct Breakpoint {
+    condition: Option<Expr>,
+    hit_count: u32,
+    ignore_count: u32,
+}
+
+impl Breakpoint {
+    fn new(condition: Option<Expr>) -> Breakpoint {
+        Breakpoint {
+            condition: condition,
+            hit_count: 0,
+            ignore_count: 0,
+        }
+    }
+}
+
+// Where execution should stop when running from the debugger with next,
+//  finish, until, nextframe or nextinterrupt
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunTarget {
+    // Returning from a call made by jal; the stack pointer check skips
+    //  recursive calls that come back to the same address
+    StepOver { return_addr: u32, sp: u32 },
+    // Calls are counted so only the jmp [r31] that leaves the current function stops
+    Finish { call_depth: u32, is_returning: bool },
+    Until(u32),
+    Frame(u64),
+    Interrupt,
+}
+
 #[derive(PartialEq, Eq)]
 enum Mode {
     Running,
@@ -42,9 +87,14 @@ pub struct Emulator {
     pub virtual_boy: VirtualBoy,
     mode: Mode,
 
-    breakpoints: HashSet<u32>,
+    breakpoints: HashMap<u32, Breakpoint>,
+    // Each watchpoint is a read hook and a write hook on the CPU
+    watchpoints: HashMap<u32, Vec<HookId>>,
+    run_target: Option<RunTarget>,
+    frame_count: u64,
 
-    labels: HashMap<String, u32>,
+    symbols: SymbolTable,
+    symbols_path: Option<String>,
     cursor: u32,
     last_command: Option<Command>,
 
@@ -57,6 +107,31 @@ pub struct Emulator {
     time_source_start_time_ns: u64,
 
     emulated_cycles: u64,
+
+    rewind_buffer: RewindBuffer,
+    frames_since_rewind_snapshot: u32,
+    is_rewinding: bool,
+    last_rewind_time_ns: u64,
+
+    movie_recording: Option<Movie>,
+    movie_player: Option<MoviePlayer>,
+
+    gdb_stub: Option<GdbStub>,
+
+    tracer: Option<InstructionTracer>,
+    trace_file_name: String,
+    trace_addr_range: Option<(u32, u32)>,
+    trace_frame_range: Option<(u64, u64)>,
+
+    profiler: Option<Profiler>,
+    is_profiling: bool,
+
+    coverage_recorder: CoverageRecorder,
+
+    memory_search: Option<MemorySearch>,
+
+    cheats: CheatList,
+    cheats_path: Option<String>,
 }
 
 impl Emulator {
@@ -68,6 +143,8 @@ impl Emulator {
             }
         });
 
+        let rom_size = rom.size();
+
         Emulator {
             window: Window::new("Rustual Boy", 384, 224, WindowOptions {
                 borderless: false,
@@ -79,9 +156,13 @@ impl Emulator {
             virtual_boy: VirtualBoy::new(rom, sram),
             mode: Mode::Running,
 
-            breakpoints: HashSet::new(),
+            breakpoints: HashMap::new(),
+            watchpoints: HashMap::new(),
+            run_target: None,
+            frame_count: 0,
 
-            labels: HashMap::new(),
+            symbols: SymbolTable::new(),
+            symbols_path: None,
             cursor: 0,
             last_command: None,
 
@@ -94,9 +175,123 @@ impl Emulator {
             time_source_start_time_ns: 0,
 
             emulated_cycles: 0,
+
+            rewind_buffer: RewindBuffer::new(REWIND_BUFFER_CAPACITY),
+            frames_since_rewind_snapshot: 0,
+            is_rewinding: false,
+            last_rewind_time_ns: 0,
+
+            movie_recording: None,
+            movie_player: None,
+
+            gdb_stub: None,
+
+            tracer: None,
+            trace_file_name: String::new(),
+            trace_addr_range: None,
+            trace_frame_range: None,
+
+            profiler: None,
+            is_profiling: false,
+
+            coverage_recorder: CoverageRecorder::new(rom_size),
+
+            memory_search: None,
+
+            cheats: CheatList::new(),
+            cheats_path: None,
+        }
+    }
+
+    /// Records game pad input into `movie` for every emulated frame. The
+    /// emulator should have been created with blank SRAM.
+    pub fn start_movie_recording(&mut self, movie: Movie) {
+        self.movie_recording = Some(movie);
+    }
+
+    pub fn take_movie_recording(&mut self) -> Option<Movie> {
+        self.movie_recording.take()
+    }
+
+    /// Drives game pad input from `movie` instead of the keyboard until it runs out of frames
+    pub fn start_movie_playback(&mut self, movie: Movie) {
+        self.movie_player = Some(MoviePlayer::new(movie));
+    }
+
+    /// Sets the map file `savelabels` writes to when no file is given
+    pub fn set_symbols_path(&mut self, symbols_path: String) {
+        self.symbols_path = Some(symbols_path);
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    /// Adds the symbols from an ELF or map file to the debugger's labels,
+    /// returning how many were loaded
+    pub fn load_symbols<P: AsRef<Path>>(&mut self, file_name: P) -> io::Result<usize> {
+        let symbols = SymbolTable::load(file_name)?;
+        let len = symbols.len();
+        self.symbols.extend(symbols);
+        Ok(len)
+    }
+
+    /// Writes every executed instruction to `file_name` (see `InstructionTracer`),
+    /// replacing any trace already in progress
+    pub fn start_trace(&mut self, file_name: &str) -> io::Result<()> {
+        self.stop_trace()?;
+
+        let mut tracer = InstructionTracer::create(file_name)?;
+        tracer.set_addr_range(self.trace_addr_range);
+        tracer.set_frame_range(self.trace_frame_range);
+        self.tracer = Some(tracer);
+        self.trace_file_name = file_name.into();
+
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_trace_addr_range(&mut self, addr_range: Option<(u32, u32)>) {
+        self.trace_addr_range = addr_range;
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.set_addr_range(addr_range);
         }
     }
 
+    /// Sets the cheat file `cheat save` writes to when no file is given
+    pub fn set_cheats_path(&mut self, cheats_path: String) {
+        self.cheats_path = Some(cheats_path);
+    }
+
+    /// Replaces the cheat list, applying its enabled ROM patches right away.
+    /// Enabled freeze codes are applied every frame.
+    pub fn set_cheats(&mut self, cheats: CheatList) {
+        self.cheats = cheats;
+        self.cheats.apply_patches(&mut self.virtual_boy.interconnect);
+    }
+
+    /// Starts recording which ROM bytes are executed or read as data
+    pub fn start_coverage(&mut self) {
+        self.coverage_recorder.start(&mut self.virtual_boy);
+    }
+
+    /// Saves the coverage recorded so far as PREFIX.cov and PREFIX.lst
+    pub fn save_coverage(&self, prefix: &str) -> io::Result<()> {
+        self.save_coverage_map(&format!("{}.cov", prefix))?;
+        self.save_coverage_listing(&format!("{}.lst", prefix))
+    }
+
+    /// Hands control of execution to a connected gdb until it detaches
+    pub fn attach_gdb_stub(&mut self, gdb_stub: GdbStub) {
+        self.gdb_stub = Some(gdb_stub);
+    }
+
     pub fn run(&mut self) {
         self.time_source_start_time_ns = self.time_source.time_ns();
 
@@ -113,16 +308,44 @@ impl Emulator {
                 inner: VecDeque::new(),
             };
 
+            if self.is_rewinding && !self.window.is_key_down(REWIND_KEY) {
+                // Pick up from wherever rewinding left off
+                self.is_rewinding = false;
+                self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+            }
+
+            self.poll_gdb_stub();
+
             let target_emulated_time_ns = self.time_source.time_ns() - self.time_source_start_time_ns;
             let target_emulated_cycles = target_emulated_time_ns / CPU_CYCLE_TIME_NS;
 
             match self.mode {
+                Mode::Running if self.is_gdb_stopped() => {
+                    // Don't try to catch up on the time spent stopped once gdb continues
+                    self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+
+                    self.window.update();
+                }
+                Mode::Running if self.window.is_key_down(REWIND_KEY) && !self.is_movie_active() => {
+                    self.rewind(&mut video_frame_sink);
+                }
                 Mode::Running => {
                     let mut start_debugger = false;
 
                     while self.emulated_cycles < target_emulated_cycles && !start_debugger {
+                        self.track_calls_for_run_target();
                         let (_, trigger_watchpoint) = self.step(&mut video_frame_sink, &mut audio_frame_sink);
-                        if trigger_watchpoint || (self.breakpoints.len() != 0 && self.breakpoints.contains(&self.virtual_boy.cpu.reg_pc())) {
+                        if self.gdb_stub.is_some() {
+                            if self.check_gdb_stop(trigger_watchpoint) {
+                                break;
+                            }
+                        } else if trigger_watchpoint {
+                            self.print_watchpoint_hit();
+                            start_debugger = true;
+                        } else if self.check_breakpoints() || self.check_run_target() {
+                            start_debugger = true;
+                        } else if let Some(fatal_exception) = self.virtual_boy.cpu.fatal_exception() {
+                            println!("CPU crashed: {}", fatal_exception);
                             start_debugger = true;
                         }
                     }
@@ -141,13 +364,13 @@ impl Emulator {
             }
 
             if let Some(frame) = video_frame_sink.into_inner().into_inner().into_inner() {
-                let frame: Vec<u32> = frame.into_iter().map(|x| x.into()).collect();
+                let frame: Vec<u32> = frame.iter().map(|x| x.into()).collect();
                 self.window.update_with_buffer(&frame);
 
                 if self.mode == Mode::Running {
-                    // We only want to update the key state when a frame is actually pushed
-                    // Otherwise some games break.
-                    self.read_input_keys();
+                    if !self.is_rewinding && !self.is_movie_active() {
+                        self.take_rewind_snapshot();
+                    }
                     if self.window.is_key_pressed(Key::F12, KeyRepeat::No) {
                         self.start_debugger();
                     }
@@ -161,13 +384,262 @@ impl Emulator {
     }
 
     fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
-        let ret = self.virtual_boy.step(video_frame_sink, audio_frame_sink);
+        let trace_entry = match self.tracer {
+            Some(ref tracer) => tracer.begin(&mut self.virtual_boy, self.frame_count),
+            _ => None,
+        };
+
+        if self.is_profiling {
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.begin_step(&self.virtual_boy.cpu);
+            }
+        }
+
+        let mut frame_sink = MostRecentSink::new();
+        let ret = self.virtual_boy.step(&mut frame_sink, audio_frame_sink);
 
         self.emulated_cycles += ret.0 as u64;
 
+        if self.is_profiling {
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.end_step(ret.0);
+            }
+        }
+
+        if let Some(trace_entry) = trace_entry {
+            let mut trace_error = None;
+            if let Some(ref mut tracer) = self.tracer {
+                trace_error = tracer.end(trace_entry, &self.virtual_boy).err();
+            }
+
+            if let Some(err) = trace_error {
+                println!("Error writing trace to {}, tracing stopped: {}", self.trace_file_name, err);
+                self.tracer = None;
+            }
+        }
+
+        if let Some(frame) = frame_sink.into_inner() {
+            video_frame_sink.append(frame);
+            self.frame_emitted();
+        }
+
+        ret
+    }
+
+    // Input is applied right after the step that emitted a frame, which keeps
+    //  recorded movies in sync with what playback (and the headless runner) does.
+    fn frame_emitted(&mut self) {
+        self.frame_count += 1;
+
+        self.cheats.apply_freezes(&mut self.virtual_boy.interconnect);
+
+        if self.is_profiling {
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.frame_emitted();
+            }
+        }
+
+        let mut movie_finished = false;
+        if let Some(ref mut movie_player) = self.movie_player {
+            movie_finished = !movie_player.apply_next_frame(&mut self.virtual_boy.interconnect.game_pad);
+        } else if self.mode == Mode::Running {
+            // We only want to update the key state when a frame is actually pushed
+            // Otherwise some games break.
+            self.read_input_keys();
+        }
+
+        if movie_finished {
+            logln!("Movie playback finished, switching to keyboard input");
+            self.movie_player = None;
+        }
+
+        if let Some(ref mut movie) = self.movie_recording {
+            movie.record_frame(&self.virtual_boy.interconnect.game_pad);
+        }
+    }
+
+    fn is_gdb_stopped(&self) -> bool {
+        self.gdb_stub.as_ref().map_or(false, |gdb_stub| !gdb_stub.is_running())
+    }
+
+    fn poll_gdb_stub(&mut self) {
+        let mut detached = false;
+        if let Some(ref mut gdb_stub) = self.gdb_stub {
+            if let Err(err) = gdb_stub.poll(&mut self.virtual_boy) {
+                logln!("gdb connection error: {}", err);
+            }
+            detached = gdb_stub.is_detached();
+        }
+
+        if detached {
+            logln!("gdb detached, resuming emulation");
+            self.gdb_stub = None;
+        }
+    }
+
+    // Returns true if gdb stopped the target
+    fn check_gdb_stop(&mut self, trigger_watchpoint: bool) -> bool {
+        let mut ret = false;
+        let mut disconnected = false;
+        if let Some(ref mut gdb_stub) = self.gdb_stub {
+            match gdb_stub.check_stop(&self.virtual_boy, trigger_watchpoint) {
+                Ok(stopped) => ret = stopped,
+                Err(err) => {
+                    logln!("gdb connection error: {}", err);
+                    disconnected = true;
+                }
+            }
+        }
+
+        if disconnected {
+            self.gdb_stub = None;
+        }
+
         ret
     }
 
+    fn print_watchpoint_hit(&self) {
+        if let Some((hook_id, access)) = self.virtual_boy.cpu.triggered_hook() {
+            if self.watchpoints.values().any(|hook_ids| hook_ids.contains(&hook_id)) {
+                println!("Watchpoint hit: {}", access);
+            }
+        }
+    }
+
+    // Returns true if there's a breakpoint at the current pc that should stop execution
+    fn check_breakpoints(&mut self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let breakpoint = match self.breakpoints.get_mut(&self.virtual_boy.cpu.reg_pc()) {
+            Some(breakpoint) => breakpoint,
+            _ => return false,
+        };
+
+        if let Some(ref condition) = breakpoint.condition {
+            match condition.evaluate(&mut self.virtual_boy, &self.symbols) {
+                Ok(0) => return false,
+                Ok(_) => (),
+                Err(e) => {
+                    println!("Error evaluating breakpoint condition: {}", e);
+                    return true;
+                }
+            }
+        }
+
+        breakpoint.hit_count += 1;
+
+        if breakpoint.ignore_count > 0 {
+            breakpoint.ignore_count -= 1;
+            return false;
+        }
+
+        true
+    }
+
+    fn track_calls_for_run_target(&mut self) {
+        if let Some(RunTarget::Finish { ref mut call_depth, ref mut is_returning }) = self.run_target {
+            let interconnect = &mut self.virtual_boy.interconnect;
+            let instruction = DecodedInstruction::decode(self.virtual_boy.cpu.reg_pc(), |addr| interconnect.read_halfword(addr));
+            match (instruction.opcode, instruction.operands) {
+                (Some(Opcode::Jal), _) => *call_depth += 1,
+                (Some(Opcode::Jmp), Operands::Reg { reg1: 31, .. }) => {
+                    if *call_depth == 0 {
+                        *is_returning = true;
+                    } else {
+                        *call_depth -= 1;
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+
+    // Returns true if the last step reached the debugger's run target
+    fn check_run_target(&mut self) -> bool {
+        let pc = self.virtual_boy.cpu.reg_pc();
+        match self.run_target {
+            Some(RunTarget::StepOver { return_addr, sp }) => pc == return_addr && self.virtual_boy.cpu.reg_gpr(3) >= sp,
+            Some(RunTarget::Finish { is_returning, .. }) => is_returning,
+            Some(RunTarget::Until(addr)) => pc == addr,
+            Some(RunTarget::Frame(frame_count)) => self.frame_count != frame_count,
+            Some(RunTarget::Interrupt) => {
+                // Interrupts vector to 0xfffffe00-0xfffffe40 and leave their code in the low half of ECR
+                let exception_code = self.virtual_boy.cpu.reg_ecr() & 0xffff;
+                exception_code >= 0xfe00 && exception_code <= 0xfe4f && pc == 0xffff0000 | exception_code
+            }
+            None => false,
+        }
+    }
+
+    fn run_to(&mut self, run_target: RunTarget) {
+        self.run_target = Some(run_target);
+        self.resume();
+    }
+
+    fn resume(&mut self) {
+        self.mode = Mode::Running;
+        self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+    }
+
+    fn is_movie_active(&self) -> bool {
+        self.movie_recording.is_some() || self.movie_player.is_some()
+    }
+
+    // Movies don't record cheats, so they can't be turned on while one is active
+    fn check_cheats_allowed(&self) -> bool {
+        if self.is_movie_active() {
+            println!("Cheats are disabled while a movie is recording or playing back");
+            return false;
+        }
+
+        true
+    }
+
+    fn take_rewind_snapshot(&mut self) {
+        self.frames_since_rewind_snapshot += 1;
+        if self.frames_since_rewind_snapshot >= REWIND_SNAPSHOT_INTERVAL_FRAMES {
+            self.frames_since_rewind_snapshot = 0;
+            self.rewind_buffer.push(self.virtual_boy.save_state());
+        }
+    }
+
+    fn rewind(&mut self, video_frame_sink: &mut Sink<VideoFrame>) {
+        let time_ns = self.time_source.time_ns();
+        if self.is_rewinding && time_ns - self.last_rewind_time_ns < (REWIND_SNAPSHOT_INTERVAL_FRAMES as u64) * FRAME_TIME_NS {
+            return;
+        }
+        self.is_rewinding = true;
+        self.last_rewind_time_ns = time_ns;
+
+        let state = match self.rewind_buffer.pop() {
+            Some(state) => state,
+            _ => return,
+        };
+        if let Err(err) = self.virtual_boy.load_state(&state) {
+            logln!("Error loading rewind snapshot, rewind history dropped: {}", err);
+            self.rewind_buffer.clear();
+            return;
+        }
+        self.frames_since_rewind_snapshot = 0;
+
+        // Snapshots are taken right after a frame is displayed, so run until the
+        //  next one to have something to show. Audio produced here is dropped.
+        let mut frame_sink = MostRecentSink::new();
+        let mut audio_frame_sink = SimpleAudioFrameSink {
+            inner: VecDeque::new(),
+        };
+        let mut cycles = 0;
+        while !frame_sink.has_frame() && cycles < FRAME_CYCLES * 2 {
+            cycles += self.virtual_boy.step(&mut frame_sink, &mut audio_frame_sink).0 as u64;
+        }
+
+        if let Some(frame) = frame_sink.into_inner() {
+            video_frame_sink.append(frame);
+        }
+    }
+
     fn read_input_keys(&mut self) {
         self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::A, self.window.is_key_down(Key::F));
         self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::B, self.window.is_key_down(Key::H));
@@ -187,6 +659,7 @@ impl Emulator {
 
     fn start_debugger(&mut self) {
         self.mode = Mode::Debugging;
+        self.run_target = None;
 
         self.cursor = self.virtual_boy.cpu.reg_pc();
         self.disassemble_instruction();
@@ -214,6 +687,8 @@ impl Emulator {
                     println!("eipc: 0x{:08x}", self.virtual_boy.cpu.reg_eipc());
                     println!("eipsw: 0x{:08x}", self.virtual_boy.cpu.reg_eipsw());
                     println!("ecr: 0x{:08x}", self.virtual_boy.cpu.reg_ecr());
+                    println!("fepc: 0x{:08x}", self.virtual_boy.cpu.reg_fepc());
+                    println!("fepsw: 0x{:08x}", self.virtual_boy.cpu.reg_fepsw());
                 }
                 Ok(Command::ShowCpuCache) => {
                     println!("CPU Instruction Cached enable: {}", self.virtual_boy.cpu.cache.is_enabled());
@@ -231,33 +706,75 @@ impl Emulator {
                         self.disassemble_instruction();
                     }
                 }
+                Ok(Command::Next) => {
+                    let pc = self.virtual_boy.cpu.reg_pc();
+                    let interconnect = &mut self.virtual_boy.interconnect;
+                    let instruction = DecodedInstruction::decode(pc, |addr| interconnect.read_halfword(addr));
+                    if instruction.opcode == Some(Opcode::Jal) {
+                        let sp = self.virtual_boy.cpu.reg_gpr(3);
+                        self.run_to(RunTarget::StepOver { return_addr: instruction.next_addr(), sp: sp });
+                    } else {
+                        self.step(video_frame_sink, audio_frame_sink);
+                        self.cursor = self.virtual_boy.cpu.reg_pc();
+                        self.disassemble_instruction();
+                    }
+                }
+                Ok(Command::Finish) => {
+                    self.run_to(RunTarget::Finish { call_depth: 0, is_returning: false });
+                }
+                Ok(Command::Until(ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        self.run_to(RunTarget::Until(addr));
+                    }
+                }
+                Ok(Command::RunToFrame) => {
+                    let frame_count = self.frame_count;
+                    self.run_to(RunTarget::Frame(frame_count));
+                }
+                Ok(Command::RunToInterrupt) => {
+                    self.run_to(RunTarget::Interrupt);
+                }
                 Ok(Command::Continue) => {
-                    self.mode = Mode::Running;
-                    self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+                    self.resume();
                 }
-                Ok(Command::Goto(addr)) => {
-                    self.cursor = addr;
+                Ok(Command::Goto(ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        self.cursor = addr;
+                    }
                 }
-                Ok(Command::ShowMem(addr)) => {
-                    if let Some(addr) = addr {
+                Ok(Command::ShowMem(None)) => {
+                    self.show_mem();
+                }
+                Ok(Command::ShowMem(Some(ref addr))) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
                         self.cursor = addr;
+                        self.show_mem();
                     }
-
-                    self.print_labels_at_cursor();
-
-                    const NUM_ROWS: u32 = 16;
-                    const NUM_COLS: u32 = 16;
-                    for _ in 0..NUM_ROWS {
-                        print!("0x{:08x}  ", self.cursor);
-                        for x in 0..NUM_COLS {
-                            let byte = self.virtual_boy.interconnect.read_byte(self.cursor);
-                            self.cursor = self.cursor.wrapping_add(1);
-                            print!("{:02x}", byte);
-                            if x < NUM_COLS - 1 {
-                                print!(" ");
+                }
+                Ok(Command::SetMem(ref addr, ref value, size)) => {
+                    if let (Some(addr), Some(value)) = (self.resolve_addr(addr), self.evaluate(value)) {
+                        self.write_mem(addr, 1, value, size);
+                    }
+                }
+                Ok(Command::Fill(ref addr, count, ref value, size)) => {
+                    if let (Some(addr), Some(value)) = (self.resolve_addr(addr), self.evaluate(value)) {
+                        self.write_mem(addr, count, value, size);
+                    }
+                }
+                Ok(Command::LoadMem(ref file_name, ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        self.load_mem(file_name, addr);
+                    }
+                }
+                Ok(Command::SetReg(ref name, ref value)) => {
+                    match Register::from_name(name) {
+                        Some(register) => if let Some(value) = self.evaluate(value) {
+                            register.write(&mut self.virtual_boy, value);
+                            if register == Register::Pc {
+                                self.cursor = self.virtual_boy.cpu.reg_pc();
                             }
-                        }
-                        println!();
+                        },
+                        _ => println!("Unknown register: {}", name),
                     }
                 }
                 Ok(Command::Disassemble(count)) => {
@@ -266,42 +783,332 @@ impl Emulator {
                     }
                 }
                 Ok(Command::Label) => {
-                    for (name, addr) in self.labels.iter() {
+                    for (name, addr) in self.symbols.iter() {
                         println!(".{}: 0x{:08x}", name, addr);
                     }
                 }
                 Ok(Command::AddLabel(ref name, addr)) => {
-                    self.labels.insert(name.clone(), addr);
+                    self.symbols.insert(name.clone(), addr);
                 }
                 Ok(Command::RemoveLabel(ref name)) => {
-                    if let None = self.labels.remove(name) {
+                    if self.symbols.remove(name).is_none() {
                         println!("Label .{} does not exist", name);
                     }
                 }
+                Ok(Command::LoadLabels(ref file_name)) => {
+                    match self.load_symbols(file_name) {
+                        Ok(len) => println!("Loaded {} labels from {}", len, file_name),
+                        Err(e) => println!("Couldn't load labels from {}: {}", file_name, e),
+                    }
+                }
+                Ok(Command::SaveLabels(ref file_name)) => {
+                    match file_name.as_ref().or(self.symbols_path.as_ref()) {
+                        Some(file_name) => match self.symbols.save(file_name) {
+                            Ok(()) => println!("Saved {} labels to {}", self.symbols.len(), file_name),
+                            Err(e) => println!("Couldn't save labels to {}: {}", file_name, e),
+                        },
+                        _ => println!("No label file given"),
+                    }
+                }
                 Ok(Command::Breakpoint) => {
-                    for addr in self.breakpoints.iter() {
-                        println!("* 0x{:08x}", addr);
+                    let mut addrs = self.breakpoints.keys().cloned().collect::<Vec<_>>();
+                    addrs.sort();
+                    for addr in addrs {
+                        let breakpoint = &self.breakpoints[&addr];
+                        print!("* {} (hits: {}", self.symbols.format_addr(addr), breakpoint.hit_count);
+                        if breakpoint.ignore_count > 0 {
+                            print!(", ignoring next {}", breakpoint.ignore_count);
+                        }
+                        print!(")");
+                        if let Some(ref condition) = breakpoint.condition {
+                            print!(" if {}", condition);
+                        }
+                        println!();
                     }
                 }
-                Ok(Command::AddBreakpoint(addr)) => {
-                    self.breakpoints.insert(addr);
+                Ok(Command::AddBreakpoint(ref addr, ref condition)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        self.breakpoints.insert(addr, Breakpoint::new(condition.clone()));
+                    }
                 }
-                Ok(Command::RemoveBreakpoint(addr)) => {
-                    if !self.breakpoints.remove(&addr) {
-                        println!("Breakpoint at 0x{:08x} does not exist", addr);
+                Ok(Command::RemoveBreakpoint(ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        if self.breakpoints.remove(&addr).is_none() {
+                            println!("Breakpoint at 0x{:08x} does not exist", addr);
+                        }
+                    }
+                }
+                Ok(Command::SetBreakpointCondition(ref addr, ref condition)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        match self.breakpoints.get_mut(&addr) {
+                            Some(breakpoint) => breakpoint.condition = condition.clone(),
+                            _ => println!("Breakpoint at 0x{:08x} does not exist", addr),
+                        }
+                    }
+                }
+                Ok(Command::IgnoreBreakpoint(ref addr, count)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        match self.breakpoints.get_mut(&addr) {
+                            Some(breakpoint) => breakpoint.ignore_count = count,
+                            _ => println!("Breakpoint at 0x{:08x} does not exist", addr),
+                        }
                     }
                 }
                 Ok(Command::Watchpoint) => {
-                    for addr in self.virtual_boy.cpu.watchpoints.iter() {
-                        println!("* 0x{:08x}", addr);
+                    let mut addrs = self.watchpoints.keys().cloned().collect::<Vec<_>>();
+                    addrs.sort();
+                    for addr in addrs {
+                        println!("* {}", self.symbols.format_addr(addr));
+                    }
+                }
+                Ok(Command::AddWatchpoint(ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        if !self.watchpoints.contains_key(&addr) {
+                            let hooks = &mut self.virtual_boy.cpu.hooks;
+                            let hook_ids = [AccessKind::Read, AccessKind::Write].iter()
+                                .map(|&kind| hooks.add(MemoryHook::new(kind, addr, addr, |_| HookAction::Break)))
+                                .collect();
+                            self.watchpoints.insert(addr, hook_ids);
+                        }
+                    }
+                }
+                Ok(Command::RemoveWatchpoint(ref addr)) => {
+                    if let Some(addr) = self.resolve_addr(addr) {
+                        match self.watchpoints.remove(&addr) {
+                            Some(hook_ids) => {
+                                for hook_id in hook_ids {
+                                    self.virtual_boy.cpu.hooks.remove(hook_id);
+                                }
+                            }
+                            _ => println!("Watchpoint at 0x{:08x} does not exist", addr),
+                        }
+                    }
+                }
+                Ok(Command::Print(ref expr)) => {
+                    match expr.evaluate(&mut self.virtual_boy, &self.symbols) {
+                        Ok(value) => println!("{} = 0x{:08x} ({})", expr, value, value),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Ok(Command::Backtrace) => {
+                    println!("#0  {}", self.symbols.format_addr(self.virtual_boy.cpu.reg_pc()));
+                    for (i, frame) in self.virtual_boy.cpu.call_stack().iter().rev().enumerate() {
+                        print!("#{:<2} {}", i + 1, self.symbols.format_addr(frame.return_addr));
+                        if let CallFrameKind::Exception(exception_code) = frame.kind {
+                            print!(" (exception 0x{:04x})", exception_code);
+                        }
+                        println!();
+                    }
+                }
+                Ok(Command::ShowTrace) => {
+                    match self.tracer {
+                        Some(ref tracer) => println!("Tracing to {} ({} instructions traced)", self.trace_file_name, tracer.num_traced_instructions()),
+                        _ => println!("Tracing is off"),
+                    }
+                    match self.trace_addr_range {
+                        Some((start, end)) => println!("pc range: {} - {}", self.symbols.format_addr(start), self.symbols.format_addr(end)),
+                        _ => println!("pc range: all"),
+                    }
+                    match self.trace_frame_range {
+                        Some((first, last)) => println!("frames: {} - {} (current: {})", first, last, self.frame_count),
+                        _ => println!("frames: all (current: {})", self.frame_count),
+                    }
+                }
+                Ok(Command::StartTrace(ref file_name)) => {
+                    if let Err(e) = self.start_trace(file_name) {
+                        println!("Couldn't start trace to {}: {}", file_name, e);
+                    }
+                }
+                Ok(Command::StopTrace) => {
+                    if let Err(e) = self.stop_trace() {
+                        println!("Error writing trace to {}: {}", self.trace_file_name, e);
+                    }
+                }
+                Ok(Command::SetTraceAddrRange(None)) => {
+                    self.set_trace_addr_range(None);
+                }
+                Ok(Command::SetTraceAddrRange(Some((ref start, ref end)))) => {
+                    if let (Some(start), Some(end)) = (self.resolve_addr(start), self.resolve_addr(end)) {
+                        self.set_trace_addr_range(Some((start, end)));
+                    }
+                }
+                Ok(Command::SetTraceFrameRange(range)) => {
+                    self.trace_frame_range = range;
+                    if let Some(ref mut tracer) = self.tracer {
+                        tracer.set_frame_range(range);
+                    }
+                }
+                Ok(Command::ShowProfile) => {
+                    match self.profiler {
+                        Some(ref profiler) => profiler.write_flat_profile(&self.symbols, &mut stdout()).unwrap(),
+                        _ => println!("No profile collected"),
+                    }
+                }
+                Ok(Command::ShowCallGraph) => {
+                    match self.profiler {
+                        Some(ref profiler) => profiler.write_call_graph(&self.symbols, &mut stdout()).unwrap(),
+                        _ => println!("No profile collected"),
                     }
                 }
-                Ok(Command::AddWatchpoint(addr)) => {
-                    self.virtual_boy.cpu.watchpoints.insert(addr);
+                Ok(Command::StartProfile) => {
+                    self.profiler = Some(Profiler::new());
+                    self.is_profiling = true;
+                }
+                Ok(Command::StopProfile) => {
+                    self.is_profiling = false;
+                }
+                Ok(Command::SaveProfile(ref file_name)) => {
+                    if let Err(e) = self.save_profile(file_name, false) {
+                        println!("Couldn't save profile to {}: {}", file_name, e);
+                    }
+                }
+                Ok(Command::SaveFoldedProfile(ref file_name)) => {
+                    if let Err(e) = self.save_profile(file_name, true) {
+                        println!("Couldn't save profile to {}: {}", file_name, e);
+                    }
+                }
+                Ok(Command::ShowCoverage) => {
+                    let coverage = self.coverage_recorder.coverage();
+                    println!("Coverage recording is {}", if self.coverage_recorder.is_recording() { "on" } else { "off" });
+                    println!("{} instructions executed, {} of {} ROM bytes read as data", coverage.num_instructions(), coverage.num_read_bytes(), coverage.size());
+                }
+                Ok(Command::StartCoverage) => {
+                    self.start_coverage();
+                }
+                Ok(Command::StopCoverage) => {
+                    self.coverage_recorder.stop(&mut self.virtual_boy);
+                }
+                Ok(Command::ClearCoverage) => {
+                    self.coverage_recorder.clear();
+                }
+                Ok(Command::SaveCoverageMap(ref file_name)) => {
+                    if let Err(e) = self.save_coverage_map(file_name) {
+                        println!("Couldn't save coverage map to {}: {}", file_name, e);
+                    }
                 }
-                Ok(Command::RemoveWatchpoint(addr)) => {
-                    if !self.virtual_boy.cpu.watchpoints.remove(&addr) {
-                        println!("Watchpoint at 0x{:08x} does not exist", addr);
+                Ok(Command::SaveCoverageListing(ref file_name)) => {
+                    if let Err(e) = self.save_coverage_listing(file_name) {
+                        println!("Couldn't save coverage listing to {}: {}", file_name, e);
+                    }
+                }
+                Ok(Command::ShowSearch) => {
+                    self.show_search();
+                }
+                Ok(Command::StartSearch(region, size)) => {
+                    self.memory_search = Some(MemorySearch::new(region, size, &mut self.virtual_boy.interconnect));
+                    self.show_search();
+                }
+                Ok(Command::FilterSearch(filter)) => {
+                    self.filter_search(filter);
+                }
+                Ok(Command::FilterSearchValue(ref expr)) => {
+                    if let Some(value) = self.evaluate(expr) {
+                        self.filter_search(SearchFilter::Value(value));
+                    }
+                }
+                Ok(Command::ShowCheats) => {
+                    if self.cheats.is_empty() {
+                        println!("No cheats");
+                    }
+                    for (index, cheat) in self.cheats.cheats().iter().enumerate() {
+                        println!("{}: [{}] {}", index, if cheat.is_enabled { "on" } else { "off" }, cheat.name);
+                        for code in cheat.codes.iter() {
+                            println!("    {}", code);
+                        }
+                    }
+                }
+                Ok(Command::EnableCheat(_, true)) if !self.check_cheats_allowed() => {}
+                Ok(Command::EnableCheat(index, is_enabled)) => {
+                    if self.cheats.set_enabled(index, is_enabled) {
+                        self.cheats.apply_patches(&mut self.virtual_boy.interconnect);
+                    } else {
+                        println!("Cheat {} does not exist", index);
+                    }
+                }
+                Ok(Command::AddCheatCode(..)) | Ok(Command::LoadCheats(_)) if !self.check_cheats_allowed() => {}
+                Ok(Command::AddCheatCode(kind, ref addr, size, ref value)) => {
+                    self.add_cheat_code(kind, addr, size, value);
+                }
+                Ok(Command::RemoveCheat(index)) => {
+                    match self.cheats.remove(index) {
+                        Some(_) => self.cheats.apply_patches(&mut self.virtual_boy.interconnect),
+                        _ => println!("Cheat {} does not exist", index),
+                    }
+                }
+                Ok(Command::LoadCheats(ref file_name)) => {
+                    match CheatList::load(file_name) {
+                        Ok(cheats) => {
+                            println!("Loaded {} cheats from {}", cheats.len(), file_name);
+                            self.set_cheats(cheats);
+                        }
+                        Err(e) => println!("Couldn't load cheats from {}: {}", file_name, e),
+                    }
+                }
+                Ok(Command::SaveCheats(ref file_name)) => {
+                    match file_name.as_ref().or(self.cheats_path.as_ref()) {
+                        Some(file_name) => match self.cheats.save(file_name) {
+                            Ok(()) => println!("Saved {} cheats to {}", self.cheats.len(), file_name),
+                            Err(e) => println!("Couldn't save cheats to {}: {}", file_name, e),
+                        },
+                        _ => println!("No cheat file given"),
+                    }
+                }
+                Ok(Command::ShowWorlds) => {
+                    self.show_worlds();
+                }
+                Ok(Command::ShowObjs(index, count)) => {
+                    let viewer = VramViewer::new(self.virtual_boy.interconnect.vip());
+                    if index >= NUM_OBJS {
+                        println!("OBJ index must be below {}", NUM_OBJS);
+                    }
+                    for index in index..cmp::min(index.saturating_add(count), NUM_OBJS) {
+                        println!("OBJ {:4}: {}", index, viewer.obj(index));
+                    }
+                }
+                Ok(Command::ShowChar(index)) => {
+                    if index < NUM_CHARS {
+                        let pixels = VramViewer::new(self.virtual_boy.interconnect.vip()).char_pixels(index);
+                        for row in pixels.chunks(8) {
+                            println!("  {}", row.iter().map(|&pixel| if pixel == 0 { ".".into() } else { pixel.to_string() }).collect::<Vec<_>>().join(""));
+                        }
+                    } else {
+                        println!("Char index must be below {}", NUM_CHARS);
+                    }
+                }
+                Ok(Command::SaveChars(ref file_name, palette)) => {
+                    let image = VramViewer::new(self.virtual_boy.interconnect.vip()).render_chars(palette);
+                    save_vram_image(&image, file_name);
+                }
+                Ok(Command::SaveBgMap(index, ref file_name)) => {
+                    if index < NUM_BGMAPS {
+                        let image = VramViewer::new(self.virtual_boy.interconnect.vip()).render_bgmap(index);
+                        save_vram_image(&image, file_name);
+                    } else {
+                        println!("BGMap index must be below {}", NUM_BGMAPS);
+                    }
+                }
+                Ok(Command::SaveObjs(ref file_name)) => {
+                    let image = VramViewer::new(self.virtual_boy.interconnect.vip()).render_objs();
+                    save_vram_image(&image, file_name);
+                }
+                Ok(Command::EnableWorld(index, is_enabled)) => {
+                    if index < NUM_WORLDS {
+                        self.virtual_boy.interconnect.vip_mut().set_world_enabled(index, is_enabled);
+                    } else {
+                        println!("World index must be below {}", NUM_WORLDS);
+                    }
+                }
+                Ok(Command::EnableObjGroup(index, is_enabled)) => {
+                    if index < 4 {
+                        self.virtual_boy.interconnect.vip_mut().set_obj_group_enabled(index as usize, is_enabled);
+                    } else {
+                        println!("OBJ group index must be below 4");
+                    }
+                }
+                Ok(Command::HighlightWorld(index)) => {
+                    match index {
+                        Some(index) if index >= NUM_WORLDS => println!("World index must be below {}", NUM_WORLDS),
+                        _ => self.virtual_boy.interconnect.vip_mut().set_highlighted_world(index),
                     }
                 }
                 Ok(Command::Exit) => {
@@ -328,117 +1135,254 @@ impl Emulator {
         stdout().flush().unwrap();
     }
 
-    fn disassemble_instruction(&mut self) -> u32 {
-        self.print_labels_at_cursor();
+    // Writes the flat profile and call graph, or the folded stacks for flamegraph tools
+    fn save_profile(&self, file_name: &str, folded: bool) -> io::Result<()> {
+        let profiler = match self.profiler {
+            Some(ref profiler) => profiler,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "No profile collected")),
+        };
 
-        if self.breakpoints.contains(&self.cursor) {
-            print!("* ");
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        if folded {
+            profiler.write_folded_stacks(&self.symbols, &mut writer)?;
         } else {
-            print!("  ");
+            profiler.write_flat_profile(&self.symbols, &mut writer)?;
+            writeln!(writer)?;
+            profiler.write_call_graph(&self.symbols, &mut writer)?;
         }
+        writer.flush()
+    }
 
-        print!("0x{:08x}  ", self.cursor);
+    fn save_coverage_map(&self, file_name: &str) -> io::Result<()> {
+        self.coverage_recorder.coverage().save_map(file_name)
+    }
 
-        let first_halfword = self.virtual_boy.interconnect.read_halfword(self.cursor);
-        let mut next_cursor = self.cursor.wrapping_add(2);
-        print!("{:02x}{:02x}", first_halfword & 0xff, first_halfword >> 8);
+    fn save_coverage_listing(&self, file_name: &str) -> io::Result<()> {
+        self.coverage_recorder.coverage().save_listing(file_name, self.virtual_boy.interconnect.rom(), &self.symbols)
+    }
 
-        let opcode = Opcode::from_halfword(first_halfword);
-        let instruction_format = opcode.instruction_format();
+    // Writes `count` consecutive copies of `value`, bypassing CPU watchpoints
+    fn write_mem(&mut self, addr: u32, count: u32, value: u32, size: AccessSize) {
+        if size != AccessSize::Word && value >> (size.num_bytes() * 8) != 0 {
+            println!("Value 0x{:x} doesn't fit in a {}", value, size_name(size));
+            return;
+        }
 
-        let second_halfword = if instruction_format.has_second_halfword() {
-            let second_halfword = self.virtual_boy.interconnect.read_halfword(next_cursor);
-            print!("{:02x}{:02x}", second_halfword & 0xff, second_halfword >> 8);
-            next_cursor = next_cursor.wrapping_add(2);
-            second_halfword
-        } else {
-            print!("    ");
-            0
+        let interconnect = &mut self.virtual_boy.interconnect;
+        for i in 0..count {
+            let addr = addr.wrapping_add(i.wrapping_mul(size.num_bytes()));
+            match size {
+                AccessSize::Byte => interconnect.write_byte(addr, value as u8),
+                AccessSize::Halfword => interconnect.write_halfword(addr, value as u16),
+                AccessSize::Word => {
+                    interconnect.write_halfword(addr, value as u16);
+                    interconnect.write_halfword(addr.wrapping_add(2), (value >> 16) as u16);
+                }
+            }
+        }
+    }
+
+    fn load_mem(&mut self, file_name: &str, addr: u32) {
+        let mut bytes = Vec::new();
+        if let Err(e) = File::open(file_name).and_then(|mut file| file.read_to_end(&mut bytes)) {
+            println!("Couldn't load {}: {}", file_name, e);
+            return;
+        }
+
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.virtual_boy.interconnect.write_byte(addr.wrapping_add(i as u32), byte);
+        }
+        println!("Loaded {} bytes to {}", bytes.len(), self.symbols.format_addr(addr));
+    }
+
+    // Adds a cheat with a single code, named after the code's address
+    fn add_cheat_code(&mut self, kind: CheatCodeKind, addr: &Expr, size: AccessSize, value: &Expr) {
+        let addr = match self.resolve_addr(addr) {
+            Some(addr) => addr,
+            _ => return,
+        };
+        let value = match self.evaluate(value) {
+            Some(value) => value,
+            _ => return,
         };
 
-        print!("    ");
+        let code = CheatCode {
+            kind: kind,
+            addr: addr,
+            size: size,
+            value: value,
+        };
+        if let Err(e) = code.validate() {
+            println!("{}", e);
+            return;
+        }
 
-        match instruction_format {
-            InstructionFormat::I => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                if opcode == Opcode::Jmp {
-                    println!("jmp [r{}]", reg1);
-                } else {
-                    println!("{} r{}, r{}", opcode, reg1, reg2);
-                }
+        println!("Added cheat {}: {}", self.cheats.len(), code);
+        self.cheats.push(Cheat {
+            name: self.symbols.format_addr(addr),
+            is_enabled: true,
+            codes: vec![code],
+        });
+        self.cheats.apply_patches(&mut self.virtual_boy.interconnect);
+        self.cheats.apply_freezes(&mut self.virtual_boy.interconnect);
+    }
+
+    fn filter_search(&mut self, filter: SearchFilter) {
+        match self.memory_search {
+            Some(ref mut memory_search) => memory_search.filter(filter, &mut self.virtual_boy.interconnect),
+            _ => {
+                println!("No search in progress (start one with search new)");
+                return;
             }
-            InstructionFormat::II => {
-                let imm5 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                match opcode {
-                    Opcode::BitString => {
-                        let bit_string_op = opcode.bit_string_op(imm5);
+        }
+        self.show_search();
+    }
+
+    fn show_search(&mut self) {
+        const MAX_SHOWN_CANDIDATES: usize = 32;
+
+        let memory_search = match self.memory_search {
+            Some(ref memory_search) => memory_search,
+            _ => {
+                println!("No search in progress (start one with search new)");
+                return;
+            }
+        };
 
-                        println!("{}", bit_string_op);
+        let candidates = memory_search.candidates();
+        println!("{} candidate {} values in {}", candidates.len(), size_name(memory_search.size()), memory_search.region());
+        if candidates.len() <= MAX_SHOWN_CANDIDATES {
+            for candidate in candidates.iter() {
+                println!("  {}: 0x{:x} ({})", self.symbols.format_addr(candidate.addr), candidate.value, candidate.value);
+            }
+        }
+    }
+
+    // Worlds are drawn from 31 down, until the END world
+    fn show_worlds(&self) {
+        let vip = self.virtual_boy.interconnect.vip();
+        let viewer = VramViewer::new(vip);
+        let mut obj_group = Some(3);
+        for index in (0..NUM_WORLDS).rev() {
+            let world = viewer.world(index);
+            print!("World {:2}: {}", index, world);
+            if !world.is_dummy() && !world.stop && world.mode == WorldMode::Obj {
+                match obj_group {
+                    Some(group) => {
+                        let start = vip.spt(group);
+                        let end = if group == 0 { 0 } else { vip.spt(group - 1) + 1 };
+                        let end = if end >= start { 0 } else { end };
+                        print!(" group {} (OBJs {}-{})", group, end, start);
+                        if !vip.is_obj_group_enabled(group) {
+                            print!(" [group off]");
+                        }
+                        obj_group = group.checked_sub(1);
                     }
-                    Opcode::Cli | Opcode::Reti | Opcode::Halt | Opcode::Sei => println!("{}", opcode),
-                    Opcode::Ldsr => println!("{} r{}, {}", opcode, reg2, opcode.system_register(imm5)),
-                    Opcode::Stsr => println!("{} {}, r{}", opcode, opcode.system_register(imm5), reg2),
-                    Opcode::MovImm | Opcode::AddImm5 | Opcode::CmpImm => println!("{} {}, r{}", opcode, (((imm5 as i32) << 27) >> 27), reg2),
-                    _ => println!("{} {}, r{}", opcode, imm5, reg2)
+                    _ => print!(" (all OBJ groups already drawn)"),
                 }
             }
-            InstructionFormat::III => {
-                let disp9 = first_halfword & 0x01ff;
-                let disp = (disp9 as u32) | if disp9 & 0x0100 == 0 { 0x00000000 } else { 0xfffffe00 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {:#x} (0x{:08x})", opcode, disp9, target);
+            if !vip.is_world_enabled(index) {
+                print!(" [off]");
             }
-            InstructionFormat::IV => {
-                let disp26 = (((first_halfword as u32) & 0x03ff) << 16) | (second_halfword as u32);
-                let disp = disp26 | if disp26 & 0x02000000 == 0 { 0x00000000 } else { 0xfc000000 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {} (0x{:08x})", opcode, disp26 as i32, target);
+            if vip.highlighted_world() == Some(index) {
+                print!(" [highlighted]");
             }
-            InstructionFormat::V => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let imm16 = second_halfword;
-
-                println!("{} {:#x}, r{}, r{}", opcode, imm16, reg1, reg2);
+            println!();
+            if world.stop {
+                break;
             }
-            InstructionFormat::VI => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
+        }
+    }
 
-                let disp16 = second_halfword as i16;
+    fn show_mem(&mut self) {
+        const NUM_ROWS: u32 = 16;
+        const NUM_COLS: u32 = 16;
+        for _ in 0..NUM_ROWS {
+            for offset in 0..NUM_COLS {
+                let addr = self.cursor.wrapping_add(offset);
+                for name in self.symbols.names_at(addr) {
+                    println!(".{}: 0x{:08x}", name, addr);
+                }
+            }
 
-                match opcode {
-                    Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Outb | Opcode::Outh | Opcode::Outw => println!("{} r{}, {}[r{}]", opcode, reg2, disp16, reg1),
-                    _ => println!("{} {}[r{}], r{}", opcode, disp16, reg1, reg2),
+            print!("0x{:08x}  ", self.cursor);
+            for x in 0..NUM_COLS {
+                let byte = self.virtual_boy.interconnect.read_byte(self.cursor);
+                self.cursor = self.cursor.wrapping_add(1);
+                print!("{:02x}", byte);
+                if x < NUM_COLS - 1 {
+                    print!(" ");
                 }
             }
-            InstructionFormat::VII => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
+            println!();
+        }
+    }
+
+    fn disassemble_instruction(&mut self) -> u32 {
+        self.print_labels_at_cursor();
+
+        if self.breakpoints.contains_key(&self.cursor) {
+            print!("* ");
+        } else {
+            print!("  ");
+        }
 
-                let subop_bits = second_halfword >> 10;
+        print!("0x{:08x}  ", self.cursor);
 
-                let subop = opcode.subop(subop_bits);
+        let interconnect = &mut self.virtual_boy.interconnect;
+        let instruction = DecodedInstruction::decode(self.cursor, |addr| interconnect.read_halfword(addr));
 
-                println!("{} r{}, r{}", subop, reg1, reg2);
-            }
+        print!("{:02x}{:02x}", instruction.first_halfword & 0xff, instruction.first_halfword >> 8);
+        if let Some(second_halfword) = instruction.second_halfword {
+            print!("{:02x}{:02x}", second_halfword & 0xff, second_halfword >> 8);
+        } else {
+            print!("    ");
         }
 
-        next_cursor
+        println!("    {}", instruction.format(&self.symbols));
+
+        instruction.next_addr()
+    }
+
+    // Evaluates a command's address argument, printing an error if it names a missing label
+    fn resolve_addr(&mut self, addr: &Expr) -> Option<u32> {
+        self.evaluate(addr)
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Option<u32> {
+        match expr.evaluate(&mut self.virtual_boy, &self.symbols) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        }
     }
 
     fn print_labels_at_cursor(&mut self) {
-        for (name, _) in self.labels.iter().filter(|x| *x.1 == self.cursor) {
+        for name in self.symbols.names_at(self.cursor) {
             println!(".{}:", name);
         }
     }
 }
 
+fn size_name(size: AccessSize) -> &'static str {
+    match size {
+        AccessSize::Byte => "byte",
+        AccessSize::Halfword => "halfword",
+        AccessSize::Word => "word",
+    }
+}
+
 fn read_stdin() -> String {
     let mut input = String::new();
     stdin().read_line(&mut input).unwrap();
     input.trim().into()
 }
+
+fn save_vram_image(image: &VramImage, file_name: &str) {
+    match image.save_png(file_name) {
+        Ok(()) => println!("Saved {}x{} image to {}", image.width, image.height, file_name),
+        Err(e) => println!("Couldn't save image to {}: {}", file_name, e),
+    }
+}
@@ -0,0 +1,343 @@
+use rustual_boy_core::bus::Bus;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_middleware::SymbolTable;
+
+use std::borrow::Cow;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Gpr(usize),
+    Pc,
+    Psw,
+    Eipc,
+    Eipsw,
+    Fepc,
+    Fepsw,
+    Ecr,
+}
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Register> {
+        match name {
+            "pc" => Some(Register::Pc),
+            "psw" => Some(Register::Psw),
+            "eipc" => Some(Register::Eipc),
+            "eipsw" => Some(Register::Eipsw),
+            "fepc" => Some(Register::Fepc),
+            "fepsw" => Some(Register::Fepsw),
+            "ecr" => Some(Register::Ecr),
+            "sp" => Some(Register::Gpr(3)),
+            "gp" => Some(Register::Gpr(4)),
+            "tp" => Some(Register::Gpr(5)),
+            "lp" => Some(Register::Gpr(31)),
+            _ if name.len() > 1 && name.starts_with('r') && name[1..].chars().all(|c| c.is_ascii_digit()) => {
+                match name[1..].parse() {
+                    Ok(index) if index < 32 => Some(Register::Gpr(index)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn read(&self, virtual_boy: &VirtualBoy) -> u32 {
+        let cpu = &virtual_boy.cpu;
+        match self {
+            &Register::Gpr(index) => cpu.reg_gpr(index),
+            &Register::Pc => cpu.reg_pc(),
+            &Register::Psw => cpu.reg_psw(),
+            &Register::Eipc => cpu.reg_eipc(),
+            &Register::Eipsw => cpu.reg_eipsw(),
+            &Register::Fepc => cpu.reg_fepc(),
+            &Register::Fepsw => cpu.reg_fepsw(),
+            &Register::Ecr => cpu.reg_ecr(),
+        }
+    }
+
+    pub fn write(&self, virtual_boy: &mut VirtualBoy, value: u32) {
+        let cpu = &mut virtual_boy.cpu;
+        match self {
+            &Register::Gpr(index) => cpu.set_reg_gpr(index, value),
+            &Register::Pc => cpu.set_reg_pc(value),
+            &Register::Psw => cpu.set_reg_psw(value),
+            &Register::Eipc => cpu.set_reg_eipc(value),
+            &Register::Eipsw => cpu.set_reg_eipsw(value),
+            &Register::Fepc => cpu.set_reg_fepc(value),
+            &Register::Fepsw => cpu.set_reg_fepsw(value),
+            &Register::Ecr => cpu.set_reg_ecr(value),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Register::Gpr(index) => write!(f, "r{}", index),
+            &Register::Pc => write!(f, "pc"),
+            &Register::Psw => write!(f, "psw"),
+            &Register::Eipc => write!(f, "eipc"),
+            &Register::Eipsw => write!(f, "eipsw"),
+            &Register::Fepc => write!(f, "fepc"),
+            &Register::Fepsw => write!(f, "fepsw"),
+            &Register::Ecr => write!(f, "ecr"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerefSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            &BinaryOp::Mul => "*",
+            &BinaryOp::Div => "/",
+            &BinaryOp::Rem => "%",
+            &BinaryOp::Add => "+",
+            &BinaryOp::Sub => "-",
+            &BinaryOp::Shl => "<<",
+            &BinaryOp::Shr => ">>",
+            &BinaryOp::Lt => "<",
+            &BinaryOp::Le => "<=",
+            &BinaryOp::Gt => ">",
+            &BinaryOp::Ge => ">=",
+            &BinaryOp::Eq => "==",
+            &BinaryOp::Ne => "!=",
+            &BinaryOp::BitAnd => "&",
+            &BinaryOp::BitXor => "^",
+            &BinaryOp::BitOr => "|",
+            &BinaryOp::And => "&&",
+            &BinaryOp::Or => "||",
+        }
+    }
+}
+
+/// A debugger expression. Values are 32-bit and unsigned; comparisons and
+/// logical operators evaluate to 1 or 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Constant(u32),
+    Register(Register),
+    Label(String),
+    Deref(Box<Expr>, DerefSize),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn evaluate(&self, virtual_boy: &mut VirtualBoy, symbols: &SymbolTable) -> Result<u32, Cow<'static, str>> {
+        Ok(match self {
+            &Expr::Constant(value) => value,
+            &Expr::Register(register) => register.read(virtual_boy),
+            &Expr::Label(ref name) => match symbols.get(name) {
+                Some(addr) => addr,
+                _ => return Err(format!("Label .{} does not exist", name).into()),
+            },
+            &Expr::Deref(ref addr, size) => {
+                let addr = addr.evaluate(virtual_boy, symbols)?;
+                let interconnect = &mut virtual_boy.interconnect;
+                match size {
+                    DerefSize::Byte => interconnect.read_byte(addr) as u32,
+                    DerefSize::Halfword => interconnect.read_halfword(addr & 0xfffffffe) as u32,
+                    DerefSize::Word => interconnect.read_word(addr & 0xfffffffc),
+                }
+            }
+            &Expr::Unary(op, ref expr) => {
+                let value = expr.evaluate(virtual_boy, symbols)?;
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as u32,
+                    UnaryOp::BitNot => !value,
+                }
+            }
+            &Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.evaluate(virtual_boy, symbols)?;
+
+                // Logical operators short-circuit, so conditions like `r10 != 0 && [r10] == 1`
+                //  don't touch memory they don't need to
+                match op {
+                    BinaryOp::And if lhs == 0 => return Ok(0),
+                    BinaryOp::Or if lhs != 0 => return Ok(1),
+                    _ => (),
+                }
+
+                let rhs = rhs.evaluate(virtual_boy, symbols)?;
+                match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs).ok_or("Division by zero")?,
+                    BinaryOp::Rem => lhs.checked_rem(rhs).ok_or("Division by zero")?,
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Shl => lhs.checked_shl(rhs).unwrap_or(0),
+                    BinaryOp::Shr => lhs.checked_shr(rhs).unwrap_or(0),
+                    BinaryOp::Lt => (lhs < rhs) as u32,
+                    BinaryOp::Le => (lhs <= rhs) as u32,
+                    BinaryOp::Gt => (lhs > rhs) as u32,
+                    BinaryOp::Ge => (lhs >= rhs) as u32,
+                    BinaryOp::Eq => (lhs == rhs) as u32,
+                    BinaryOp::Ne => (lhs != rhs) as u32,
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    BinaryOp::BitOr => lhs | rhs,
+                    BinaryOp::And | BinaryOp::Or => (rhs != 0) as u32,
+                }
+            }
+        })
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Expr::Binary(..) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Expr::Constant(value) => write!(f, "0x{:x}", value),
+            &Expr::Register(register) => write!(f, "{}", register),
+            &Expr::Label(ref name) => write!(f, ".{}", name),
+            &Expr::Deref(ref addr, size) => {
+                let suffix = match size {
+                    DerefSize::Byte => "b",
+                    DerefSize::Halfword => "h",
+                    DerefSize::Word => "w",
+                };
+                write!(f, "[{}].{}", addr, suffix)
+            }
+            &Expr::Unary(op, ref expr) => {
+                let symbol = match op {
+                    UnaryOp::Negate => "-",
+                    UnaryOp::Not => "!",
+                    UnaryOp::BitNot => "~",
+                };
+                write!(f, "{}", symbol)?;
+                expr.fmt_operand(f)
+            }
+            &Expr::Binary(op, ref lhs, ref rhs) => {
+                lhs.fmt_operand(f)?;
+                write!(f, " {} ", op.symbol())?;
+                rhs.fmt_operand(f)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use command::Command;
+
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sram::Sram;
+
+    fn evaluate(s: &str, virtual_boy: &mut VirtualBoy, symbols: &SymbolTable) -> Result<u32, Cow<'static, str>> {
+        match format!("print {}", s).parse() {
+            Ok(Command::Print(expr)) => expr.evaluate(virtual_boy, symbols),
+            other => panic!("Unable to parse expression {:?}: {:?}", s, other),
+        }
+    }
+
+    fn test_virtual_boy() -> VirtualBoy {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        VirtualBoy::new(rom, Sram::new())
+    }
+
+    #[test]
+    fn arithmetic_and_logic() {
+        let mut virtual_boy = test_virtual_boy();
+        let symbols = SymbolTable::new();
+        let mut eval = |s| evaluate(s, &mut virtual_boy, &symbols).unwrap();
+
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 3 - 2"), 5);
+        assert_eq!(eval("17 / 5"), 3);
+        assert_eq!(eval("17 % 5"), 2);
+        assert_eq!(eval("0 - 1"), 0xffffffff);
+        assert_eq!(eval("-1"), 0xffffffff);
+        assert_eq!(eval("~0x0f"), 0xfffffff0);
+        assert_eq!(eval("!5"), 0);
+        assert_eq!(eval("1 << 31"), 0x80000000);
+        assert_eq!(eval("1 << 32"), 0);
+        assert_eq!(eval("0x80000000 >> 31"), 1);
+        assert_eq!(eval("3 < 4 && 4 <= 4 && 5 > 4 && 4 >= 5 == 0"), 1);
+        assert_eq!(eval("0xf0 & 0x3c | 0x01 ^ 0x03"), 0x32);
+        assert_eq!(eval("2 || 0"), 1);
+        assert_eq!(eval("0 && 2"), 0);
+    }
+
+    #[test]
+    fn registers_labels_and_memory() {
+        let mut virtual_boy = test_virtual_boy();
+        virtual_boy.cpu.set_reg_gpr(3, 0x0500fff0);
+        virtual_boy.cpu.set_reg_gpr(10, 7);
+        virtual_boy.interconnect.write_word(0x0500fff4, 0x12345678);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("counter".to_string(), 0x0500fff4);
+
+        let mut eval = |s| evaluate(s, &mut virtual_boy, &symbols);
+
+        assert_eq!(eval("r10 * 2").unwrap(), 14);
+        assert_eq!(eval("sp").unwrap(), 0x0500fff0);
+        assert_eq!(eval(".counter").unwrap(), 0x0500fff4);
+        assert_eq!(eval("[sp + 4]").unwrap(), 0x12345678);
+        assert_eq!(eval("[counter].h").unwrap(), 0x5678);
+        assert_eq!(eval("[counter + 3].b").unwrap(), 0x12);
+        assert_eq!(eval("[counter + 1].w").unwrap(), 0x12345678);
+        assert!(eval("missing + 1").is_err());
+    }
+
+    #[test]
+    fn errors_and_short_circuit() {
+        let mut virtual_boy = test_virtual_boy();
+        let symbols = SymbolTable::new();
+        let mut eval = |s| evaluate(s, &mut virtual_boy, &symbols);
+
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 % (2 - 2)").is_err());
+
+        // The right hand side is never evaluated, so neither error is reported
+        assert_eq!(eval("0 && 1 / 0").unwrap(), 0);
+        assert_eq!(eval("1 || missing").unwrap(), 1);
+        assert!(eval("1 && missing").is_err());
+    }
+}
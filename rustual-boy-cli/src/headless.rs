@@ -0,0 +1,70 @@
+use argparse::{HeadlessConfig, RunLength};
+
+use rustual_boy_core::vip::{DISPLAY_RESOLUTION_X, DISPLAY_RESOLUTION_Y};
+use rustual_boy_core::vsu::SAMPLE_RATE;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use rustual_boy_middleware::{CoverageRecorder, HeadlessRunner, InputScript, Movie, PngColorType, SymbolTable, WaveFileBufferSink, write_png_file};
+
+pub fn run(config: HeadlessConfig, virtual_boy: VirtualBoy, play_movie: Option<Movie>, symbols: &SymbolTable, coverage_prefix: Option<String>) {
+    let mut runner = HeadlessRunner::new(virtual_boy);
+
+    if let Some(input_script_path) = config.input_script_path {
+        logln!("Loading input script {}", input_script_path);
+        runner.set_input_script(InputScript::load(input_script_path).unwrap());
+    }
+
+    if let Some(movie) = play_movie {
+        runner.set_movie(movie);
+    }
+
+    if let Some(ref dump_audio_path) = config.dump_audio_path {
+        logln!("Dumping audio to {}", dump_audio_path);
+        runner.set_audio_buffer_sink(Box::new(WaveFileBufferSink::new(dump_audio_path, SAMPLE_RATE).unwrap()));
+    }
+
+    let mut coverage_recorder = CoverageRecorder::new(runner.virtual_boy.interconnect.rom().size());
+    if coverage_prefix.is_some() {
+        coverage_recorder.start(&mut runner.virtual_boy);
+    }
+
+    match config.run_length {
+        RunLength::Frames(frames) => runner.run_frames(frames),
+        RunLength::Cycles(cycles) => runner.run_cycles(cycles),
+    }
+
+    logln!("Ran {} frames ({} cycles)", runner.emulated_frames(), runner.emulated_cycles());
+
+    if let Some(fatal_exception) = runner.virtual_boy.cpu.fatal_exception() {
+        println!("CPU crashed: {}", fatal_exception);
+    }
+
+    if let Some(dump_frame_prefix) = config.dump_frame_prefix {
+        match runner.last_video_frame() {
+            Some(&(ref left, ref right)) => {
+                for &(eye, buffer) in [("left", left), ("right", right)].iter() {
+                    let file_name = format!("{}-{}.png", dump_frame_prefix, eye);
+                    logln!("Dumping {} eye frame to {}", eye, file_name);
+                    write_png_file(file_name, DISPLAY_RESOLUTION_X, DISPLAY_RESOLUTION_Y, PngColorType::Grayscale, buffer).unwrap();
+                }
+            }
+            _ => logln!("No video frame was emitted, nothing to dump"),
+        }
+    }
+
+    if let Some(coverage_prefix) = coverage_prefix {
+        let coverage = coverage_recorder.coverage();
+        logln!("{} instructions executed, {} ROM bytes read as data", coverage.num_instructions(), coverage.num_read_bytes());
+
+        let map_file_name = format!("{}.cov", coverage_prefix);
+        logln!("Saving coverage map to {}", map_file_name);
+        coverage.save_map(map_file_name).unwrap();
+
+        let listing_file_name = format!("{}.lst", coverage_prefix);
+        logln!("Saving coverage listing to {}", listing_file_name);
+        coverage.save_listing(listing_file_name, runner.virtual_boy.interconnect.rom(), symbols).unwrap();
+    }
+
+    println!("RAM hash (WRAM + SRAM): 0x{:08x}", runner.ram_hash());
+}
+
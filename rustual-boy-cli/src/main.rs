@@ -1,3 +1,9 @@
+// Keep the 2015-edition idioms used throughout the crate
+#![allow(bare_trait_objects)]
+#![allow(clippy::redundant_field_names, clippy::needless_return, clippy::match_ref_pats, clippy::needless_borrowed_reference)]
+#![allow(clippy::redundant_closure, clippy::useless_format, clippy::unnecessary_map_or)]
+#![allow(clippy::manual_range_contains, clippy::io_other_error)]
+
 extern crate minifb;
 
 extern crate cpal;
@@ -19,15 +25,20 @@ mod logging;
 mod command;
 mod cpal_driver;
 mod emulator;
+mod expression;
+mod headless;
 mod system_time_source;
-mod wave_file_buffer_sink;
 
 use rustual_boy_core::rom::*;
 use rustual_boy_core::sram::*;
 use rustual_boy_core::vsu::*;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_middleware::{CheatList, GdbStub, Movie, RomIdentity, SymbolTable};
 use cpal_driver::*;
 use emulator::*;
 
+use std::path::Path;
+
 fn main() {
     let config = argparse::parse_args();
 
@@ -48,30 +59,135 @@ fn main() {
     logln!(" game code: \"{}\"", rom.game_code().unwrap());
     logln!(" game version: 1.{:#02}", rom.game_version_byte());
 
-    logln!("Attempting to load SRAM file: {}", config.sram_path);
-    let sram = match Sram::load(&config.sram_path) {
-        Ok(sram) => {
-            logln!(" SRAM loaded successfully");
+    let rom_identity = RomIdentity::from_rom(&rom);
 
-            sram
-        }
-        Err(err) => {
-            logln!(" Couldn't load SRAM file: {}", err);
+    let play_movie = config.play_movie_path.as_ref().map(|play_movie_path| {
+        logln!("Loading movie file {}", play_movie_path);
+        let movie = Movie::load(play_movie_path).unwrap();
+        movie.check_rom_identity(&rom_identity).unwrap();
+        logln!(" {} frames", movie.len());
+        movie
+    });
+
+    let is_movie_active = play_movie.is_some() || config.record_movie_path.is_some();
+
+    // Movies and headless runs always start from power-on with blank SRAM so they replay identically
+    let sram = if is_movie_active || config.headless.is_some() {
+        logln!("Movie or headless run active, starting with blank SRAM");
 
-            Sram::new()
+        Sram::new()
+    } else {
+        logln!("Attempting to load SRAM file: {}", config.sram_path);
+        match Sram::load(&config.sram_path) {
+            Ok(sram) => {
+                logln!(" SRAM loaded successfully");
+
+                sram
+            }
+            Err(err) => {
+                logln!(" Couldn't load SRAM file: {}", err);
+
+                Sram::new()
+            }
         }
     };
 
+    let mut symbols = SymbolTable::new();
+    if Path::new(&config.symbols_path).exists() {
+        load_symbols(&mut symbols, &config.symbols_path);
+    }
+    for symbol_file_path in config.symbol_file_paths.iter() {
+        load_symbols(&mut symbols, symbol_file_path);
+    }
+
+    if let Some(headless_config) = config.headless {
+        headless::run(headless_config, VirtualBoy::new(rom, sram), play_movie, &symbols, config.coverage_prefix);
+        return;
+    }
+
+    let cheats_path = config.cheats_path.clone().unwrap_or_else(|| {
+        Path::new(&config.rom_path).with_file_name(CheatList::file_name(&rom)).to_string_lossy().into_owned()
+    });
+
     let audio_driver = CpalDriver::new(SAMPLE_RATE, 100).unwrap();
 
     let audio_buffer_sink = audio_driver.sink();
     let time_source = audio_driver.time_source();
 
     let mut emulator = Emulator::new(rom, sram, audio_buffer_sink, time_source);
+    if let Some(movie) = play_movie {
+        emulator.start_movie_playback(movie);
+    }
+    if config.record_movie_path.is_some() {
+        emulator.start_movie_recording(Movie::new(rom_identity));
+    }
+
+    emulator.set_symbols_path(config.symbols_path.clone());
+    emulator.set_symbols(symbols);
+
+    // Movies don't record cheats, so they'd play back differently with them
+    let mut cheats = CheatList::new();
+    if is_movie_active {
+        logln!("Movie active, cheats disabled");
+    } else if Path::new(&cheats_path).exists() {
+        logln!("Loading cheat file {}", cheats_path);
+        match CheatList::load(&cheats_path) {
+            Ok(loaded_cheats) => {
+                logln!(" {} cheats loaded", loaded_cheats.len());
+                cheats = loaded_cheats;
+            }
+            Err(err) => logln!(" Couldn't load cheat file: {}", err),
+        }
+    }
+    emulator.set_cheats_path(cheats_path);
+    emulator.set_cheats(cheats);
+
+    if let Some(ref trace_path) = config.trace_path {
+        logln!("Tracing instructions to {}", trace_path);
+        emulator.start_trace(trace_path).unwrap();
+    }
+
+    if config.coverage_prefix.is_some() {
+        emulator.start_coverage();
+    }
+
+    if let Some(gdb_port) = config.gdb_port {
+        logln!("Waiting for gdb to connect on port {}", gdb_port);
+        emulator.attach_gdb_stub(GdbStub::listen(("127.0.0.1", gdb_port)).unwrap());
+        logln!(" gdb connected");
+    }
+
     emulator.run();
 
-    if emulator.virtual_boy.interconnect.sram.size() > 0 {
+    if let Err(err) = emulator.stop_trace() {
+        logln!("Couldn't finish writing trace: {}", err);
+    }
+
+    if let Some(ref coverage_prefix) = config.coverage_prefix {
+        logln!("Saving coverage to {}.cov and {}.lst", coverage_prefix, coverage_prefix);
+        if let Err(err) = emulator.save_coverage(coverage_prefix) {
+            logln!("Couldn't save coverage: {}", err);
+        }
+    }
+
+    if let (Some(record_movie_path), Some(movie)) = (config.record_movie_path, emulator.take_movie_recording()) {
+        logln!("Saving movie ({} frames) to {}", movie.len(), record_movie_path);
+        movie.save(record_movie_path).unwrap();
+    }
+
+    if !is_movie_active && emulator.virtual_boy.interconnect.sram.size() > 0 {
         logln!("SRAM used, saving to {}", config.sram_path);
         emulator.virtual_boy.interconnect.sram.save(config.sram_path).unwrap();
     }
 }
+
+fn load_symbols(symbols: &mut SymbolTable, file_name: &str) {
+    logln!("Loading symbol file {}", file_name);
+    match SymbolTable::load(file_name) {
+        Ok(loaded_symbols) => {
+            logln!(" {} symbols loaded", loaded_symbols.len());
+            symbols.extend(loaded_symbols);
+        }
+        Err(err) => logln!(" Couldn't load symbol file: {}", err),
+    }
+}
@@ -0,0 +1,41 @@
+/// The memory bus as seen by the CPU. `Interconnect` implements this for the
+/// real Virtual Boy memory map, but anything (a flat RAM for tests, a tracing
+/// proxy, etc.) can be plugged into `V810::step`.
+pub trait Bus {
+    fn read_byte(&mut self, addr: u32) -> u8;
+    fn read_halfword(&mut self, addr: u32) -> u16;
+    fn write_byte(&mut self, addr: u32, value: u8);
+    fn write_halfword(&mut self, addr: u32, value: u16);
+
+    /// Reads a byte without any side effects on the hardware behind it, so
+    /// debuggers and memory hooks can look at memory without disturbing it
+    fn peek_byte(&self, addr: u32) -> u8;
+
+    /// Word accesses are split into two halfword accesses, as the V810 does on
+    /// the Virtual Boy's 16-bit data bus
+    fn read_word(&mut self, addr: u32) -> u32 {
+        (self.read_halfword(addr) as u32) |
+        ((self.read_halfword(addr.wrapping_add(2)) as u32) << 16)
+    }
+
+    fn write_word(&mut self, addr: u32, value: u32) {
+        self.write_halfword(addr, value as _);
+        self.write_halfword(addr.wrapping_add(2), (value >> 16) as _);
+    }
+
+    fn peek_halfword(&self, addr: u32) -> u16 {
+        let addr = addr & 0xfffffffe;
+        (self.peek_byte(addr) as u16) |
+        ((self.peek_byte(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn peek_word(&self, addr: u32) -> u32 {
+        (self.peek_halfword(addr) as u32) |
+        ((self.peek_halfword(addr.wrapping_add(2)) as u32) << 16)
+    }
+
+    /// Returns the number of wait states added to each access to `addr`
+    fn wait_states(&self, _addr: u32) -> u32 {
+        0
+    }
+}
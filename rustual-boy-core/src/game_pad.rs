@@ -1,3 +1,8 @@
+use std::io;
+
+use state::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Button {
     A,
     B,
@@ -86,6 +91,31 @@ impl GamePad {
         if self.left_d_pad_right_pressed { 1 } else { 0 }
     }
 
+    /// Returns the state of all buttons as a bitmask laid out like the input
+    /// registers (high register in the upper byte). Bits that don't correspond
+    /// to a button are always clear.
+    pub fn buttons(&self) -> u16 {
+        ((self.read_input_high_reg() as u16) << 8) |
+        ((self.read_input_low_reg() & 0xfc) as u16)
+    }
+
+    pub fn set_buttons(&mut self, buttons: u16) {
+        self.right_d_pad_down_pressed = (buttons & 0x8000) != 0;
+        self.right_d_pad_left_pressed = (buttons & 0x4000) != 0;
+        self.select_pressed = (buttons & 0x2000) != 0;
+        self.start_pressed = (buttons & 0x1000) != 0;
+        self.left_d_pad_up_pressed = (buttons & 0x0800) != 0;
+        self.left_d_pad_down_pressed = (buttons & 0x0400) != 0;
+        self.left_d_pad_left_pressed = (buttons & 0x0200) != 0;
+        self.left_d_pad_right_pressed = (buttons & 0x0100) != 0;
+        self.right_d_pad_right_pressed = (buttons & 0x0080) != 0;
+        self.right_d_pad_up_pressed = (buttons & 0x0040) != 0;
+        self.l_pressed = (buttons & 0x0020) != 0;
+        self.r_pressed = (buttons & 0x0010) != 0;
+        self.b_pressed = (buttons & 0x0008) != 0;
+        self.a_pressed = (buttons & 0x0004) != 0;
+    }
+
     pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
         match button {
             Button::A => self.a_pressed = pressed,
@@ -104,4 +134,40 @@ impl GamePad {
             Button::RightDPadRight => self.right_d_pad_right_pressed = pressed,
         }
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.a_pressed);
+        writer.write_bool(self.b_pressed);
+        writer.write_bool(self.start_pressed);
+        writer.write_bool(self.select_pressed);
+        writer.write_bool(self.l_pressed);
+        writer.write_bool(self.r_pressed);
+        writer.write_bool(self.left_d_pad_up_pressed);
+        writer.write_bool(self.left_d_pad_down_pressed);
+        writer.write_bool(self.left_d_pad_left_pressed);
+        writer.write_bool(self.left_d_pad_right_pressed);
+        writer.write_bool(self.right_d_pad_up_pressed);
+        writer.write_bool(self.right_d_pad_down_pressed);
+        writer.write_bool(self.right_d_pad_left_pressed);
+        writer.write_bool(self.right_d_pad_right_pressed);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.a_pressed = reader.read_bool()?;
+        self.b_pressed = reader.read_bool()?;
+        self.start_pressed = reader.read_bool()?;
+        self.select_pressed = reader.read_bool()?;
+        self.l_pressed = reader.read_bool()?;
+        self.r_pressed = reader.read_bool()?;
+        self.left_d_pad_up_pressed = reader.read_bool()?;
+        self.left_d_pad_down_pressed = reader.read_bool()?;
+        self.left_d_pad_left_pressed = reader.read_bool()?;
+        self.left_d_pad_right_pressed = reader.read_bool()?;
+        self.right_d_pad_up_pressed = reader.read_bool()?;
+        self.right_d_pad_down_pressed = reader.read_bool()?;
+        self.right_d_pad_left_pressed = reader.read_bool()?;
+        self.right_d_pad_right_pressed = reader.read_bool()?;
+
+        Ok(())
+    }
 }
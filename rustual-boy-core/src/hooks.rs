@@ -0,0 +1,178 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+impl AccessSize {
+    pub fn num_bytes(&self) -> u32 {
+        match *self {
+            AccessSize::Byte => 1,
+            AccessSize::Halfword => 2,
+            AccessSize::Word => 4,
+        }
+    }
+}
+
+/// A single CPU memory access, as reported to hooks. For reads and
+/// instruction fetches `old_value` and `new_value` are both the value read;
+/// for writes they're the memory contents before and after the write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub size: AccessSize,
+    pub pc: u32,
+    pub addr: u32,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+            AccessKind::Execute => "execute",
+        };
+        let width = (self.size.num_bytes() * 2) as usize;
+        write!(f, "{} at 0x{:08x} (pc: 0x{:08x}): 0x{:0width$x} -> 0x{:0width$x}", kind, self.addr, self.pc, self.old_value, self.new_value, width = width)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Asks the frontend to stop emulation once the current instruction completes
+    Break,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u32);
+
+/// Calls back when the CPU accesses memory in `start...end` (inclusive) with
+/// the given access kind. Hooks can additionally be limited to one access
+/// size, and to accesses whose (new) value equals `value`.
+pub struct MemoryHook {
+    pub kind: AccessKind,
+    pub start: u32,
+    pub end: u32,
+    pub size: Option<AccessSize>,
+    pub value: Option<u32>,
+    callback: Box<FnMut(&MemoryAccess) -> HookAction>,
+}
+
+impl MemoryHook {
+    pub fn new<F>(kind: AccessKind, start: u32, end: u32, callback: F) -> MemoryHook
+        where F: FnMut(&MemoryAccess) -> HookAction + 'static {
+        MemoryHook {
+            kind: kind,
+            start: start,
+            end: end,
+            size: None,
+            value: None,
+            callback: Box::new(callback),
+        }
+    }
+
+    fn covers(&self, kind: AccessKind, addr: u32, size: AccessSize) -> bool {
+        if self.kind != kind {
+            return false;
+        }
+
+        if let Some(hook_size) = self.size {
+            if hook_size != size {
+                return false;
+            }
+        }
+
+        let last_addr = addr.wrapping_add(size.num_bytes() - 1);
+        addr <= self.end && last_addr >= self.start
+    }
+
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        if !self.covers(access.kind, access.addr, access.size) {
+            return false;
+        }
+
+        match self.value {
+            Some(value) => value == access.new_value,
+            _ => true
+        }
+    }
+}
+
+/// The set of memory hooks registered on the CPU
+#[derive(Default)]
+pub struct MemoryHooks {
+    hooks: Vec<(HookId, MemoryHook)>,
+    next_id: u32,
+}
+
+impl MemoryHooks {
+    pub fn new() -> MemoryHooks {
+        MemoryHooks {
+            hooks: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn add(&mut self, hook: MemoryHook) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.hooks.push((id, hook));
+        id
+    }
+
+    /// Removes a hook, returning false if it wasn't registered
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|&(hook_id, _)| hook_id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn get(&self, id: HookId) -> Option<&MemoryHook> {
+        self.hooks.iter().find(|entry| entry.0 == id).map(|entry| &entry.1)
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = (HookId, &'a MemoryHook)> + 'a> {
+        Box::new(self.hooks.iter().map(|&(id, ref hook)| (id, hook)))
+    }
+
+    /// Returns true if any hook could fire for this access, regardless of value
+    pub fn covers(&self, kind: AccessKind, addr: u32, size: AccessSize) -> bool {
+        self.hooks.iter().any(|entry| entry.1.covers(kind, addr, size))
+    }
+
+    /// Calls every matching hook, returning the first one that asked to break
+    pub fn dispatch(&mut self, access: &MemoryAccess) -> Option<HookId> {
+        let mut break_id = None;
+        for &mut (id, ref mut hook) in self.hooks.iter_mut() {
+            if hook.matches(access) && (hook.callback)(access) == HookAction::Break && break_id.is_none() {
+                break_id = Some(id);
+            }
+        }
+        break_id
+    }
+}
@@ -43,6 +43,7 @@ pub const OPCODE_BITS_SHL_IMM: u16 = 0b010100;
 pub const OPCODE_BITS_SHR_IMM: u16 = 0b010101;
 pub const OPCODE_BITS_CLI: u16 = 0b010110;
 pub const OPCODE_BITS_SAR_IMM: u16 = 0b010111;
+pub const OPCODE_BITS_TRAP: u16 = 0b011000;
 pub const OPCODE_BITS_RETI: u16 = 0b011001;
 pub const OPCODE_BITS_HALT: u16 = 0b011010;
 pub const OPCODE_BITS_LDSR: u16 = 0b011100;
@@ -118,7 +119,7 @@ pub const OPCODE_CONDITION_BITS_F: u32 = 0x0d;
 pub const OPCODE_CONDITION_BITS_GE: u32 = 0x0e;
 pub const OPCODE_CONDITION_BITS_GT: u32 = 0x0f;
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Opcode {
     MovReg,
     AddReg,
@@ -144,6 +145,7 @@ pub enum Opcode {
     ShrImm,
     Cli,
     SarImm,
+    Trap,
     Reti,
     Halt,
     Ldsr,
@@ -190,10 +192,11 @@ pub enum Opcode {
 }
 
 impl Opcode {
-    pub fn from_halfword(halfword: u16) -> Opcode {
+    /// Decodes the opcode from an instruction's first halfword. Returns None for illegal opcodes.
+    pub fn from_halfword(halfword: u16) -> Option<Opcode> {
         if halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
             let cond_bits = (halfword >> 9) & 0x0f;
-            match cond_bits {
+            Some(match cond_bits {
                 OPCODE_BITS_BCOND_BV => Opcode::Bv,
                 OPCODE_BITS_BCOND_BC => Opcode::Bc,
                 OPCODE_BITS_BCOND_BZ => Opcode::Bz,
@@ -210,11 +213,11 @@ impl Opcode {
                 OPCODE_BITS_BCOND_NOP => Opcode::Nop,
                 OPCODE_BITS_BCOND_BGE => Opcode::Bge,
                 OPCODE_BITS_BCOND_BGT => Opcode::Bgt,
-                _ => panic!("Unrecognized cond bits: {:04b} (halfword: 0b{:016b})", cond_bits, halfword)
-            }
+                _ => unreachable!()
+            })
         } else {
             let opcode_bits = halfword >> 10;
-            match opcode_bits {
+            Some(match opcode_bits {
                 OPCODE_BITS_MOV_REG => Opcode::MovReg,
                 OPCODE_BITS_ADD_REG => Opcode::AddReg,
                 OPCODE_BITS_SUB => Opcode::Sub,
@@ -239,6 +242,7 @@ impl Opcode {
                 OPCODE_BITS_SHR_IMM => Opcode::ShrImm,
                 OPCODE_BITS_CLI => Opcode::Cli,
                 OPCODE_BITS_SAR_IMM => Opcode::SarImm,
+                OPCODE_BITS_TRAP => Opcode::Trap,
                 OPCODE_BITS_RETI => Opcode::Reti,
                 OPCODE_BITS_HALT => Opcode::Halt,
                 OPCODE_BITS_LDSR => Opcode::Ldsr,
@@ -266,8 +270,8 @@ impl Opcode {
                 OPCODE_BITS_OUTH => Opcode::Outh,
                 OPCODE_BITS_EXTENDED => Opcode::Extended,
                 OPCODE_BITS_OUTW => Opcode::Outw,
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, halfword),
-            }
+                _ => return None,
+            })
         }
     }
 
@@ -297,6 +301,7 @@ impl Opcode {
             &Opcode::ShrImm => InstructionFormat::II,
             &Opcode::Cli => InstructionFormat::II,
             &Opcode::SarImm => InstructionFormat::II,
+            &Opcode::Trap => InstructionFormat::II,
             &Opcode::Reti => InstructionFormat::II,
             &Opcode::Halt => InstructionFormat::II,
             &Opcode::Ldsr => InstructionFormat::II,
@@ -343,8 +348,8 @@ impl Opcode {
         }
     }
 
-    pub fn bit_string_op(&self, bit_string_op: u32) -> BitStringOp {
-        match bit_string_op {
+    pub fn bit_string_op(&self, bit_string_op: u32) -> Option<BitStringOp> {
+        Some(match bit_string_op {
             OPCODE_BITS_BIT_STRING_OP_ORBSU => BitStringOp::Orbsu,
             OPCODE_BITS_BIT_STRING_OP_ANDBSU => BitStringOp::Andbsu,
             OPCODE_BITS_BIT_STRING_OP_XORBSU => BitStringOp::Xorbsu,
@@ -353,12 +358,12 @@ impl Opcode {
             OPCODE_BITS_BIT_STRING_OP_ANDNBSU => BitStringOp::Andnbsu,
             OPCODE_BITS_BIT_STRING_OP_XORNBSU => BitStringOp::Xornbsu,
             OPCODE_BITS_BIT_STRING_OP_NOTBSU => BitStringOp::Notbsu,
-            _ => panic!("Unrecognized bit string op: {:05b}", bit_string_op)
-        }
+            _ => return None,
+        })
     }
 
-    pub fn subop(&self, subop: u16) -> SubOp {
-        match subop {
+    pub fn subop(&self, subop: u16) -> Option<SubOp> {
+        Some(match subop {
             OPCODE_BITS_SUB_OP_CMPF_S => SubOp::CmpfS,
             OPCODE_BITS_SUB_OP_CVT_WS => SubOp::CvtWs,
             OPCODE_BITS_SUB_OP_CVT_SW => SubOp::CvtSw,
@@ -371,8 +376,8 @@ impl Opcode {
             OPCODE_BITS_SUB_OP_REV => SubOp::Rev,
             OPCODE_BITS_SUB_OP_TRNC_SW => SubOp::TrncSw,
             OPCODE_BITS_SUB_OP_MPYHW => SubOp::Mpyhw,
-            _ => panic!("Unrecognized subop bits: {:06b}", subop),
-        }
+            _ => return None,
+        })
     }
 
     pub fn system_register(&self, imm5: u32) -> SystemRegister {
@@ -410,6 +415,7 @@ impl fmt::Display for Opcode {
             &Opcode::Not => "not",
             &Opcode::Setf => "setf",
             &Opcode::Cli => "cli",
+            &Opcode::Trap => "trap",
             &Opcode::Reti => "reti",
             &Opcode::Halt => "halt",
             &Opcode::Ldsr => "ldsr",
@@ -458,6 +464,7 @@ impl fmt::Display for Opcode {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InstructionFormat {
     I,
     II,
@@ -482,6 +489,7 @@ impl InstructionFormat {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BitStringOp {
     Orbsu,
     Andbsu,
@@ -509,6 +517,7 @@ impl fmt::Display for BitStringOp {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SubOp {
     CmpfS,
     CvtWs,
@@ -544,6 +553,7 @@ impl fmt::Display for SubOp {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SystemRegister {
     Eipc,
     Eipsw,
@@ -569,3 +579,361 @@ impl fmt::Display for SystemRegister {
         }
     }
 }
+
+/// The operands of a decoded instruction. Register operands are GPR indices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operands {
+    /// cli, halt, reti and sei
+    None,
+    /// Format I (jmp only uses reg1)
+    Reg { reg1: usize, reg2: usize },
+    /// Format II, sign-extended for mov, add and cmp, and unsigned otherwise
+    /// (for setf, imm5 holds the condition code)
+    Imm5 { imm5: i32, reg2: usize },
+    /// trap
+    Vector(u32),
+    /// ldsr/stsr
+    SystemRegister { system_register: SystemRegister, reg2: usize },
+    /// Bit string instructions take their operands from r26-r30
+    BitString(BitStringOp),
+    /// Format III (including nop); `target` is the absolute branch target
+    Disp9 { disp9: i32, target: u32 },
+    /// Format IV; `target` is the absolute jump target
+    Disp26 { disp26: i32, target: u32 },
+    /// Format V
+    Imm16 { imm16: u16, reg1: usize, reg2: usize },
+    /// Format VI; the address is `disp16` + reg1
+    Disp16 { disp16: i16, reg1: usize, reg2: usize },
+    /// Format VII
+    SubOp { subop: SubOp, reg1: usize, reg2: usize },
+    /// Illegal opcode, bit string op or sub-op
+    Illegal,
+}
+
+/// A single instruction decoded from memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub addr: u32,
+    pub first_halfword: u16,
+    pub second_halfword: Option<u16>,
+    /// None if the first halfword doesn't contain a legal opcode
+    pub opcode: Option<Opcode>,
+    pub operands: Operands,
+}
+
+impl DecodedInstruction {
+    /// Decodes the instruction at `addr`, fetching halfwords with `read_halfword`
+    pub fn decode<F: FnMut(u32) -> u16>(addr: u32, mut read_halfword: F) -> DecodedInstruction {
+        let first_halfword = read_halfword(addr);
+        let opcode = Opcode::from_halfword(first_halfword);
+        let second_halfword = match opcode {
+            Some(opcode) if opcode.instruction_format().has_second_halfword() => Some(read_halfword(addr.wrapping_add(2))),
+            _ => None
+        };
+
+        DecodedInstruction::from_halfwords(addr, first_halfword, second_halfword)
+    }
+
+    /// Decodes the (little-endian) instruction at the start of `bytes`, which is
+    /// located at `addr`. Returns None if `bytes` ends before the instruction does.
+    pub fn decode_bytes(addr: u32, bytes: &[u8]) -> Option<DecodedInstruction> {
+        let read_halfword = |offset: usize| {
+            if offset + 2 <= bytes.len() {
+                Some((bytes[offset] as u16) | ((bytes[offset + 1] as u16) << 8))
+            } else {
+                None
+            }
+        };
+
+        let first_halfword = read_halfword(0)?;
+        let second_halfword = match Opcode::from_halfword(first_halfword) {
+            Some(opcode) if opcode.instruction_format().has_second_halfword() => Some(read_halfword(2)?),
+            _ => None
+        };
+
+        Some(DecodedInstruction::from_halfwords(addr, first_halfword, second_halfword))
+    }
+
+    fn from_halfwords(addr: u32, first_halfword: u16, second_halfword: Option<u16>) -> DecodedInstruction {
+        let opcode = Opcode::from_halfword(first_halfword);
+
+        let reg1 = (first_halfword & 0x1f) as usize;
+        let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
+        let imm5 = (first_halfword & 0x1f) as u32;
+        let second = second_halfword.unwrap_or(0);
+
+        let operands = match opcode {
+            None => Operands::Illegal,
+            Some(opcode) => match opcode.instruction_format() {
+                InstructionFormat::I => Operands::Reg { reg1: reg1, reg2: reg2 },
+                InstructionFormat::II => match opcode {
+                    Opcode::BitString => match opcode.bit_string_op(imm5) {
+                        Some(bit_string_op) => Operands::BitString(bit_string_op),
+                        _ => Operands::Illegal
+                    },
+                    Opcode::Cli | Opcode::Reti | Opcode::Halt | Opcode::Sei => Operands::None,
+                    Opcode::Trap => Operands::Vector(imm5),
+                    Opcode::Ldsr | Opcode::Stsr => Operands::SystemRegister {
+                        system_register: opcode.system_register(imm5),
+                        reg2: reg2,
+                    },
+                    Opcode::MovImm | Opcode::AddImm5 | Opcode::CmpImm => Operands::Imm5 {
+                        imm5: ((imm5 as i32) << 27) >> 27,
+                        reg2: reg2,
+                    },
+                    _ => Operands::Imm5 { imm5: imm5 as i32, reg2: reg2 }
+                },
+                InstructionFormat::III => {
+                    let disp9 = (((first_halfword as i32) & 0x01ff) << 23) >> 23;
+                    Operands::Disp9 { disp9: disp9, target: addr.wrapping_add(disp9 as u32) & 0xfffffffe }
+                }
+                InstructionFormat::IV => {
+                    let disp26 = (((((first_halfword as i32) & 0x03ff) << 16) | (second as i32)) << 6) >> 6;
+                    Operands::Disp26 { disp26: disp26, target: addr.wrapping_add(disp26 as u32) & 0xfffffffe }
+                }
+                InstructionFormat::V => Operands::Imm16 { imm16: second, reg1: reg1, reg2: reg2 },
+                InstructionFormat::VI => Operands::Disp16 { disp16: second as i16, reg1: reg1, reg2: reg2 },
+                InstructionFormat::VII => match opcode.subop(second >> 10) {
+                    Some(subop) => Operands::SubOp { subop: subop, reg1: reg1, reg2: reg2 },
+                    _ => Operands::Illegal
+                },
+            }
+        };
+
+        DecodedInstruction {
+            addr: addr,
+            first_halfword: first_halfword,
+            second_halfword: second_halfword,
+            opcode: opcode,
+            operands: operands,
+        }
+    }
+
+    /// Returns the size of the instruction in bytes (2 or 4)
+    pub fn size(&self) -> u32 {
+        if self.second_halfword.is_some() { 4 } else { 2 }
+    }
+
+    /// Returns the address of the instruction that follows this one in memory
+    pub fn next_addr(&self) -> u32 {
+        self.addr.wrapping_add(self.size())
+    }
+
+    /// Returns the instruction's raw bytes, in memory order
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut ret = vec![self.first_halfword as u8, (self.first_halfword >> 8) as u8];
+        if let Some(second_halfword) = self.second_halfword {
+            ret.push(second_halfword as u8);
+            ret.push((second_halfword >> 8) as u8);
+        }
+        ret
+    }
+
+    /// Returns an object that displays this instruction using `formatter`
+    pub fn format<'a, F: InstructionFormatter + ?Sized>(&'a self, formatter: &'a F) -> FormattedInstruction<'a, F> {
+        FormattedInstruction {
+            instruction: self,
+            formatter: formatter,
+        }
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(&DefaultInstructionFormatter))
+    }
+}
+
+/// Customizes how decoded instructions are printed, e.g. to show labels for addresses
+pub trait InstructionFormatter {
+    fn format_reg(&self, f: &mut fmt::Formatter, reg: usize) -> fmt::Result {
+        write!(f, "r{}", reg)
+    }
+
+    /// Formats the absolute target of a branch or jump
+    fn format_target(&self, f: &mut fmt::Formatter, target: u32) -> fmt::Result {
+        write!(f, "0x{:08x}", target)
+    }
+}
+
+/// Prints plain register names and hexadecimal branch targets
+pub struct DefaultInstructionFormatter;
+
+impl InstructionFormatter for DefaultInstructionFormatter {}
+
+pub struct FormattedInstruction<'a, F: 'a + ?Sized> {
+    instruction: &'a DecodedInstruction,
+    formatter: &'a F,
+}
+
+impl<'a, F: InstructionFormatter + ?Sized> fmt::Display for FormattedInstruction<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcode = match self.instruction.opcode {
+            Some(opcode) => opcode,
+            _ => return write!(f, "??? (0x{:04x})", self.instruction.first_halfword),
+        };
+
+        match self.instruction.operands {
+            Operands::None => write!(f, "{}", opcode),
+            Operands::Reg { reg1, reg2 } => {
+                if opcode == Opcode::Jmp {
+                    write!(f, "jmp [")?;
+                    self.formatter.format_reg(f, reg1)?;
+                    write!(f, "]")
+                } else {
+                    write!(f, "{} ", opcode)?;
+                    self.formatter.format_reg(f, reg1)?;
+                    write!(f, ", ")?;
+                    self.formatter.format_reg(f, reg2)
+                }
+            }
+            Operands::Imm5 { imm5, reg2 } => {
+                write!(f, "{} {}, ", opcode, imm5)?;
+                self.formatter.format_reg(f, reg2)
+            }
+            Operands::Vector(vector) => write!(f, "{} {}", opcode, vector),
+            Operands::SystemRegister { system_register, reg2 } => {
+                if opcode == Opcode::Ldsr {
+                    write!(f, "{} ", opcode)?;
+                    self.formatter.format_reg(f, reg2)?;
+                    write!(f, ", {}", system_register)
+                } else {
+                    write!(f, "{} {}, ", opcode, system_register)?;
+                    self.formatter.format_reg(f, reg2)
+                }
+            }
+            Operands::BitString(bit_string_op) => write!(f, "{}", bit_string_op),
+            Operands::Disp9 { disp9, target } => {
+                // Printed as the raw 9-bit field, like the original CLI disassembler did
+                write!(f, "{} {:#x} (", opcode, (disp9 as u32) & 0x01ff)?;
+                self.formatter.format_target(f, target)?;
+                write!(f, ")")
+            }
+            Operands::Disp26 { disp26, target } => {
+                write!(f, "{} {} (", opcode, (disp26 as u32) & 0x03ffffff)?;
+                self.formatter.format_target(f, target)?;
+                write!(f, ")")
+            }
+            Operands::Imm16 { imm16, reg1, reg2 } => {
+                write!(f, "{} {:#x}, ", opcode, imm16)?;
+                self.formatter.format_reg(f, reg1)?;
+                write!(f, ", ")?;
+                self.formatter.format_reg(f, reg2)
+            }
+            Operands::Disp16 { disp16, reg1, reg2 } => {
+                match opcode {
+                    Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Outb | Opcode::Outh | Opcode::Outw => {
+                        write!(f, "{} ", opcode)?;
+                        self.formatter.format_reg(f, reg2)?;
+                        write!(f, ", {}[", disp16)?;
+                        self.formatter.format_reg(f, reg1)?;
+                        write!(f, "]")
+                    }
+                    _ => {
+                        write!(f, "{} {}[", opcode, disp16)?;
+                        self.formatter.format_reg(f, reg1)?;
+                        write!(f, "], ")?;
+                        self.formatter.format_reg(f, reg2)
+                    }
+                }
+            }
+            Operands::SubOp { subop, reg1, reg2 } => {
+                write!(f, "{} ", subop)?;
+                self.formatter.format_reg(f, reg1)?;
+                write!(f, ", ")?;
+                self.formatter.format_reg(f, reg2)
+            }
+            Operands::Illegal => write!(f, "??? (0x{:04x})", self.instruction.first_halfword),
+        }
+    }
+}
+
+/// Decodes consecutive instructions starting at a given address
+pub struct Disassembler<F: FnMut(u32) -> u16> {
+    addr: u32,
+    read_halfword: F,
+}
+
+impl<F: FnMut(u32) -> u16> Disassembler<F> {
+    pub fn new(addr: u32, read_halfword: F) -> Disassembler<F> {
+        Disassembler {
+            addr: addr,
+            read_halfword: read_halfword,
+        }
+    }
+
+    /// Returns the address of the next instruction to be decoded
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+}
+
+impl<F: FnMut(u32) -> u16> Iterator for Disassembler<F> {
+    type Item = DecodedInstruction;
+
+    fn next(&mut self) -> Option<DecodedInstruction> {
+        let ret = DecodedInstruction::decode(self.addr, &mut self.read_halfword);
+        self.addr = ret.next_addr();
+        Some(ret)
+    }
+}
+
+/// Decodes all instructions in `bytes`, which are located at `addr`.
+/// A trailing partial instruction is ignored.
+pub fn disassemble_bytes(addr: u32, bytes: &[u8]) -> Vec<DecodedInstruction> {
+    let mut ret = Vec::new();
+    let mut offset = 0;
+    while let Some(instruction) = DecodedInstruction::decode_bytes(addr.wrapping_add(offset as u32), &bytes[offset..]) {
+        offset += instruction.size() as usize;
+        ret.push(instruction);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(addr: u32, halfwords: &[u16]) -> String {
+        let bytes = halfwords.iter().flat_map(|&halfword| vec![halfword as u8, (halfword >> 8) as u8]).collect::<Vec<_>>();
+        format!("{}", DecodedInstruction::decode_bytes(addr, &bytes).unwrap())
+    }
+
+    fn bcond(cond: u16, disp9: u16) -> u16 {
+        (OPCODE_BITS_BCOND_PREFIX << 13) | (cond << 9) | (disp9 & 0x01ff)
+    }
+
+    #[test]
+    fn format_branches() {
+        assert_eq!(disassemble(0x07000100, &[bcond(OPCODE_BITS_BCOND_BR, 0x1fc)]), "br 0x1fc (0x070000fc)");
+        assert_eq!(disassemble(0x07000100, &[bcond(OPCODE_BITS_BCOND_BR, 0x010)]), "br 0x10 (0x07000110)");
+        assert_eq!(disassemble(0x07000100, &[bcond(OPCODE_BITS_BCOND_NOP, 0)]), "nop 0x0 (0x07000100)");
+        assert_eq!(disassemble(0x00000000, &[bcond(OPCODE_BITS_BCOND_BR, 0x1fe)]), "br 0x1fe (0xfffffffe)");
+        assert_eq!(disassemble(0xfffffff0, &[bcond(OPCODE_BITS_BCOND_BR, 0x020)]), "br 0x20 (0x00000010)");
+        assert_eq!(disassemble(0x07000100, &[bcond(OPCODE_BITS_BCOND_BR, 0x011)]), "br 0x11 (0x07000110)");
+    }
+
+    #[test]
+    fn format_jumps() {
+        let jal = OPCODE_BITS_JAL << 10;
+        assert_eq!(disassemble(0x07000100, &[jal, 0x0020]), "jal 32 (0x07000120)");
+        assert_eq!(disassemble(0x07000100, &[jal | 0x03ff, 0xfff0]), "jal 67108848 (0x070000f0)");
+        assert_eq!(disassemble(0x00000010, &[jal | 0x03ff, 0xffe0]), "jal 67108832 (0xfffffff0)");
+        assert_eq!(disassemble(0xfffffff0, &[OPCODE_BITS_JR << 10, 0x0021]), "jr 33 (0x00000010)");
+    }
+
+    #[test]
+    fn format_operands() {
+        assert_eq!(disassemble(0, &[(OPCODE_BITS_MOV_IMM << 10) | (3 << 5) | 0x1f]), "mov -1, r3");
+        assert_eq!(disassemble(0, &[(OPCODE_BITS_STW << 10) | (4 << 5) | 2, 0xfffc]), "st.w r4, -4[r2]");
+        assert_eq!(disassemble(0, &[(OPCODE_BITS_TRAP << 10) | 0x13]), "trap 19");
+    }
+
+    #[test]
+    fn illegal_opcodes_decode() {
+        let instruction = DecodedInstruction::decode_bytes(0, &[0x00, 0xd8]).unwrap();
+        assert_eq!(instruction.opcode, None);
+        assert_eq!(instruction.operands, Operands::Illegal);
+        assert_eq!(instruction.size(), 2);
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::io;
+
+use bus::*;
 use sinks::*;
 use rom::*;
 use wram::*;
@@ -7,8 +11,12 @@ use vsu::*;
 use timer::*;
 use game_pad::*;
 use link_port::*;
+use state::*;
 use mem_map::*;
 
+// Value read from addresses that nothing drives
+const OPEN_BUS_VALUE: u16 = 0;
+
 pub struct Interconnect {
     rom: Rom,
     wram: Wram,
@@ -18,6 +26,10 @@ pub struct Interconnect {
     timer: Timer,
     pub game_pad: GamePad,
     pub link_port: LinkPort,
+
+    wait_control_reg: u8,
+
+    rom_patches: HashMap<u32, u8>,
 }
 
 impl Interconnect {
@@ -31,12 +43,99 @@ impl Interconnect {
             timer: Timer::new(),
             game_pad: GamePad::new(),
             link_port: LinkPort::new(),
+
+            wait_control_reg: 0,
+
+            rom_patches: HashMap::new(),
+        }
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn vip(&self) -> &Vip {
+        &self.vip
+    }
+
+    pub fn vip_mut(&mut self) -> &mut Vip {
+        &mut self.vip
+    }
+
+    /// Makes reads of the ROM byte at `addr` (through any mirror) return
+    /// `value` instead, leaving the ROM itself untouched
+    pub fn add_rom_patch(&mut self, addr: u32, value: u8) {
+        let offset = self.rom_offset(addr);
+        self.rom_patches.insert(offset, value);
+    }
+
+    pub fn clear_rom_patches(&mut self) {
+        self.rom_patches.clear();
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.wram.save_state(writer);
+        self.sram.save_state(writer);
+        self.vip.save_state(writer);
+        self.vsu.save_state(writer);
+        self.timer.save_state(writer);
+        self.game_pad.save_state(writer);
+        self.link_port.save_state(writer);
+        writer.write_u8(self.wait_control_reg);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.wram.load_state(reader)?;
+        self.sram.load_state(reader)?;
+        self.vip.load_state(reader)?;
+        self.vsu.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.game_pad.load_state(reader)?;
+        self.link_port.load_state(reader)?;
+
+        self.wait_control_reg = reader.read_u8()?;
+
+        Ok(())
+    }
+
+    /// Returns the number of wait states the bus inserts into each access to `addr`
+    pub fn wait_states(&self, addr: u32) -> u32 {
+        match mirror_addr(addr) {
+            CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => if self.wait_control_reg & 0x01 == 0 { 2 } else { 1 },
+            // Cartridge RAM sits on the expansion bus
+            CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END |
+            CARTRIDGE_RAM_START ... CARTRIDGE_RAM_END => if self.wait_control_reg & 0x02 == 0 { 2 } else { 1 },
+            _ => 0
         }
     }
 
     pub fn read_byte(&mut self, addr: u32) -> u8 {
-        let addr = addr & 0x07ffffff;
+        let addr = mirror_addr(addr);
         match addr {
+            CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => {
+                logln!(Log::Ic, "WARNING: Read byte from Cartridge Expansion not yet implemented (addr: 0x{:08x})", addr - CARTRIDGE_EXPANSION_START);
+                0
+            }
+            CARTRIDGE_RAM_START ... CARTRIDGE_RAM_END => self.sram.read_byte(addr - CARTRIDGE_RAM_START),
+            _ => match self.peek_mapped_byte(addr) {
+                Some(value) => value,
+                _ => {
+                    logln!(Log::Ic, "WARNING: Read byte from unmapped address 0x{:08x}", addr);
+                    OPEN_BUS_VALUE as _
+                }
+            }
+        }
+    }
+
+    /// Reads a byte the way `read_byte` does, but without side effects (such as
+    /// growing SRAM) or warnings, so debuggers can inspect any address
+    pub fn peek_byte(&self, addr: u32) -> u8 {
+        self.peek_mapped_byte(mirror_addr(addr)).unwrap_or(OPEN_BUS_VALUE as _)
+    }
+
+    // Returns None for unmapped addresses; addr must already be mirrored
+    fn peek_mapped_byte(&self, addr: u32) -> Option<u8> {
+        Some(match addr {
             VIP_START ... VIP_END => self.vip.read_byte(addr - VIP_START),
             VSU_START ... VSU_END => self.vsu.read_byte(addr - VSU_START),
             LINK_CONTROL_REG => self.link_port.read_control_reg(),
@@ -48,24 +147,18 @@ impl Interconnect {
             TIMER_COUNTER_RELOAD_LOW_REG => self.timer.read_counter_reload_low_reg(),
             TIMER_COUNTER_RELOAD_HIGH_REG => self.timer.read_counter_reload_high_reg(),
             TIMER_CONTROL_REG => self.timer.read_control_reg(),
-            WAIT_CONTROL_REG => {
-                logln!(Log::Ic, "WARNING: Read byte from Wait Control Register not yet implemented");
-                0
-            }
+            WAIT_CONTROL_REG => self.wait_control_reg,
             GAME_PAD_INPUT_CONTROL_REG => self.game_pad.read_input_control_reg(),
-            CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => {
-                logln!(Log::Ic, "WARNING: Read byte from Cartridge Expansion not yet implemented (addr: 0x{:08x})", addr - CARTRIDGE_EXPANSION_START);
-                0
-            }
+            CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => 0,
             WRAM_START ... WRAM_END => self.wram.read_byte(addr - WRAM_START),
-            CARTRIDGE_RAM_START ... CARTRIDGE_RAM_END => self.sram.read_byte(addr - CARTRIDGE_RAM_START),
-            CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => self.rom.read_byte(addr - CARTRIDGE_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
-        }
+            CARTRIDGE_RAM_START ... CARTRIDGE_RAM_END => self.sram.peek_byte(addr - CARTRIDGE_RAM_START),
+            CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => self.read_rom_byte(addr - CARTRIDGE_ROM_START),
+            _ => return None
+        })
     }
 
     pub fn read_halfword(&mut self, addr: u32) -> u16 {
-        let addr = addr & 0x07ffffff;
+        let addr = mirror_addr(addr);
         let addr = addr & 0xfffffffe;
         match addr {
             VIP_START ... VIP_END => self.vip.read_halfword(addr - VIP_START),
@@ -79,10 +172,7 @@ impl Interconnect {
             TIMER_COUNTER_RELOAD_LOW_REG => self.timer.read_counter_reload_low_reg() as _,
             TIMER_COUNTER_RELOAD_HIGH_REG => self.timer.read_counter_reload_high_reg() as _,
             TIMER_CONTROL_REG => self.timer.read_control_reg() as _,
-            WAIT_CONTROL_REG => {
-                logln!(Log::Ic, "Read halfword from Wait Control Register not yet implemented");
-                0
-            }
+            WAIT_CONTROL_REG => self.wait_control_reg as _,
             GAME_PAD_INPUT_CONTROL_REG => self.game_pad.read_input_control_reg() as _,
             CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => {
                 logln!(Log::Ic, "WARNING: Read halfword from Cartridge Expansion not yet implemented (addr: 0x{:08x})", addr - CARTRIDGE_EXPANSION_START);
@@ -90,13 +180,16 @@ impl Interconnect {
             }
             WRAM_START ... WRAM_END => self.wram.read_halfword(addr - WRAM_START),
             CARTRIDGE_RAM_START ... CARTRIDGE_RAM_END => self.sram.read_halfword(addr - CARTRIDGE_RAM_START),
-            CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => self.rom.read_halfword(addr - CARTRIDGE_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => self.read_rom_halfword(addr - CARTRIDGE_ROM_START),
+            _ => {
+                logln!(Log::Ic, "WARNING: Read halfword from unmapped address 0x{:08x}", addr);
+                OPEN_BUS_VALUE
+            }
         }
     }
 
     pub fn write_byte(&mut self, addr: u32, value: u8) {
-        let addr = addr & 0x07ffffff;
+        let addr = mirror_addr(addr);
         match addr {
             VIP_START ... VIP_END => self.vip.write_byte(addr - VIP_START, value),
             VSU_START ... VSU_END => self.vsu.write_byte(addr - VSU_START, value),
@@ -119,6 +212,7 @@ impl Interconnect {
                 logln!(Log::Ic, "Wait Control Register (0x{:08x}) written: 0x{:02x}", addr, value);
                 logln!(Log::Ic, " Cartridge ROM Waits: {}", if value & 0x01 == 0 { 2 } else { 1 });
                 logln!(Log::Ic, " Cartridge Expansion Waits: {}", if value & 0x02 == 0 { 2 } else { 1 });
+                self.wait_control_reg = value & 0x03;
             }
             GAME_PAD_INPUT_CONTROL_REG => self.game_pad.write_input_control_reg(value),
             CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => {
@@ -129,12 +223,14 @@ impl Interconnect {
             CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Cartridge ROM at 0x{:08x}", addr - CARTRIDGE_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => {
+                logln!(Log::Ic, "WARNING: Write byte to unmapped address 0x{:08x} (value: 0x{:02x})", addr, value);
+            }
         }
     }
 
     pub fn write_halfword(&mut self, addr: u32, value: u16) {
-        let addr = addr & 0x07ffffff;
+        let addr = mirror_addr(addr);
         let addr = addr & 0xfffffffe;
         match addr {
             VIP_START ... VIP_END => self.vip.write_halfword(addr - VIP_START, value),
@@ -155,7 +251,8 @@ impl Interconnect {
             TIMER_COUNTER_RELOAD_HIGH_REG => self.timer.write_counter_reload_high_reg(value as _),
             TIMER_CONTROL_REG => self.timer.write_control_reg(value as _),
             WAIT_CONTROL_REG => {
-                logln!(Log::Ic, "WARNING: Write halfword to Wait Control Register not yet implemented (value: 0x{:04x})", value);
+                logln!(Log::Ic, "Wait Control Register (0x{:08x}) written: 0x{:04x}", addr, value);
+                self.wait_control_reg = (value as u8) & 0x03;
             }
             GAME_PAD_INPUT_CONTROL_REG => self.game_pad.write_input_control_reg(value as _),
             CARTRIDGE_EXPANSION_START ... CARTRIDGE_EXPANSION_END => {
@@ -166,7 +263,9 @@ impl Interconnect {
             CARTRIDGE_ROM_START ... CARTRIDGE_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Cartridge ROM at 0x{:08x}", addr - CARTRIDGE_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => {
+                logln!(Log::Ic, "WARNING: Write halfword to unmapped address 0x{:08x} (value: 0x{:04x})", addr, value);
+            }
         }
     }
 
@@ -185,4 +284,58 @@ impl Interconnect {
 
         interrupt
     }
+
+    fn read_rom_byte(&self, addr: u32) -> u8 {
+        if !self.rom_patches.is_empty() {
+            if let Some(&value) = self.rom_patches.get(&self.rom_offset(addr)) {
+                return value;
+            }
+        }
+        self.rom.read_byte(addr)
+    }
+
+    fn read_rom_halfword(&self, addr: u32) -> u16 {
+        if self.rom_patches.is_empty() {
+            return self.rom.read_halfword(addr);
+        }
+        (self.read_rom_byte(addr) as u16) | ((self.read_rom_byte(addr + 1) as u16) << 8)
+    }
+
+    fn rom_offset(&self, addr: u32) -> u32 {
+        addr & ((self.rom.size() - 1) as u32)
+    }
+}
+
+impl Bus for Interconnect {
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        Interconnect::read_byte(self, addr)
+    }
+
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        Interconnect::read_halfword(self, addr)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        Interconnect::write_byte(self, addr, value);
+    }
+
+    fn write_halfword(&mut self, addr: u32, value: u16) {
+        Interconnect::write_halfword(self, addr, value);
+    }
+
+    fn peek_byte(&self, addr: u32) -> u8 {
+        Interconnect::peek_byte(self, addr)
+    }
+
+    fn wait_states(&self, addr: u32) -> u32 {
+        Interconnect::wait_states(self, addr)
+    }
+}
+
+fn mirror_addr(addr: u32) -> u32 {
+    let addr = addr & 0x07ffffff;
+    match addr {
+        HARDWARE_REGS_START ... HARDWARE_REGS_END => HARDWARE_REGS_START | (addr & HARDWARE_REGS_MIRROR_MASK),
+        _ => addr
+    }
 }
@@ -1,16 +1,26 @@
+// Keep the 2015-edition idioms used throughout the crate
+#![allow(ellipsis_inclusive_range_patterns, bare_trait_objects)]
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes, clippy::new_without_default)]
+#![allow(clippy::match_ref_pats, clippy::needless_return, clippy::write_literal, clippy::identity_op)]
+#![allow(clippy::ptr_offset_with_cast, clippy::collapsible_match, clippy::manual_range_contains)]
+#![allow(clippy::nonminimal_bool, clippy::precedence, clippy::too_many_arguments, clippy::manual_checked_ops)]
+
 extern crate encoding;
 
 #[macro_use]
 mod logging;
-mod mem_map;
 
+pub mod bus;
 pub mod game_pad;
+pub mod hooks;
 pub mod instruction;
 pub mod interconnect;
 pub mod link_port;
+pub mod mem_map;
 pub mod rom;
 pub mod sinks;
 pub mod sram;
+pub mod state;
 pub mod time_source;
 pub mod timer;
 pub mod v810;
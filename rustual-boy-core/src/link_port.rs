@@ -1,3 +1,7 @@
+use std::io;
+
+use state::*;
+
 pub struct LinkPort {
     transmit_data_reg: u8,
     receive_data_reg: u8,
@@ -71,4 +75,22 @@ impl LinkPort {
 
         ret
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.transmit_data_reg);
+        writer.write_u8(self.receive_data_reg);
+
+        writer.write_bool(self.is_transfer_active);
+        writer.write_u32(self.transfer_bit_index);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.transmit_data_reg = reader.read_u8()?;
+        self.receive_data_reg = reader.read_u8()?;
+
+        self.is_transfer_active = reader.read_bool()?;
+        self.transfer_bit_index = reader.read_u32()?;
+
+        Ok(())
+    }
 }
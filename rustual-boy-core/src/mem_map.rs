@@ -6,6 +6,12 @@ pub const VSU_START: u32 = 0x01000000;
 pub const VSU_LENGTH: u32 = 0x01000000;
 pub const VSU_END: u32 = VSU_START + VSU_LENGTH - 1;
 
+// The hardware registers are mirrored every 64 bytes throughout this region
+pub const HARDWARE_REGS_START: u32 = 0x02000000;
+pub const HARDWARE_REGS_LENGTH: u32 = 0x01000000;
+pub const HARDWARE_REGS_END: u32 = HARDWARE_REGS_START + HARDWARE_REGS_LENGTH - 1;
+pub const HARDWARE_REGS_MIRROR_MASK: u32 = 0x0000003f;
+
 pub const LINK_CONTROL_REG: u32 = 0x02000000;
 pub const AUX_LINK_REG: u32 = 0x02000004;
 pub const LINK_TRANSMIT_DATA_REG: u32 = 0x02000008;
@@ -2,6 +2,8 @@ use std::io::{self, Read, Write, Error, ErrorKind};
 use std::fs::File;
 use std::path::Path;
 
+use state::*;
+
 pub const MIN_SRAM_SIZE: usize = 1024;
 pub const MAX_SRAM_SIZE: usize = 16 * 1024 * 1024;
 
@@ -55,6 +57,40 @@ impl Sram {
         self.size
     }
 
+    /// Returns the used part of SRAM, without growing it
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.size]
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        // Only the used part of SRAM is saved; the rest is still in its initial state
+        writer.write_u32(self.size as _);
+        writer.write_bytes(&self.bytes[..self.size]);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        let size = reader.read_u32()? as usize;
+        if size != 0 && (size < MIN_SRAM_SIZE || size > MAX_SRAM_SIZE || !size.is_power_of_two()) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid SRAM size in save state"));
+        }
+
+        if size > self.bytes.len() {
+            let mut bytes = vec![0xff; MAX_SRAM_SIZE];
+            bytes[..self.bytes.len()].copy_from_slice(&self.bytes);
+            self.bytes = bytes.into_boxed_slice();
+            self.bytes_ptr = self.bytes.as_mut_ptr();
+        }
+        reader.read_bytes(&mut self.bytes[..size])?;
+        if size < self.size {
+            for byte in self.bytes[size..self.size].iter_mut() {
+                *byte = 0xff;
+            }
+        }
+        self.size = size;
+
+        Ok(())
+    }
+
     pub fn read_byte(&mut self, addr: u32) -> u8 {
         let addr = self.mask_addr(addr);
         unsafe {
@@ -69,6 +105,12 @@ impl Sram {
         }
     }
 
+    /// Reads a byte without growing SRAM, for debuggers
+    pub fn peek_byte(&self, addr: u32) -> u8 {
+        let addr = addr & ((self.bytes.len() - 1) as u32);
+        self.bytes[addr as usize]
+    }
+
     pub fn read_halfword(&mut self, addr: u32) -> u16 {
         let addr = addr & 0xfffffffe;
         let addr = self.mask_addr(addr);
@@ -88,9 +130,9 @@ impl Sram {
     }
 
     fn mask_addr(&mut self, addr: u32) -> u32 {
-        let mask = (MAX_SRAM_SIZE - 1) as u32;
+        let mask = (self.bytes.len() - 1) as u32;
         let addr = addr & mask;
-        if addr >= self.size as u32 {
+        while addr >= self.size as u32 {
             self.size = match self.size {
                 0 => MIN_SRAM_SIZE,
                 _ => self.size * 2,
@@ -99,3 +141,77 @@ impl Sram {
         addr
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(sram: &Sram) -> Sram {
+        let mut writer = StateWriter::new();
+        sram.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+
+        let mut ret = Sram::new();
+        ret.load_state(&mut StateReader::new(&bytes).unwrap()).unwrap();
+        ret
+    }
+
+    #[test]
+    fn grows_to_cover_written_address() {
+        let mut sram = Sram::new();
+        assert_eq!(sram.size(), 0);
+
+        sram.write_byte(0x1234, 0x56);
+        assert_eq!(sram.size(), 0x2000);
+        assert_eq!(sram.read_byte(0x1234), 0x56);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut sram = Sram::new();
+        sram.write_halfword(0x0402, 0xbeef);
+
+        let mut loaded = round_trip(&sram);
+        assert_eq!(loaded.size(), sram.size());
+        assert_eq!(loaded.read_halfword(0x0402), 0xbeef);
+        assert_eq!(loaded.read_byte(0x0000), 0xff);
+    }
+
+    #[test]
+    fn load_state_clears_bytes_past_new_size() {
+        let empty = Sram::new();
+
+        let mut sram = Sram::new();
+        sram.write_byte(0x0010, 0x00);
+        let mut writer = StateWriter::new();
+        empty.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+        sram.load_state(&mut StateReader::new(&bytes).unwrap()).unwrap();
+
+        assert_eq!(sram.size(), 0);
+        assert_eq!(sram.read_byte(0x0010), 0xff);
+    }
+
+    #[test]
+    fn load_state_rejects_invalid_sizes() {
+        for &size in &[1, 512, 3 * 1024, 2 * MAX_SRAM_SIZE as u32] {
+            let mut writer = StateWriter::new();
+            writer.write_u32(size);
+            let bytes = writer.into_bytes();
+
+            let mut sram = Sram::new();
+            assert!(sram.load_state(&mut StateReader::new(&bytes).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn load_state_rejects_truncated_data() {
+        let mut writer = StateWriter::new();
+        writer.write_u32(MIN_SRAM_SIZE as _);
+        writer.write_bytes(&[0; 16]);
+        let bytes = writer.into_bytes();
+
+        let mut sram = Sram::new();
+        assert!(sram.load_state(&mut StateReader::new(&bytes).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Error, ErrorKind};
+
+pub const SAVE_STATE_MAGIC: &'static [u8; 4] = b"RBSS";
+pub const SAVE_STATE_VERSION: u32 = 1;
+
+/// Serializes machine state into the save state format. All values are
+/// written little-endian, so the output is stable across hosts.
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut ret = StateWriter {
+            bytes: Vec::new(),
+        };
+
+        ret.write_bytes(SAVE_STATE_MAGIC);
+        ret.write_u32(SAVE_STATE_VERSION);
+
+        ret
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(if value { 1 } else { 0 });
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_u8(value as _);
+        self.write_u8((value >> 8) as _);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_u16(value as _);
+        self.write_u16((value >> 16) as _);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_u32(value as _);
+        self.write_u32((value >> 32) as _);
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Deserializes machine state written by a `StateWriter`. The header is
+/// validated on construction, and `version` can be used by components to
+/// read states written by older versions of the format.
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+
+    version: u32,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> io::Result<StateReader<'a>> {
+        let mut ret = StateReader {
+            bytes: bytes,
+            pos: 0,
+
+            version: 0,
+        };
+
+        let mut magic = [0; 4];
+        ret.read_bytes(&mut magic)?;
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a save state"));
+        }
+
+        let version = ret.read_u32()?;
+        if version == 0 || version > SAVE_STATE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported save state version: {}", version)));
+        }
+        ret.version = version;
+
+        Ok(ret)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn read_bool(&mut self) -> io::Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::new(ErrorKind::InvalidData, "Invalid bool value in save state")),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        if self.pos >= self.bytes.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected end of save state"));
+        }
+
+        let ret = self.bytes[self.pos];
+        self.pos += 1;
+
+        Ok(ret)
+    }
+
+    pub fn read_u16(&mut self) -> io::Result<u16> {
+        let low = self.read_u8()? as u16;
+        let high = self.read_u8()? as u16;
+        Ok(low | (high << 8))
+    }
+
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        let low = self.read_u16()? as u32;
+        let high = self.read_u16()? as u32;
+        Ok(low | (high << 16))
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        let low = self.read_u32()? as u64;
+        let high = self.read_u32()? as u64;
+        Ok(low | (high << 32))
+    }
+
+    pub fn read_bytes(&mut self, value: &mut [u8]) -> io::Result<()> {
+        if self.bytes.len() - self.pos < value.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected end of save state"));
+        }
+
+        value.copy_from_slice(&self.bytes[self.pos..self.pos + value.len()]);
+        self.pos += value.len();
+
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+use std::io::{self, Error, ErrorKind};
+
+use state::*;
+
 // 20mhz / (1s / 100us) = 2000
 const LARGE_INTERVAL_PERIOD: u32 = 2000;
 
@@ -107,4 +111,37 @@ impl Timer {
 
         self.zero_interrupt
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(match self.interval {
+            Interval::Large => 0,
+            Interval::Small => 1,
+        });
+        writer.write_bool(self.zero_interrupt_enable);
+        writer.write_bool(self.zero_status);
+        writer.write_bool(self.enable);
+        writer.write_u16(self.reload);
+        writer.write_u16(self.counter);
+
+        writer.write_u32(self.tick_counter);
+        writer.write_bool(self.zero_interrupt);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.interval = match reader.read_u8()? {
+            0 => Interval::Large,
+            1 => Interval::Small,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid timer interval in save state")),
+        };
+        self.zero_interrupt_enable = reader.read_bool()?;
+        self.zero_status = reader.read_bool()?;
+        self.enable = reader.read_bool()?;
+        self.reload = reader.read_u16()?;
+        self.counter = reader.read_u16()?;
+
+        self.tick_counter = reader.read_u32()?;
+        self.zero_interrupt = reader.read_bool()?;
+
+        Ok(())
+    }
 }
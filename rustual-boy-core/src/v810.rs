@@ -1,19 +1,39 @@
+use bus::*;
+use hooks::*;
 use instruction::*;
-use interconnect::*;
+use state::*;
 
-use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io;
+use std::num::FpCategory;
+
+const EXCEPTION_CODE_FP_RESERVED_OPERAND: u16 = 0xff60;
+const EXCEPTION_CODE_FP_OVERFLOW: u16 = 0xff64;
+const EXCEPTION_CODE_FP_ZERO_DIVISION: u16 = 0xff68;
+const EXCEPTION_CODE_FP_INVALID_OPERATION: u16 = 0xff70;
+const EXCEPTION_CODE_ILLEGAL_OPCODE: u16 = 0xff90;
+const EXCEPTION_CODE_TRAP_BASE: u16 = 0xffa0;
+
+const CACHE_NUM_ENTRIES: usize = 128;
+
+// Dumped cache contents are laid out as all of the data words, followed by a
+// word per entry holding the tag and subblock valid bits
+const CACHE_DUMP_TAGS_OFFSET: u32 = (CACHE_NUM_ENTRIES as u32) * 8;
+const CACHE_DUMP_TAG_MASK: u32 = 0x003fffff;
+const CACHE_DUMP_SUBBLOCK_VALID_SHIFT: u32 = 22;
 
 #[derive(Copy, Clone, Default)]
 pub struct CacheEntry {
     pub tag: u32,
     pub base_addr: u32,
     pub subblock_valid: [bool; 2],
+    pub subblock_data: [u32; 2],
 }
 
 impl fmt::Display for CacheEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "base_addr: 0x{:08x}, tag: 0x{:08x}, sb1 valid: {:5}, sb2 valid: {:5}", self.base_addr, self.tag, self.subblock_valid[0], self.subblock_valid[1])
+        write!(f, "base_addr: 0x{:08x}, tag: 0x{:08x}, sb1 valid: {:5}, sb2 valid: {:5}, sb1 data: 0x{:08x}, sb2 data: 0x{:08x}", self.base_addr, self.tag, self.subblock_valid[0], self.subblock_valid[1], self.subblock_data[0], self.subblock_data[1])
     }
 }
 
@@ -27,7 +47,7 @@ pub struct Cache {
     hits: u64,
     misses: u64,
     is_enabled: bool,
-    entries: Box<[CacheEntry; 128]>,
+    entries: Box<[CacheEntry; CACHE_NUM_ENTRIES]>,
 }
 
 impl Cache {
@@ -36,27 +56,22 @@ impl Cache {
             hits: 0,
             misses: 0,
             is_enabled: false,
-            entries: Box::new([CacheEntry::default(); 128]),
+            entries: Box::new([CacheEntry::default(); CACHE_NUM_ENTRIES]),
         }
     }
 
-    pub fn clear_entries(&mut self, start: usize, mut count: usize) {
-        if start >= 128 {
+    /// Invalidates `count` entries starting at `start`. Tags and data are left
+    /// intact, as on hardware; only the subblock valid bits are cleared.
+    pub fn clear_entries(&mut self, start: usize, count: usize) {
+        if start >= CACHE_NUM_ENTRIES {
             return;
         }
 
-        if count > 128 {
-            count = 128;
-        }
+        let end = if count > CACHE_NUM_ENTRIES - start { CACHE_NUM_ENTRIES } else { start + count };
 
-        if start + count > 128 {
-            count = 128 - start;
+        for entry in self.entries[start..end].iter_mut() {
+            entry.subblock_valid = [false; 2];
         }
-
-        for offset in 0..count {
-            self.entries[start + offset].tag = 0;
-            self.entries[start + offset].subblock_valid = [false; 2];
-         }
     }
 
     pub fn set_is_enabled(&mut self, is_enabled: bool) {
@@ -67,34 +82,84 @@ impl Cache {
         return self.is_enabled;
     }
 
-    pub fn read_halfword(&mut self, interconnect: &mut Interconnect, addr: u32) -> (u16, CacheResult) {
-        let halfword = interconnect.read_halfword(addr);
-
+    /// Fetches an instruction halfword. On a miss, the whole 4-byte subblock
+    /// containing `addr` is filled from memory; hits are served from the cache
+    /// without touching the bus.
+    pub fn read_halfword<B: Bus>(&mut self, bus: &mut B, addr: u32) -> (u16, CacheResult) {
         if !self.is_enabled {
-            return (halfword, CacheResult::Disabled);
+            return (bus.read_halfword(addr), CacheResult::Disabled);
         }
 
-        let byte_offset = (addr & 0x07) as usize;
         let entry = ((addr >> 3) & 0x7f) as usize;
         let tag = addr >> 10;
-        let subblock = if byte_offset >= 4 { 1 } else { 0 };
+        let subblock = ((addr >> 2) & 0x01) as usize;
+        let shift = (addr & 0x02) * 8;
 
-        if self.entries[entry].tag == tag {
-            if self.entries[entry].subblock_valid[subblock] {
-                self.hits += 1;
-                return (halfword, CacheResult::Hit);
-            }
-            self.entries[entry].subblock_valid[subblock] = true;
-            self.misses += 1;
-            return (halfword, CacheResult::Miss);
-        } else {
+        if self.entries[entry].tag == tag && self.entries[entry].subblock_valid[subblock] {
+            self.hits += 1;
+            let halfword = (self.entries[entry].subblock_data[subblock] >> shift) as u16;
+            return (halfword, CacheResult::Hit);
+        }
+
+        if self.entries[entry].tag != tag {
             self.entries[entry].tag = tag;
             self.entries[entry].subblock_valid = [false; 2];
-            self.entries[entry].subblock_valid[subblock] = true;
             self.entries[entry].base_addr = addr & 0xfffffff8;
-            self.misses += 1;
-            return (halfword, CacheResult::Miss);
         }
+
+        let data = bus.read_word(addr & 0xfffffffc);
+        self.entries[entry].subblock_data[subblock] = data;
+        self.entries[entry].subblock_valid[subblock] = true;
+        self.misses += 1;
+        ((data >> shift) as u16, CacheResult::Miss)
+    }
+
+    /// Writes the cache contents to memory at `addr`, returning the number of
+    /// cycles taken by the transfer
+    pub fn dump<B: Bus>(&self, bus: &mut B, addr: u32) -> u32 {
+        for (i, entry) in self.entries.iter().enumerate() {
+            let data_addr = addr.wrapping_add((i as u32) * 8);
+            bus.write_word(data_addr, entry.subblock_data[0]);
+            bus.write_word(data_addr.wrapping_add(4), entry.subblock_data[1]);
+        }
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            let tag_addr = addr.wrapping_add(CACHE_DUMP_TAGS_OFFSET + (i as u32) * 4);
+            let value =
+                (entry.tag & CACHE_DUMP_TAG_MASK) |
+                ((entry.subblock_valid[0] as u32) << CACHE_DUMP_SUBBLOCK_VALID_SHIFT) |
+                ((entry.subblock_valid[1] as u32) << (CACHE_DUMP_SUBBLOCK_VALID_SHIFT + 1));
+            bus.write_word(tag_addr, value);
+        }
+
+        Cache::transfer_cycles(bus, addr)
+    }
+
+    /// Loads the cache contents from memory at `addr` (in the layout written by
+    /// `dump`), returning the number of cycles taken by the transfer
+    pub fn restore<B: Bus>(&mut self, bus: &mut B, addr: u32) -> u32 {
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            let data_addr = addr.wrapping_add((i as u32) * 8);
+            entry.subblock_data[0] = bus.read_word(data_addr);
+            entry.subblock_data[1] = bus.read_word(data_addr.wrapping_add(4));
+        }
+
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            let tag_addr = addr.wrapping_add(CACHE_DUMP_TAGS_OFFSET + (i as u32) * 4);
+            let value = bus.read_word(tag_addr);
+            entry.tag = value & CACHE_DUMP_TAG_MASK;
+            entry.base_addr = (entry.tag << 10) | ((i as u32) << 3);
+            entry.subblock_valid[0] = (value >> CACHE_DUMP_SUBBLOCK_VALID_SHIFT) & 0x01 != 0;
+            entry.subblock_valid[1] = (value >> (CACHE_DUMP_SUBBLOCK_VALID_SHIFT + 1)) & 0x01 != 0;
+        }
+
+        Cache::transfer_cycles(bus, addr)
+    }
+
+    fn transfer_cycles<B: Bus>(bus: &B, addr: u32) -> u32 {
+        // Each of the 3 words per entry is moved as two halfword accesses
+        let num_accesses = (CACHE_NUM_ENTRIES as u32) * 3 * 2;
+        num_accesses * (1 + bus.wait_states(addr))
     }
 
     pub fn entry(&self, entry: usize) -> CacheEntry {
@@ -104,6 +169,80 @@ impl Cache {
     pub fn stats(&self) -> (u64, u64) {
         return (self.hits, self.misses);
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.hits);
+        writer.write_u64(self.misses);
+        writer.write_bool(self.is_enabled);
+        for entry in self.entries.iter() {
+            writer.write_u32(entry.tag);
+            writer.write_u32(entry.base_addr);
+            writer.write_bool(entry.subblock_valid[0]);
+            writer.write_bool(entry.subblock_valid[1]);
+            writer.write_u32(entry.subblock_data[0]);
+            writer.write_u32(entry.subblock_data[1]);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.hits = reader.read_u64()?;
+        self.misses = reader.read_u64()?;
+        self.is_enabled = reader.read_bool()?;
+        for entry in self.entries.iter_mut() {
+            entry.tag = reader.read_u32()?;
+            entry.base_addr = reader.read_u32()?;
+            entry.subblock_valid[0] = reader.read_bool()?;
+            entry.subblock_valid[1] = reader.read_bool()?;
+            entry.subblock_data[0] = reader.read_u32()?;
+            entry.subblock_data[1] = reader.read_u32()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The state recorded when the CPU hits an exception while already handling
+/// a duplexed exception. The CPU stops executing until it's reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FatalException {
+    pub exception_code: u16,
+    pub psw: u32,
+    pub pc: u32,
+}
+
+impl fmt::Display for FatalException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fatal exception (code: 0x{:04x}, psw: 0x{:08x}, pc: 0x{:08x})", self.exception_code, self.psw, self.pc)
+    }
+}
+
+// Code that never returns (eg. jal used as a plain jump) would otherwise grow
+//  the shadow call stack forever
+const MAX_CALL_STACK_DEPTH: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallFrameKind {
+    Call,
+    Exception(u16),
+}
+
+/// An entry in the shadow call stack, pushed by `jal` and exception entry
+/// and popped by the matching `jmp [r31]` or `reti`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: CallFrameKind,
+    /// The address of the `jal`, or of the instruction the exception interrupted
+    pub call_addr: u32,
+    pub target_addr: u32,
+    pub return_addr: u32,
+}
+
+// Tracks the previous instruction's memory access for pipeline timing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MemoryOp {
+    None,
+    Load,
+    Store,
 }
 
 pub struct V810 {
@@ -114,7 +253,7 @@ pub struct V810 {
 
     reg_eipc: u32,
     reg_eipsw: u32,
-    reg_ecr: u16,
+    reg_ecr: u32,
     reg_fepc: u32,
     reg_fepsw: u32,
 
@@ -135,10 +274,16 @@ pub struct V810 {
     psw_interrupt_mask_level: u32,
 
     is_halted: bool,
+    fatal_exception: Option<FatalException>,
+
+    previous_memory_op: MemoryOp,
 
     pub cache: Cache,
 
-    pub watchpoints: HashSet<u32>,
+    pub hooks: MemoryHooks,
+    triggered_hook: Option<(HookId, MemoryAccess)>,
+
+    call_stack: VecDeque<CallFrame>,
 }
 
 impl V810 {
@@ -176,10 +321,16 @@ impl V810 {
             psw_interrupt_mask_level: 0,
 
             is_halted: false,
+            fatal_exception: None,
+
+            previous_memory_op: MemoryOp::None,
 
             cache: Cache::new(),
 
-            watchpoints: HashSet::new(),
+            hooks: MemoryHooks::new(),
+            triggered_hook: None,
+
+            call_stack: VecDeque::new(),
         }
     }
 
@@ -188,13 +339,20 @@ impl V810 {
     }
 
     pub fn reg_gpr(&self, index: usize) -> u32 {
+        assert!(index < 32, "Invalid general purpose register: r{}", index);
         unsafe {
             let reg_ptr = self.reg_gpr_ptr.offset(index as _);
             *reg_ptr
         }
     }
 
-    fn set_reg_gpr(&mut self, index: usize, value: u32) {
+    pub fn set_reg_pc(&mut self, value: u32) {
+        self.reg_pc = value & 0xfffffffe;
+    }
+
+    /// Writes to r0 are ignored, just like they are for instructions
+    pub fn set_reg_gpr(&mut self, index: usize, value: u32) {
+        assert!(index < 32, "Invalid general purpose register: r{}", index);
         if index != 0 {
             unsafe {
                 let reg_ptr = self.reg_gpr_ptr.offset(index as _);
@@ -230,10 +388,62 @@ impl V810 {
         self.reg_eipsw
     }
 
-    pub fn reg_ecr(&self) -> u16 {
+    pub fn set_reg_eipc(&mut self, value: u32) {
+        self.reg_eipc = value & 0xfffffffe;
+    }
+
+    pub fn set_reg_eipsw(&mut self, value: u32) {
+        self.reg_eipsw = value & 0x000ff3ff;
+    }
+
+    pub fn reg_fepc(&self) -> u32 {
+        self.reg_fepc
+    }
+
+    pub fn set_reg_fepc(&mut self, value: u32) {
+        self.reg_fepc = value & 0xfffffffe;
+    }
+
+    pub fn reg_fepsw(&self) -> u32 {
+        self.reg_fepsw
+    }
+
+    pub fn set_reg_fepsw(&mut self, value: u32) {
+        self.reg_fepsw = value & 0x000ff3ff;
+    }
+
+    /// Returns the exception cause register: the duplexed exception code in
+    /// the high halfword and the exception/interrupt code in the low halfword
+    pub fn reg_ecr(&self) -> u32 {
         self.reg_ecr
     }
 
+    pub fn set_reg_ecr(&mut self, value: u32) {
+        self.reg_ecr = value;
+    }
+
+    /// Returns the fatal exception that stopped the CPU, if any
+    pub fn fatal_exception(&self) -> Option<FatalException> {
+        self.fatal_exception
+    }
+
+    /// Returns the shadow call stack, innermost frame last. It's rebuilt from
+    /// calls and exceptions seen while running, so it starts out empty after
+    /// loading a save state.
+    pub fn call_stack(&self) -> &VecDeque<CallFrame> {
+        &self.call_stack
+    }
+
+    /// Returns true if the CPU is stopped by `halt`, waiting for an interrupt
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    /// Returns the hook that asked to break during the last step, and the access that triggered it
+    pub fn triggered_hook(&self) -> Option<(HookId, MemoryAccess)> {
+        self.triggered_hook
+    }
+
     pub fn reg_psw(&self) -> u32 {
         (if self.psw_zero { 1 << 0 } else { 0 }) |
         (if self.psw_sign { 1 << 1 } else { 0 }) |
@@ -270,18 +480,101 @@ impl V810 {
         self.psw_interrupt_mask_level = (value >> 16) & 0x0f;
     }
 
-    pub fn step(&mut self, interconnect: &mut Interconnect) -> (u32, bool) {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.reg_pc);
+
+        for index in 0..32 {
+            writer.write_u32(self.reg_gpr(index));
+        }
+
+        writer.write_u32(self.reg_eipc);
+        writer.write_u32(self.reg_eipsw);
+        writer.write_u32(self.reg_ecr);
+        writer.write_u32(self.reg_fepc);
+        writer.write_u32(self.reg_fepsw);
+
+        writer.write_u32(self.reg_psw());
+
+        writer.write_bool(self.is_halted);
+
+        self.cache.save_state(writer);
+
+        match self.fatal_exception {
+            Some(ref fatal_exception) => {
+                writer.write_bool(true);
+                writer.write_u16(fatal_exception.exception_code);
+                writer.write_u32(fatal_exception.psw);
+                writer.write_u32(fatal_exception.pc);
+            }
+            _ => writer.write_bool(false),
+        }
+
+        writer.write_u8(match self.previous_memory_op {
+            MemoryOp::None => 0,
+            MemoryOp::Load => 1,
+            MemoryOp::Store => 2,
+        });
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.reg_pc = reader.read_u32()?;
+
+        for index in 0..32 {
+            let value = reader.read_u32()?;
+            self.set_reg_gpr(index, value);
+        }
+
+        self.reg_eipc = reader.read_u32()?;
+        self.reg_eipsw = reader.read_u32()?;
+        self.reg_ecr = reader.read_u32()?;
+        self.reg_fepc = reader.read_u32()?;
+        self.reg_fepsw = reader.read_u32()?;
+
+        let psw = reader.read_u32()?;
+        self.set_reg_psw(psw);
+
+        self.is_halted = reader.read_bool()?;
+
+        self.cache.load_state(reader)?;
+
+        self.fatal_exception = None;
+        if reader.read_bool()? {
+            self.fatal_exception = Some(FatalException {
+                exception_code: reader.read_u16()?,
+                psw: reader.read_u32()?,
+                pc: reader.read_u32()?,
+            });
+        }
+
+        self.previous_memory_op = match reader.read_u8()? {
+            0 => MemoryOp::None,
+            1 => MemoryOp::Load,
+            2 => MemoryOp::Store,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid memory op in save state")),
+        };
+
+        self.call_stack.clear();
+
+        Ok(())
+    }
+
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> (u32, bool) {
         if self.is_halted {
             return (1, false);
         }
 
         let original_pc = self.reg_pc;
 
-        let (first_halfword, _) = self.cache.read_halfword(interconnect, original_pc);
+        self.triggered_hook = None;
+
+        let mut fetch_cycles = 0;
+        let first_halfword = self.fetch_halfword(bus, original_pc, &mut fetch_cycles);
         let mut next_pc = original_pc.wrapping_add(2);
 
         let mut num_cycles = 1;
-        let mut trigger_watchpoint = false;
+        let mut memory_op = MemoryOp::None;
+        let mut trigger_watchpoint = self.check_execute_hooks(bus, original_pc, first_halfword);
+        let mut exception = None;
 
         if first_halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
             let cond_bits = (first_halfword >> 9) & 0x0f;
@@ -302,7 +595,7 @@ impl V810 {
                 OPCODE_BITS_BCOND_NOP => false,
                 OPCODE_BITS_BCOND_BGE => !(self.psw_sign != self.psw_overflow),
                 OPCODE_BITS_BCOND_BGT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                _ => panic!("Unrecognized cond bits: {:04b} (halfword: 0b{:016b})", cond_bits, first_halfword)
+                _ => unreachable!()
             };
 
             if take_branch {
@@ -330,7 +623,7 @@ impl V810 {
 
             macro_rules! format_iv {
                 ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
+                    let second_halfword = self.fetch_halfword(bus, next_pc, &mut fetch_cycles);
                     next_pc = next_pc.wrapping_add(2);
 
                     let disp = ((((((first_halfword as i16) << 6) >> 6) as u32) << 16) | (second_halfword as u32)) & 0xfffffffe;
@@ -341,7 +634,7 @@ impl V810 {
 
             macro_rules! format_v {
                 ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
+                    let second_halfword = self.fetch_halfword(bus, next_pc, &mut fetch_cycles);
                     next_pc = next_pc.wrapping_add(2);
 
                     let reg1 = (first_halfword & 0x1f) as usize;
@@ -353,7 +646,7 @@ impl V810 {
 
             macro_rules! format_vi {
                 ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
+                    let second_halfword = self.fetch_halfword(bus, next_pc, &mut fetch_cycles);
                     next_pc = next_pc.wrapping_add(2);
 
                     let reg1 = (first_halfword & 0x1f) as usize;
@@ -399,6 +692,9 @@ impl V810 {
                 }),
                 OPCODE_BITS_JMP => format_i!(|reg1, _| {
                     next_pc = self.reg_gpr(reg1) & 0xfffffffe;
+                    if reg1 == 31 {
+                        self.pop_call_frames(next_pc);
+                    }
                     num_cycles = 3;
                 }),
                 OPCODE_BITS_SAR_REG => format_i!(|reg1, reg2| {
@@ -418,6 +714,7 @@ impl V810 {
                     self.set_reg_gpr(reg2, res_low);
                     self.set_zero_sign_flags(res_low);
                     self.psw_overflow = overflow;
+                    // The multiplier and divider take the same time for any operands
                     num_cycles = 13;
                 }),
                 OPCODE_BITS_DIV => format_i!(|reg1, reg2| {
@@ -527,7 +824,7 @@ impl V810 {
                         OPCODE_CONDITION_BITS_F => false,
                         OPCODE_CONDITION_BITS_GE => !(self.psw_sign != self.psw_overflow),
                         OPCODE_CONDITION_BITS_GT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                        _ => panic!("Unrecognized condition: {}", imm5),
+                        _ => unreachable!(),
                     };
                     self.set_reg_gpr(reg2, if set { 1 } else { 0 });
                 }),
@@ -559,6 +856,12 @@ impl V810 {
                     let res = self.sar_and_set_flags(lhs, rhs);
                     self.set_reg_gpr(reg2, res);
                 }),
+                OPCODE_BITS_TRAP => format_ii!(|imm5, _| {
+                    // Unlike other exceptions, traps return to the following instruction
+                    self.reg_pc = next_pc;
+                    next_pc = self.enter_exception(EXCEPTION_CODE_TRAP_BASE + (imm5 as u16));
+                    num_cycles = 15;
+                }),
                 OPCODE_BITS_RETI => format_ii!(|_, _| {
                     next_pc = self.return_from_exception();
                     num_cycles = 10;
@@ -583,11 +886,10 @@ impl V810 {
                             self.reg_fepsw = value & 0x000ff3ff;
                         }
                         OPCODE_SYSTEM_REGISTER_ID_ECR => {
-                            self.reg_ecr = value as _;
+                            self.reg_ecr = value;
                         }
                         OPCODE_SYSTEM_REGISTER_ID_PSW => self.set_reg_psw(value),
                         OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: ldsr chcw not fully implemented (value: 0x{:08x})", value);
                             let enable = (value >> 1) & 0x01 == 1;
                             if enable != self.cache.is_enabled() {
                                 logln!(Log::Cpu, "ldsr chcw cache enable changed to {}", enable);
@@ -595,16 +897,18 @@ impl V810 {
                             }
 
                             if value & 0x01 == 1 {
-                                let entry_count = ((value >> 8) & 0x7ffff) as usize;
+                                let entry_count = ((value >> 8) & 0xfff) as usize;
                                 let entry_start = (value >> 20) as usize;
                                 logln!(Log::Cpu, "ldsr chcw request to clear cache for start entry: {}, entry count: {}", entry_start, entry_count);
                                 self.cache.clear_entries(entry_start, entry_count);
                             } else if (value >> 4) & 0x01 == 1 {
                                 let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to dump instruction cache to 0x{:08x} not implemented yet", addr);
+                                logln!(Log::Cpu, "ldsr chcw request to dump instruction cache to 0x{:08x}", addr);
+                                num_cycles += self.cache.dump(bus, addr);
                             } else if (value >> 5) & 0x01 == 1 {
                                 let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to restore instruction cache from 0x{:08x} not implemented yet", addr);
+                                logln!(Log::Cpu, "ldsr chcw request to restore instruction cache from 0x{:08x}", addr);
+                                num_cycles += self.cache.restore(bus, addr);
                             }
                         }
                         _ => logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", imm5),
@@ -616,10 +920,10 @@ impl V810 {
                         OPCODE_SYSTEM_REGISTER_ID_EIPSW => self.reg_eipsw,
                         OPCODE_SYSTEM_REGISTER_ID_FEPC => self.reg_fepc,
                         OPCODE_SYSTEM_REGISTER_ID_FEPSW => self.reg_fepsw,
-                        OPCODE_SYSTEM_REGISTER_ID_ECR => self.reg_ecr as _,
+                        OPCODE_SYSTEM_REGISTER_ID_ECR => self.reg_ecr,
                         OPCODE_SYSTEM_REGISTER_ID_PSW => self.reg_psw(),
                         OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: stsr chcw not fully implemented");
+                            // Only the enable bit reads back; the other bits are commands
                             match self.cache.is_enabled() {
                                 true => 2,
                                 false => 0,
@@ -646,25 +950,38 @@ impl V810 {
                             let mut dst_bit_offset = self.reg_gpr(26) & 0x1f;
                             let mut num_bits = self.reg_gpr(28);
 
+                            num_cycles = bit_string_cycles(bus, src_word_addr, src_bit_offset, dst_word_addr, dst_bit_offset, num_bits);
+
+                            let mut is_new_src_word = true;
                             while num_bits > 0 {
-                                let src_word = read_word(interconnect, src_word_addr);
-                                let dst_word = read_word(interconnect, dst_word_addr);
+                                let src_word = bus.read_word(src_word_addr);
+                                let dst_word = bus.read_word(dst_word_addr);
+                                // Each source word is reported once, rather than once per bit
+                                if is_new_src_word {
+                                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Word, original_pc, src_word_addr, src_word, src_word);
+                                    is_new_src_word = false;
+                                }
                                 let src_bit = (src_word >> src_bit_offset) & 0x01;
                                 let dst_bit = (dst_word >> dst_bit_offset) & 0x01;
                                 let res_bit = $f(src_bit, dst_bit) & 0x01;
                                 let dst_bit_mask = !(1 << dst_bit_offset);
                                 let res_word = (dst_word & dst_bit_mask) | (res_bit << dst_bit_offset);
-                                write_word(interconnect, dst_word_addr, res_word);
+                                bus.write_word(dst_word_addr, res_word);
+                                // The destination word is rewritten once per bit, so only changes are reported
+                                if res_word != dst_word {
+                                    trigger_watchpoint |= self.check_hooks(AccessKind::Write, AccessSize::Word, original_pc, dst_word_addr, dst_word, res_word);
+                                }
 
                                 src_bit_offset += 1;
                                 if src_bit_offset >= 32 {
                                     src_bit_offset = 0;
-                                    src_word_addr += 4;
+                                    src_word_addr = src_word_addr.wrapping_add(4);
+                                    is_new_src_word = true;
                                 }
                                 dst_bit_offset += 1;
                                 if dst_bit_offset >= 32 {
                                     dst_bit_offset = 0;
-                                    dst_word_addr += 4;
+                                    dst_word_addr = dst_word_addr.wrapping_add(4);
                                 }
 
                                 num_bits -= 1;
@@ -687,7 +1004,10 @@ impl V810 {
                         OPCODE_BITS_BIT_STRING_OP_ANDNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit & dst_bit),
                         OPCODE_BITS_BIT_STRING_OP_XORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit ^ dst_bit),
                         OPCODE_BITS_BIT_STRING_OP_NOTBSU => bsu!(|src_bit: u32, _| !src_bit),
-                        _ => panic!("Unrecognized bit string op: {:05b}", imm5)
+                        _ => {
+                            logln!(Log::Cpu, "WARNING: Unrecognized bit string op: {:05b} (pc: 0x{:08x})", imm5, original_pc);
+                            exception = Some(EXCEPTION_CODE_ILLEGAL_OPCODE);
+                        }
                     }
                 }),
                 OPCODE_BITS_MOVEA => format_v!(|reg1, reg2, imm16| {
@@ -707,6 +1027,12 @@ impl V810 {
                 }),
                 OPCODE_BITS_JAL => format_iv!(|target| {
                     self.set_reg_gpr(31, next_pc);
+                    self.push_call_frame(CallFrame {
+                        kind: CallFrameKind::Call,
+                        call_addr: original_pc,
+                        target_addr: target,
+                        return_addr: next_pc,
+                    });
                     next_pc = target;
                     num_cycles = 3;
                 }),
@@ -742,67 +1068,84 @@ impl V810 {
                 }),
                 OPCODE_BITS_LDB => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_byte(addr) as i8) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
+                    let value = bus.read_byte(addr);
+                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Byte, original_pc, addr, value as u32, value as u32);
+                    self.set_reg_gpr(reg2, (value as i8) as u32);
+                    num_cycles = self.load_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Load;
                 }),
                 OPCODE_BITS_LDH => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
                     let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_halfword(addr) as i16) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
+                    let value = bus.read_halfword(addr);
+                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Halfword, original_pc, addr, value as u32, value as u32);
+                    self.set_reg_gpr(reg2, (value as i16) as u32);
+                    num_cycles = self.load_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Load;
                 }),
                 OPCODE_BITS_LDW | OPCODE_BITS_INW => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
                     let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = read_word(interconnect, addr);
+                    let value = bus.read_word(addr);
+                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Word, original_pc, addr, value, value);
                     self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
+                    num_cycles = self.load_cycles(bus, addr, 2);
+                    memory_op = MemoryOp::Load;
                 }),
                 OPCODE_BITS_STB | OPCODE_BITS_OUTB => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
                     let value = self.reg_gpr(reg2) as u8;
-                    interconnect.write_byte(addr, value);
-                    num_cycles = 4;
+                    let old_value = self.peek_hooked_value(bus, AccessKind::Write, AccessSize::Byte, addr);
+                    bus.write_byte(addr, value);
+                    if let Some(old_value) = old_value {
+                        trigger_watchpoint |= self.check_hooks(AccessKind::Write, AccessSize::Byte, original_pc, addr, old_value, value as u32);
+                    }
+                    num_cycles = self.store_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Store;
                 }),
                 OPCODE_BITS_STH | OPCODE_BITS_OUTH => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
                     let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
                     let value = self.reg_gpr(reg2) as u16;
-                    interconnect.write_halfword(addr, value);
-                    num_cycles = 4;
+                    let old_value = self.peek_hooked_value(bus, AccessKind::Write, AccessSize::Halfword, addr);
+                    bus.write_halfword(addr, value);
+                    if let Some(old_value) = old_value {
+                        trigger_watchpoint |= self.check_hooks(AccessKind::Write, AccessSize::Halfword, original_pc, addr, old_value, value as u32);
+                    }
+                    num_cycles = self.store_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Store;
                 }),
                 OPCODE_BITS_STW | OPCODE_BITS_OUTW => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
                     let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
                     let value = self.reg_gpr(reg2);
-                    write_word(interconnect, addr, value);
-                    num_cycles = 4;
+                    let old_value = self.peek_hooked_value(bus, AccessKind::Write, AccessSize::Word, addr);
+                    bus.write_word(addr, value);
+                    if let Some(old_value) = old_value {
+                        trigger_watchpoint |= self.check_hooks(AccessKind::Write, AccessSize::Word, original_pc, addr, old_value, value);
+                    }
+                    num_cycles = self.store_cycles(bus, addr, 2);
+                    memory_op = MemoryOp::Store;
                 }),
                 OPCODE_BITS_INB => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_byte(addr) as u32;
+                    let value = bus.read_byte(addr) as u32;
+                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Byte, original_pc, addr, value, value);
                     self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
+                    num_cycles = self.load_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Load;
                 }),
                 OPCODE_BITS_INH => format_vi!(|reg1, reg2, disp16| {
                     let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
                     let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_halfword(addr) as u32;
+                    let value = bus.read_halfword(addr) as u32;
+                    trigger_watchpoint |= self.check_hooks(AccessKind::Read, AccessSize::Halfword, original_pc, addr, value, value);
                     self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
+                    num_cycles = self.load_cycles(bus, addr, 1);
+                    memory_op = MemoryOp::Load;
                 }),
                 OPCODE_BITS_EXTENDED => {
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
+                    let second_halfword = self.fetch_halfword(bus, next_pc, &mut fetch_cycles);
                     next_pc = next_pc.wrapping_add(2);
 
                     let reg1 = (first_halfword & 0x1f) as usize;
@@ -814,14 +1157,22 @@ impl V810 {
                         OPCODE_BITS_SUB_OP_CMPF_S => {
                             let lhs = self.reg_gpr_float(reg2);
                             let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs - rhs;
-
-                            self.set_fp_flags(value);
+                            match self.check_fp_operands(lhs, rhs) {
+                                Ok(()) => {
+                                    let value = lhs - rhs;
+                                    self.set_fp_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 10;
                         }
                         OPCODE_BITS_SUB_OP_CVT_WS => {
-                            let value = (self.reg_gpr(reg1) as i32) as f32;
+                            let int_value = self.reg_gpr(reg1) as i32;
+                            let value = int_value as f32;
+                            if (value as f64) != (int_value as f64) {
+                                self.psw_fp_precision_degredation = true;
+                            }
                             self.set_reg_gpr_float(reg2, value);
 
                             self.set_fp_flags(value);
@@ -829,51 +1180,72 @@ impl V810 {
                             num_cycles = 16;
                         }
                         OPCODE_BITS_SUB_OP_CVT_SW => {
-                            let value = (self.reg_gpr_float(reg1).round() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
+                            let float_value = self.reg_gpr_float(reg1);
+                            match self.float_to_int(float_value.round(), float_value) {
+                                Ok(value) => {
+                                    self.set_reg_gpr(reg2, value);
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+                                    self.psw_overflow = false;
+                                    self.set_zero_sign_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 14;
                         }
                         OPCODE_BITS_SUB_OP_ADDF_S => {
                             let lhs = self.reg_gpr_float(reg2);
                             let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs + rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                            match self.float_add(lhs, rhs) {
+                                Ok(value) => {
+                                    self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                                    self.set_fp_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 28;
                         }
                         OPCODE_BITS_SUB_OP_SUBF_S => {
                             let lhs = self.reg_gpr_float(reg2);
                             let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs - rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                            match self.float_add(lhs, -rhs) {
+                                Ok(value) => {
+                                    self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                                    self.set_fp_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 28;
                         }
                         OPCODE_BITS_SUB_OP_MULF_S => {
                             let lhs = self.reg_gpr_float(reg2);
                             let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs * rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                            match self.float_mul(lhs, rhs) {
+                                Ok(value) => {
+                                    self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                                    self.set_fp_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 30;
                         }
                         OPCODE_BITS_SUB_OP_DIVF_S => {
                             let lhs = self.reg_gpr_float(reg2);
                             let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs / rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                            match self.float_div(lhs, rhs) {
+                                Ok(value) => {
+                                    self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                                    self.set_fp_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 44;
                         }
@@ -900,11 +1272,16 @@ impl V810 {
                             num_cycles = 22;
                         }
                         OPCODE_BITS_SUB_OP_TRNC_SW => {
-                            let value = (self.reg_gpr_float(reg1).trunc() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
+                            let float_value = self.reg_gpr_float(reg1);
+                            match self.float_to_int(float_value.trunc(), float_value) {
+                                Ok(value) => {
+                                    self.set_reg_gpr(reg2, value);
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+                                    self.psw_overflow = false;
+                                    self.set_zero_sign_flags(value);
+                                }
+                                Err(exception_code) => exception = Some(exception_code),
+                            }
 
                             num_cycles = 14;
                         }
@@ -916,20 +1293,117 @@ impl V810 {
 
                             num_cycles = 9;
                         }
-                        _ => panic!("Unrecognized subop bits: {:06b}", subop_bits)
+                        _ => {
+                            logln!(Log::Cpu, "WARNING: Unrecognized subop bits: {:06b} (pc: 0x{:08x})", subop_bits, original_pc);
+                            exception = Some(EXCEPTION_CODE_ILLEGAL_OPCODE);
+                        }
                     }
                 }
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, first_halfword),
+                _ => {
+                    logln!(Log::Cpu, "WARNING: Unrecognized opcode bits: {:06b} (halfword: 0b{:016b}, pc: 0x{:08x})", opcode_bits, first_halfword, original_pc);
+                    exception = Some(EXCEPTION_CODE_ILLEGAL_OPCODE);
+                }
             }
         }
 
+        if let Some(exception_code) = exception {
+            // The exception handler returns to the faulting instruction
+            next_pc = self.enter_exception(exception_code);
+        }
+
+        if let Some(fatal_exception) = self.fatal_exception {
+            // Debug words are written to the start of the address space before the CPU stops
+            bus.write_word(0x00000000, 0xffff0000 | (fatal_exception.exception_code as u32));
+            bus.write_word(0x00000004, fatal_exception.psw);
+            bus.write_word(0x00000008, fatal_exception.pc);
+        }
+
         self.reg_pc = next_pc;
+        self.previous_memory_op = memory_op;
+
+        // Instruction fetches overlap with execution of the previous instruction,
+        //  so they only cost extra when they take longer than executing
+        if fetch_cycles > num_cycles {
+            num_cycles = fetch_cycles;
+        }
 
         (num_cycles, trigger_watchpoint)
     }
 
-    fn check_watchpoints(&self, addr: u32) -> bool {
-        self.watchpoints.len() != 0 && self.watchpoints.contains(&addr)
+    fn fetch_halfword<B: Bus>(&mut self, bus: &mut B, addr: u32, fetch_cycles: &mut u32) -> u16 {
+        let (halfword, cache_result) = self.cache.read_halfword(bus, addr);
+        *fetch_cycles += match cache_result {
+            CacheResult::Hit => 0,
+            // A miss fills a whole subblock, which takes two bus accesses
+            CacheResult::Miss => 2 * (1 + bus.wait_states(addr)),
+            CacheResult::Disabled => 1 + bus.wait_states(addr),
+        };
+        halfword
+    }
+
+    fn load_cycles<B: Bus>(&self, bus: &B, addr: u32, num_accesses: u32) -> u32 {
+        // Back-to-back loads overlap in the pipeline
+        let cycles = if self.previous_memory_op == MemoryOp::Load { 4 } else { 5 };
+        cycles + bus.wait_states(addr) * num_accesses
+    }
+
+    fn store_cycles<B: Bus>(&self, bus: &B, addr: u32, num_accesses: u32) -> u32 {
+        // Stores are absorbed by the write buffer unless the previous instruction was also a store
+        let cycles = if self.previous_memory_op == MemoryOp::Store { 4 } else { 1 };
+        cycles + bus.wait_states(addr) * num_accesses
+    }
+
+    fn check_hooks(&mut self, kind: AccessKind, size: AccessSize, pc: u32, addr: u32, old_value: u32, new_value: u32) -> bool {
+        if self.hooks.is_empty() {
+            return false;
+        }
+
+        let access = MemoryAccess {
+            kind: kind,
+            size: size,
+            pc: pc,
+            addr: addr,
+            old_value: old_value,
+            new_value: new_value,
+        };
+        match self.hooks.dispatch(&access) {
+            Some(id) => {
+                self.triggered_hook = Some((id, access));
+                true
+            }
+            _ => false
+        }
+    }
+
+    // Execute hooks see the whole instruction, so a watch that only covers the
+    //  second halfword of a 32-bit instruction still fires
+    fn check_execute_hooks<B: Bus>(&mut self, bus: &B, pc: u32, first_halfword: u16) -> bool {
+        if self.hooks.is_empty() {
+            return false;
+        }
+
+        let (size, value) = match Opcode::from_halfword(first_halfword) {
+            Some(opcode) if opcode.instruction_format().has_second_halfword() => {
+                let second_halfword = bus.peek_halfword(pc.wrapping_add(2));
+                (AccessSize::Word, ((second_halfword as u32) << 16) | (first_halfword as u32))
+            }
+            _ => (AccessSize::Halfword, first_halfword as u32)
+        };
+        self.check_hooks(AccessKind::Execute, size, pc, pc, value, value)
+    }
+
+    // Peeks at the current value at addr only if a hook wants it, so
+    //  memory that nobody is watching costs nothing extra
+    fn peek_hooked_value<B: Bus>(&self, bus: &B, kind: AccessKind, size: AccessSize, addr: u32) -> Option<u32> {
+        if self.hooks.is_empty() || !self.hooks.covers(kind, addr, size) {
+            return None;
+        }
+
+        Some(match size {
+            AccessSize::Byte => bus.peek_byte(addr) as u32,
+            AccessSize::Halfword => bus.peek_halfword(addr) as u32,
+            AccessSize::Word => bus.peek_word(addr),
+        })
     }
 
     fn add(&mut self, lhs: u32, rhs: u32, reg2: usize) {
@@ -996,6 +1470,94 @@ impl V810 {
         self.psw_sign = (value & 0x80000000) != 0;
     }
 
+    // The V810 doesn't support NaN's, infinities or denormals as FPU operands
+    fn check_fp_operands(&mut self, lhs: f32, rhs: f32) -> Result<(), u16> {
+        if is_fp_reserved_operand(lhs) || is_fp_reserved_operand(rhs) {
+            self.psw_fp_reserved_operand = true;
+            return Err(EXCEPTION_CODE_FP_RESERVED_OPERAND);
+        }
+
+        Ok(())
+    }
+
+    fn float_add(&mut self, lhs: f32, rhs: f32) -> Result<f32, u16> {
+        self.check_fp_operands(lhs, rhs)?;
+
+        // Sums of singles are exact in double precision unless the exponents
+        //  are far enough apart for the smaller operand to (partly) vanish,
+        //  which shows up when the sum is round-tripped back to the operands
+        let (lhs, rhs) = (lhs as f64, rhs as f64);
+        let sum = lhs + rhs;
+        if sum - lhs != rhs || sum - rhs != lhs {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        self.round_fp_result(sum)
+    }
+
+    fn float_mul(&mut self, lhs: f32, rhs: f32) -> Result<f32, u16> {
+        self.check_fp_operands(lhs, rhs)?;
+
+        // Products of singles are always exact in double precision
+        self.round_fp_result((lhs as f64) * (rhs as f64))
+    }
+
+    fn float_div(&mut self, lhs: f32, rhs: f32) -> Result<f32, u16> {
+        self.check_fp_operands(lhs, rhs)?;
+
+        if rhs == 0.0 {
+            if lhs == 0.0 {
+                self.psw_fp_invalid_operation = true;
+                return Err(EXCEPTION_CODE_FP_INVALID_OPERATION);
+            }
+
+            self.psw_fp_zero_division = true;
+            return Err(EXCEPTION_CODE_FP_ZERO_DIVISION);
+        }
+
+        self.round_fp_result((lhs as f64) / (rhs as f64))
+    }
+
+    // Rounds a result to single precision and updates the sticky overflow,
+    //  underflow and precision degradation flags. Overflow raises an
+    //  exception; underflowed results are flushed to zero.
+    fn round_fp_result(&mut self, exact: f64) -> Result<f32, u16> {
+        let value = exact as f32;
+
+        if value.is_infinite() {
+            self.psw_fp_overflow = true;
+            return Err(EXCEPTION_CODE_FP_OVERFLOW);
+        }
+
+        if value.classify() == FpCategory::Subnormal || (value == 0.0 && exact != 0.0) {
+            self.psw_fp_underflow = true;
+            self.psw_fp_precision_degredation = true;
+            return Ok(0.0);
+        }
+
+        if (value as f64) != exact {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        Ok(value)
+    }
+
+    // Converts an already rounded/truncated value to a word; `value` is the original operand
+    fn float_to_int(&mut self, rounded: f32, value: f32) -> Result<u32, u16> {
+        self.check_fp_operands(value, 0.0)?;
+
+        if rounded >= 2147483648.0 || rounded < -2147483648.0 {
+            self.psw_fp_invalid_operation = true;
+            return Err(EXCEPTION_CODE_FP_INVALID_OPERATION);
+        }
+
+        if rounded != value {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        Ok((rounded as i32) as u32)
+    }
+
     fn set_fp_flags(&mut self, value: f32) {
         self.psw_carry = value.is_sign_negative();
         self.psw_overflow = false;
@@ -1004,7 +1566,7 @@ impl V810 {
     }
 
     pub fn request_interrupt(&mut self, exception_code: u16) {
-        if self.psw_nmi_pending || self.psw_exception_pending || self.psw_interrupt_disable {
+        if self.fatal_exception.is_some() || self.psw_nmi_pending || self.psw_exception_pending || self.psw_interrupt_disable {
             return;
         }
 
@@ -1024,38 +1586,661 @@ impl V810 {
 
     fn enter_exception(&mut self, exception_code: u16) -> u32 {
         logln!(Log::Cpu, "Entering exception (code: 0x{:04x})", exception_code);
-        // TODO: Duplexed exception
-        self.reg_eipc = self.reg_pc;
+        let mut restore_pc = self.reg_pc;
         if self.is_halted {
-            self.reg_eipc = self.reg_eipc.wrapping_add(2);
+            restore_pc = restore_pc.wrapping_add(2);
             self.is_halted = false;
         }
-        self.reg_eipsw = self.reg_psw();
-        self.reg_ecr = exception_code;
-        self.psw_exception_pending = true;
+
+        if self.psw_nmi_pending {
+            logln!(Log::Cpu, "Fatal exception (code: 0x{:04x}, pc: 0x{:08x})", exception_code, restore_pc);
+            self.fatal_exception = Some(FatalException {
+                exception_code: exception_code,
+                psw: self.reg_psw(),
+                pc: restore_pc,
+            });
+            self.is_halted = true;
+            return self.reg_pc;
+        }
+
+        let psw = self.reg_psw();
+        if self.psw_exception_pending {
+            logln!(Log::Cpu, "Duplexed exception (code: 0x{:04x})", exception_code);
+            self.reg_fepc = restore_pc;
+            self.reg_fepsw = psw;
+            self.reg_ecr = (self.reg_ecr & 0x0000ffff) | ((exception_code as u32) << 16);
+            self.psw_nmi_pending = true;
+        } else {
+            self.reg_eipc = restore_pc;
+            self.reg_eipsw = psw;
+            self.reg_ecr = (self.reg_ecr & 0xffff0000) | (exception_code as u32);
+            self.psw_exception_pending = true;
+        }
         self.psw_interrupt_disable = true;
         self.psw_address_trap_enable = false;
-        0xffff0000 | (exception_code as u32)
+
+        let handler_addr = if self.psw_nmi_pending {
+            0xffffffd0
+        } else {
+            match exception_code {
+                // All FPU exceptions share a handler
+                0xff60 ... 0xff7f => 0xffffff60,
+                // Trap vectors 0-15 and 16-31 share a handler each
+                0xffa0 ... 0xffbf => 0xffff0000 | ((exception_code as u32) & 0xfff0),
+                _ => 0xffff0000 | (exception_code as u32)
+            }
+        };
+
+        self.push_call_frame(CallFrame {
+            kind: CallFrameKind::Exception(exception_code),
+            call_addr: restore_pc,
+            target_addr: handler_addr,
+            return_addr: restore_pc,
+        });
+
+        handler_addr
+    }
+
+    fn push_call_frame(&mut self, frame: CallFrame) {
+        if self.call_stack.len() >= MAX_CALL_STACK_DEPTH {
+            self.call_stack.pop_front();
+        }
+        self.call_stack.push_back(frame);
+    }
+
+    // Returns unwind to the innermost call that returns to return_addr. Frames
+    //  above it were left without returning (eg. by longjmp-style code); a
+    //  return that matches no call (eg. jmp [r31] used as a computed jump) is ignored.
+    //  Only reti leaves an exception handler, so calls below the innermost
+    //  exception frame are never matched.
+    fn pop_call_frames(&mut self, return_addr: u32) {
+        let position = self.call_stack.iter().rev()
+            .take_while(|frame| frame.kind == CallFrameKind::Call)
+            .position(|frame| frame.return_addr == return_addr);
+        if let Some(position) = position {
+            let index = self.call_stack.len() - 1 - position;
+            self.call_stack.truncate(index);
+        }
     }
 
     fn return_from_exception(&mut self) -> u32 {
-        logln!(Log::Cpu, "Returning from exception (code: 0x{:04x})", self.reg_ecr);
-        let psw = self.reg_eipsw;
-        self.set_reg_psw(psw);
-        self.reg_eipc
+        logln!(Log::Cpu, "Returning from exception (code: 0x{:08x})", self.reg_ecr);
+        let index = self.call_stack.iter().rposition(|frame| frame.kind != CallFrameKind::Call);
+        if let Some(index) = index {
+            self.call_stack.truncate(index);
+        }
+
+        if self.psw_nmi_pending {
+            let psw = self.reg_fepsw;
+            self.set_reg_psw(psw);
+            self.reg_fepc
+        } else {
+            let psw = self.reg_eipsw;
+            self.set_reg_psw(psw);
+            self.reg_eipc
+        }
+    }
+}
+
+fn is_fp_reserved_operand(value: f32) -> bool {
+    matches!(value.classify(), FpCategory::Nan | FpCategory::Infinite | FpCategory::Subnormal)
+}
+
+// Bit string instructions work a word at a time: every destination word is
+//  read and written back, and every source word feeding it is read once.
+fn bit_string_cycles<B: Bus>(bus: &B, src_word_addr: u32, src_bit_offset: u32, dst_word_addr: u32, dst_bit_offset: u32, num_bits: u32) -> u32 {
+    const SETUP_CYCLES: u64 = 20;
+    const CYCLES_PER_WORD: u64 = 6;
+
+    if num_bits == 0 {
+        return SETUP_CYCLES as _;
     }
+
+    let num_src_words = (src_bit_offset as u64 + num_bits as u64).div_ceil(32);
+    let num_dst_words = (dst_bit_offset as u64 + num_bits as u64).div_ceil(32);
+
+    // Each word access is two bus accesses
+    let src_waits = num_src_words * 2 * (bus.wait_states(src_word_addr) as u64);
+    let dst_waits = num_dst_words * 4 * (bus.wait_states(dst_word_addr) as u64);
+
+    let cycles = SETUP_CYCLES + num_dst_words * CYCLES_PER_WORD + src_waits + dst_waits;
+    if cycles > 0xffffffff { 0xffffffff } else { cycles as _ }
 }
 
 fn sign_extend_imm5(imm5: u32) -> u32 {
     (((imm5 as i32) << 27) >> 27) as _
 }
 
-fn read_word(interconnect: &mut Interconnect, addr: u32) -> u32 {
-    (interconnect.read_halfword(addr) as u32) |
-    ((interconnect.read_halfword(addr + 2) as u32) << 16)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAM_SIZE: usize = 0x10000;
+    const CODE_START: u32 = 0x1000;
+
+    // Mirrors a small RAM throughout the whole address space
+    struct FlatRam {
+        bytes: Vec<u8>,
+        num_reads: usize,
+    }
+
+    impl FlatRam {
+        fn new() -> FlatRam {
+            FlatRam {
+                bytes: vec![0; RAM_SIZE],
+                num_reads: 0,
+            }
+        }
+
+        fn load_code(&mut self, halfwords: &[u16]) {
+            for (index, &halfword) in halfwords.iter().enumerate() {
+                self.write_halfword(CODE_START + (index as u32) * 2, halfword);
+            }
+        }
+    }
+
+    impl Bus for FlatRam {
+        fn read_byte(&mut self, addr: u32) -> u8 {
+            self.num_reads += 1;
+            self.bytes[(addr as usize) & (RAM_SIZE - 1)]
+        }
+
+        fn read_halfword(&mut self, addr: u32) -> u16 {
+            let addr = addr & 0xfffffffe;
+            (self.read_byte(addr) as u16) | ((self.read_byte(addr + 1) as u16) << 8)
+        }
+
+        fn write_byte(&mut self, addr: u32, value: u8) {
+            self.bytes[(addr as usize) & (RAM_SIZE - 1)] = value;
+        }
+
+        fn write_halfword(&mut self, addr: u32, value: u16) {
+            let addr = addr & 0xfffffffe;
+            self.write_byte(addr, value as _);
+            self.write_byte(addr + 1, (value >> 8) as _);
+        }
+
+        fn peek_byte(&self, addr: u32) -> u8 {
+            self.bytes[(addr as usize) & (RAM_SIZE - 1)]
+        }
+    }
+
+    fn format_i(opcode_bits: u16, reg1: u16, reg2: u16) -> u16 {
+        (opcode_bits << 10) | (reg2 << 5) | reg1
+    }
+
+    fn format_ii(opcode_bits: u16, imm5: u16, reg2: u16) -> u16 {
+        (opcode_bits << 10) | (reg2 << 5) | (imm5 & 0x1f)
+    }
+
+    fn format_vi(opcode_bits: u16, disp16: i16, reg1: u16, reg2: u16) -> [u16; 2] {
+        [(opcode_bits << 10) | (reg2 << 5) | reg1, disp16 as u16]
+    }
+
+    fn format_vii(sub_op_bits: u16, reg1: u16, reg2: u16) -> [u16; 2] {
+        [(OPCODE_BITS_EXTENDED << 10) | (reg2 << 5) | reg1, sub_op_bits << 10]
+    }
+
+    fn run(cpu: &mut V810, ram: &mut FlatRam, num_instructions: usize) {
+        cpu.set_reg_pc(CODE_START);
+        for _ in 0..num_instructions {
+            cpu.step(ram);
+        }
+    }
+
+    fn flags(cpu: &V810) -> (bool, bool, bool, bool) {
+        let psw = cpu.reg_psw();
+        (psw & 0x01 != 0, psw & 0x02 != 0, psw & 0x04 != 0, psw & 0x08 != 0)
+    }
+
+    #[test]
+    fn add_sets_carry_and_zero() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        ram.load_code(&[format_i(OPCODE_BITS_ADD_REG, 1, 2)]);
+        cpu.set_reg_gpr(1, 1);
+        cpu.set_reg_gpr(2, 0xffffffff);
+
+        run(&mut cpu, &mut ram, 1);
+
+        assert_eq!(cpu.reg_gpr(2), 0);
+        // (zero, sign, overflow, carry)
+        assert_eq!(flags(&cpu), (true, false, false, true));
+        assert_eq!(cpu.reg_pc(), CODE_START + 2);
+    }
+
+    #[test]
+    fn add_sets_overflow_and_sign() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        ram.load_code(&[format_ii(OPCODE_BITS_ADD_IMM_5, 1, 2)]);
+        cpu.set_reg_gpr(2, 0x7fffffff);
+
+        run(&mut cpu, &mut ram, 1);
+
+        assert_eq!(cpu.reg_gpr(2), 0x80000000);
+        assert_eq!(flags(&cpu), (false, true, true, false));
+    }
+
+    #[test]
+    fn store_and_load_word() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        let store = format_vi(OPCODE_BITS_STW, -4, 1, 2);
+        let load = format_vi(OPCODE_BITS_LDW, -4, 1, 3);
+        ram.load_code(&[store[0], store[1], load[0], load[1]]);
+        cpu.set_reg_gpr(1, 0x2004);
+        cpu.set_reg_gpr(2, 0x12345678);
+
+        run(&mut cpu, &mut ram, 2);
+
+        assert_eq!(ram.read_word(0x2000), 0x12345678);
+        assert_eq!(ram.read_halfword(0x2000), 0x5678);
+        assert_eq!(cpu.reg_gpr(3), 0x12345678);
+        assert_eq!(cpu.reg_pc(), CODE_START + 8);
+    }
+
+    #[test]
+    fn jal_and_jmp() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        // jal +0x100, then return with jmp [r31]
+        ram.load_code(&[OPCODE_BITS_JAL << 10, 0x0100]);
+        ram.write_halfword(CODE_START + 0x100, format_i(OPCODE_BITS_JMP, 31, 0));
+
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!(cpu.reg_pc(), CODE_START + 0x100);
+        assert_eq!(cpu.reg_gpr(31), CODE_START + 4);
+
+        cpu.step(&mut ram);
+        assert_eq!(cpu.reg_pc(), CODE_START + 4);
+    }
+
+    #[test]
+    fn returns_in_exception_handlers_keep_outer_calls() {
+        let mut cpu = V810::new();
+        cpu.set_reg_psw(0);
+        let mut ram = FlatRam::new();
+        // jal +0x100, which traps; the trap handler then does a jmp [r31]
+        //  that happens to match the outer call's return address
+        ram.load_code(&[OPCODE_BITS_JAL << 10, 0x0100]);
+        ram.write_halfword(CODE_START + 0x100, format_ii(OPCODE_BITS_TRAP, 0, 0));
+        ram.write_halfword(0xffffffa0, format_i(OPCODE_BITS_JMP, 31, 0));
+
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(cpu.reg_pc(), 0xffffffa0);
+        assert_eq!(cpu.call_stack().len(), 2);
+
+        cpu.step(&mut ram);
+        assert_eq!(cpu.reg_pc(), CODE_START + 4);
+        let kinds = cpu.call_stack().iter().map(|frame| frame.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![CallFrameKind::Call, CallFrameKind::Exception(0xffa0)]);
+    }
+
+    #[test]
+    fn movbsu_copies_bits() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        ram.load_code(&[format_ii(OPCODE_BITS_BIT_STRING, OPCODE_BITS_BIT_STRING_OP_MOVBSU as _, 0)]);
+        ram.write_word(0x2000, 0xdeadbeef);
+        ram.write_word(0x2004, 0x00000001);
+        ram.write_word(0x3000, 0xffffffff);
+        ram.write_word(0x3004, 0xffffffff);
+        // Copy 36 bits from 0x2000 bit 0 to 0x3000 bit 4
+        cpu.set_reg_gpr(30, 0x2000);
+        cpu.set_reg_gpr(29, 0x3000);
+        cpu.set_reg_gpr(27, 0);
+        cpu.set_reg_gpr(26, 4);
+        cpu.set_reg_gpr(28, 36);
+
+        run(&mut cpu, &mut ram, 1);
+
+        assert_eq!(ram.read_word(0x3000), 0xeadbeeff);
+        assert_eq!(ram.read_word(0x3004), 0xffffff1d);
+        assert_eq!(cpu.reg_gpr(28), 0);
+        assert_eq!(cpu.reg_gpr(30), 0x2004);
+        assert_eq!(cpu.reg_gpr(27), 4);
+        assert_eq!(cpu.reg_gpr(29), 0x3004);
+        assert_eq!(cpu.reg_gpr(26), 8);
+    }
+
+    #[test]
+    fn xorbsu_wraps_at_top_of_address_space() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        ram.load_code(&[format_ii(OPCODE_BITS_BIT_STRING, OPCODE_BITS_BIT_STRING_OP_XORBSU as _, 0)]);
+        ram.write_word(0xfffc, 0xffffffff);
+        ram.write_word(0x0000, 0x00000000);
+        cpu.set_reg_gpr(30, 0xfffffffc);
+        cpu.set_reg_gpr(29, 0xfffffffc);
+        cpu.set_reg_gpr(27, 0);
+        cpu.set_reg_gpr(26, 0);
+        cpu.set_reg_gpr(28, 64);
+
+        run(&mut cpu, &mut ram, 1);
+
+        assert_eq!(ram.read_word(0xfffc), 0);
+        assert_eq!(cpu.reg_gpr(30), 0x00000004);
+        assert_eq!(cpu.reg_gpr(29), 0x00000004);
+    }
+
+    #[test]
+    fn set_reg_gpr_ignores_r0() {
+        let mut cpu = V810::new();
+        cpu.set_reg_gpr(0, 0x12345678);
+        cpu.set_reg_gpr(31, 0x87654321);
+
+        assert_eq!(cpu.reg_gpr(0), 0);
+        assert_eq!(cpu.reg_gpr(31), 0x87654321);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_reg_gpr_rejects_invalid_registers() {
+        let mut cpu = V810::new();
+        cpu.set_reg_gpr(32, 0);
+    }
+
+    #[test]
+    fn write_hook_reports_access_and_breaks() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        let store = format_vi(OPCODE_BITS_STW, 0, 1, 2);
+        ram.load_code(&[store[0], store[1]]);
+        ram.write_word(0x2000, 0xaaaaaaaa);
+        cpu.set_reg_gpr(1, 0x2000);
+        cpu.set_reg_gpr(2, 0x12345678);
+        let hook_id = cpu.hooks.add(MemoryHook::new(AccessKind::Write, 0x2002, 0x2002, |_| HookAction::Break));
+
+        cpu.set_reg_pc(CODE_START);
+        let (_, trigger_watchpoint) = cpu.step(&mut ram);
+
+        assert!(trigger_watchpoint);
+        assert_eq!(cpu.triggered_hook(), Some((hook_id, MemoryAccess {
+            kind: AccessKind::Write,
+            size: AccessSize::Word,
+            pc: CODE_START,
+            addr: 0x2000,
+            old_value: 0xaaaaaaaa,
+            new_value: 0x12345678,
+        })));
+    }
+
+    #[test]
+    fn write_hooks_peek_without_reading() {
+        let store = format_vi(OPCODE_BITS_STW, 0, 1, 2);
+        let mut num_reads = Vec::new();
+        for &is_hooked in [false, true].iter() {
+            let mut cpu = V810::new();
+            let mut ram = FlatRam::new();
+            ram.load_code(&[store[0], store[1]]);
+            cpu.set_reg_gpr(1, 0x2000);
+            if is_hooked {
+                cpu.hooks.add(MemoryHook::new(AccessKind::Write, 0x2000, 0x2003, |_| HookAction::Continue));
+            }
+
+            run(&mut cpu, &mut ram, 1);
+            num_reads.push(ram.num_reads);
+        }
+
+        // Only the instruction fetch reads from the bus
+        assert_eq!(num_reads[0], num_reads[1]);
+    }
 
-fn write_word(interconnect: &mut Interconnect, addr: u32, value: u32) {
-    interconnect.write_halfword(addr, value as _);
-    interconnect.write_halfword(addr + 2, (value >> 16) as _);
-}
\ No newline at end of file
+    #[test]
+    fn execute_hooks_cover_the_second_halfword() {
+        let load = format_vi(OPCODE_BITS_LDW, 0, 1, 2);
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        ram.load_code(&[load[0], load[1], format_i(OPCODE_BITS_MOV_REG, 0, 0)]);
+        cpu.hooks.add(MemoryHook::new(AccessKind::Execute, CODE_START + 2, CODE_START + 3, |_| HookAction::Break));
+
+        run(&mut cpu, &mut ram, 1);
+        let (_, access) = cpu.triggered_hook.expect("Execute hook didn't trigger");
+        assert_eq!(access.size, AccessSize::Word);
+        assert_eq!(access.addr, CODE_START);
+        assert_eq!(access.new_value, ((load[1] as u32) << 16) | (load[0] as u32));
+
+        // The following 16-bit instruction doesn't reach into the watched range
+        cpu.step(&mut ram);
+        assert!(cpu.triggered_hook.is_none());
+    }
+
+    const PSW_FP_PRECISION_DEGREDATION: u32 = 1 << 4;
+    const PSW_FP_UNDERFLOW: u32 = 1 << 5;
+    const PSW_FP_OVERFLOW: u32 = 1 << 6;
+    const PSW_FP_ZERO_DIVISION: u32 = 1 << 7;
+    const PSW_FP_RESERVED_OPERAND: u32 = 1 << 9;
+
+    // Runs `lhs <op> rhs` with lhs in r2 and rhs in r1
+    fn run_float_op(sub_op_bits: u16, lhs: f32, rhs: f32) -> V810 {
+        let mut cpu = V810::new();
+        // Clear the NMI pending flag set at reset so exceptions aren't fatal
+        cpu.set_reg_psw(0);
+        let mut ram = FlatRam::new();
+        let op = format_vii(sub_op_bits, 1, 2);
+        ram.load_code(&[op[0], op[1]]);
+        cpu.set_reg_gpr(1, rhs.to_bits());
+        cpu.set_reg_gpr(2, lhs.to_bits());
+
+        run(&mut cpu, &mut ram, 1);
+        cpu
+    }
+
+    fn assert_fp_exception(cpu: &V810, exception_code: u16, psw_flag: u32) {
+        assert_eq!(cpu.reg_pc(), 0xffffff60);
+        assert_eq!(cpu.reg_ecr() & 0xffff, exception_code as u32);
+        assert_eq!(cpu.reg_eipc(), CODE_START);
+        assert!(cpu.reg_eipsw() & psw_flag != 0);
+    }
+
+    #[test]
+    fn float_reserved_operands_raise_exceptions() {
+        for &value in [f32::NAN, f32::INFINITY, f32::MIN_POSITIVE / 2.0].iter() {
+            let cpu = run_float_op(OPCODE_BITS_SUB_OP_ADDF_S, 1.0, value);
+            assert_fp_exception(&cpu, EXCEPTION_CODE_FP_RESERVED_OPERAND, PSW_FP_RESERVED_OPERAND);
+            assert_eq!(cpu.reg_gpr(2), 1.0f32.to_bits());
+        }
+    }
+
+    #[test]
+    fn float_divide_by_zero_raises_exception() {
+        let cpu = run_float_op(OPCODE_BITS_SUB_OP_DIVF_S, 1.0, 0.0);
+        assert_fp_exception(&cpu, EXCEPTION_CODE_FP_ZERO_DIVISION, PSW_FP_ZERO_DIVISION);
+    }
+
+    #[test]
+    fn float_overflow_raises_exception() {
+        let cpu = run_float_op(OPCODE_BITS_SUB_OP_MULF_S, f32::MAX, 2.0);
+        assert_fp_exception(&cpu, EXCEPTION_CODE_FP_OVERFLOW, PSW_FP_OVERFLOW);
+    }
+
+    #[test]
+    fn float_underflow_flushes_to_zero() {
+        let cpu = run_float_op(OPCODE_BITS_SUB_OP_MULF_S, f32::MIN_POSITIVE, 0.5);
+        assert_eq!(cpu.reg_pc(), CODE_START + 4);
+        assert_eq!(cpu.reg_gpr(2), 0);
+        assert!(cpu.reg_psw() & PSW_FP_UNDERFLOW != 0);
+        assert!(cpu.reg_psw() & PSW_FP_PRECISION_DEGREDATION != 0);
+    }
+
+    #[test]
+    fn float_precision_degredation_is_sticky() {
+        let cpu = run_float_op(OPCODE_BITS_SUB_OP_ADDF_S, 1.0, 1.5);
+        assert_eq!(cpu.reg_gpr(2), 2.5f32.to_bits());
+        assert_eq!(cpu.reg_psw() & PSW_FP_PRECISION_DEGREDATION, 0);
+
+        let cpu = run_float_op(OPCODE_BITS_SUB_OP_DIVF_S, 1.0, 3.0);
+        assert!(cpu.reg_psw() & PSW_FP_PRECISION_DEGREDATION != 0);
+
+        // The smaller operand vanishes entirely, even in double precision
+        for &sub_op_bits in [OPCODE_BITS_SUB_OP_ADDF_S, OPCODE_BITS_SUB_OP_SUBF_S].iter() {
+            let cpu = run_float_op(sub_op_bits, 1.0e30, 1.0);
+            assert_eq!(cpu.reg_pc(), CODE_START + 4);
+            assert_eq!(cpu.reg_gpr(2), 1.0e30f32.to_bits());
+            assert!(cpu.reg_psw() & PSW_FP_PRECISION_DEGREDATION != 0);
+        }
+    }
+
+    const PSW_EXCEPTION_PENDING: u32 = 1 << 14;
+    const PSW_NMI_PENDING: u32 = 1 << 15;
+
+    fn run_trap(psw: u32, vector: u16) -> (V810, FlatRam) {
+        let mut cpu = V810::new();
+        cpu.set_reg_psw(psw);
+        let mut ram = FlatRam::new();
+        ram.load_code(&[format_ii(OPCODE_BITS_TRAP, vector, 0)]);
+
+        run(&mut cpu, &mut ram, 1);
+        (cpu, ram)
+    }
+
+    #[test]
+    fn trap_returns_to_the_following_instruction() {
+        let (cpu, _) = run_trap(0, 5);
+        assert_eq!(cpu.reg_pc(), 0xffffffa0);
+        assert_eq!(cpu.reg_ecr() & 0xffff, 0xffa5);
+        assert_eq!(cpu.reg_eipc(), CODE_START + 2);
+        assert!(cpu.reg_psw() & PSW_EXCEPTION_PENDING != 0);
+
+        let (cpu, _) = run_trap(0, 0x13);
+        assert_eq!(cpu.reg_pc(), 0xffffffb0);
+        assert_eq!(cpu.reg_ecr() & 0xffff, 0xffb3);
+    }
+
+    #[test]
+    fn exceptions_in_exception_handlers_are_duplexed() {
+        let (cpu, _) = run_trap(PSW_EXCEPTION_PENDING, 1);
+        assert_eq!(cpu.reg_pc(), 0xffffffd0);
+        assert_eq!(cpu.reg_ecr() >> 16, 0xffa1);
+        assert_eq!(cpu.reg_fepc(), CODE_START + 2);
+        assert_eq!(cpu.reg_fepsw(), PSW_EXCEPTION_PENDING);
+        assert!(cpu.reg_psw() & PSW_NMI_PENDING != 0);
+        assert!(cpu.fatal_exception().is_none());
+    }
+
+    #[test]
+    fn exceptions_while_nmi_pending_are_fatal() {
+        let (mut cpu, mut ram) = run_trap(PSW_NMI_PENDING, 2);
+        let fatal_exception = cpu.fatal_exception().expect("Exception wasn't fatal");
+        assert_eq!(fatal_exception.exception_code, 0xffa2);
+        assert_eq!(fatal_exception.psw, PSW_NMI_PENDING);
+        assert!(cpu.is_halted());
+
+        // The exception code, psw and pc are left at the start of memory
+        assert_eq!(ram.read_word(0x00000000), 0xffffffa2);
+        assert_eq!(ram.read_word(0x00000004), PSW_NMI_PENDING);
+        assert_eq!(ram.read_word(0x00000008), fatal_exception.pc);
+
+        // Interrupts can't wake the CPU back up
+        cpu.request_interrupt(0xfe40);
+        cpu.step(&mut ram);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.fatal_exception(), Some(fatal_exception));
+    }
+
+    // base_addr is left out, since it's derived from the tag and entry index
+    fn entry_fields(entry: CacheEntry) -> (u32, [bool; 2], [u32; 2]) {
+        (entry.tag, entry.subblock_valid, entry.subblock_data)
+    }
+
+    fn entry_index(addr: u32) -> usize {
+        ((addr >> 3) & 0x7f) as usize
+    }
+
+    fn is_hit(result: CacheResult) -> bool {
+        matches!(result, CacheResult::Hit)
+    }
+
+    #[test]
+    fn cache_fills_subblocks_and_hits_without_the_bus() {
+        let mut cache = Cache::new();
+        cache.set_is_enabled(true);
+        let mut ram = FlatRam::new();
+        ram.write_word(0x1000, 0x22221111);
+        ram.write_word(0x1004, 0x44443333);
+
+        let (halfword, result) = cache.read_halfword(&mut ram, 0x1000);
+        assert_eq!(halfword, 0x1111);
+        assert!(!is_hit(result));
+        let num_reads = ram.num_reads;
+
+        // The miss filled the rest of the subblock
+        let (halfword, result) = cache.read_halfword(&mut ram, 0x1002);
+        assert_eq!(halfword, 0x2222);
+        assert!(is_hit(result));
+        assert_eq!(ram.num_reads, num_reads);
+
+        // ..but not the entry's other subblock
+        let (halfword, result) = cache.read_halfword(&mut ram, 0x1006);
+        assert_eq!(halfword, 0x4444);
+        assert!(!is_hit(result));
+
+        let entry = cache.entry(entry_index(0x1000));
+        assert_eq!(entry_fields(entry), (0x1000 >> 10, [true, true], [0x22221111, 0x44443333]));
+        assert_eq!(entry.base_addr, 0x1000);
+        assert_eq!(cache.stats(), (1, 2));
+    }
+
+    #[test]
+    fn cache_serves_stale_data_until_invalidated() {
+        let mut cache = Cache::new();
+        cache.set_is_enabled(true);
+        let mut ram = FlatRam::new();
+        ram.write_word(0x1000, 0x22221111);
+        cache.read_halfword(&mut ram, 0x1000);
+
+        ram.write_word(0x1000, 0x66665555);
+        assert_eq!(cache.read_halfword(&mut ram, 0x1000).0, 0x1111);
+
+        // Clearing entries leaves their tags and data, but they miss again
+        cache.clear_entries(entry_index(0x1000), 1);
+        assert_eq!(cache.entry(entry_index(0x1000)).subblock_data[0], 0x22221111);
+        let (halfword, result) = cache.read_halfword(&mut ram, 0x1000);
+        assert_eq!(halfword, 0x5555);
+        assert!(!is_hit(result));
+
+        // A different tag for the same entry evicts it
+        ram.write_word(0x1400, 0x88887777);
+        assert_eq!(cache.read_halfword(&mut ram, 0x1400).0, 0x7777);
+        assert_eq!(cache.entry(entry_index(0x1000)).tag, 0x1400 >> 10);
+        assert!(!is_hit(cache.read_halfword(&mut ram, 0x1000).1));
+    }
+
+    #[test]
+    fn cache_dump_and_restore_round_trip() {
+        let mut cpu = V810::new();
+        let mut ram = FlatRam::new();
+        // Enable the cache, fill an entry by running from it, then dump the
+        //  cache to 0x8000 with ldsr r1, chcw
+        let ldsr_chcw = format_ii(OPCODE_BITS_LDSR, OPCODE_SYSTEM_REGISTER_ID_CHCW as _, 1);
+        ram.load_code(&[ldsr_chcw, format_i(OPCODE_BITS_MOV_REG, 0, 0), ldsr_chcw]);
+        cpu.set_reg_gpr(1, 0x02);
+        cpu.set_reg_pc(CODE_START);
+        cpu.step(&mut ram);
+        cpu.set_reg_gpr(1, 0x8000 | 0x10 | 0x02);
+        cpu.step(&mut ram);
+        cpu.step(&mut ram);
+
+        let filled = entry_fields(cpu.cache.entry(entry_index(CODE_START)));
+        assert_eq!(filled.1, [true, true]);
+
+        let mut restored = Cache::new();
+        restored.restore(&mut ram, 0x8000);
+        for index in 0..CACHE_NUM_ENTRIES {
+            assert_eq!(entry_fields(restored.entry(index)), entry_fields(cpu.cache.entry(index)));
+        }
+
+        // Save states keep the cache contents and statistics too
+        let mut writer = StateWriter::new();
+        cpu.cache.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+        let mut loaded = Cache::new();
+        loaded.load_state(&mut StateReader::new(&bytes).unwrap()).unwrap();
+        assert!(loaded.is_enabled());
+        assert_eq!(loaded.stats(), cpu.cache.stats());
+        for index in 0..CACHE_NUM_ENTRIES {
+            assert_eq!(entry_fields(loaded.entry(index)), entry_fields(cpu.cache.entry(index)));
+        }
+    }
+}
@@ -1,6 +1,9 @@
 mod mem_map;
 
+use std::io::{self, Error, ErrorKind};
+
 use sinks::*;
+use state::*;
 
 use self::mem_map::*;
 
@@ -17,12 +20,20 @@ const DRAWING_BLOCK_COUNT: u32 = DISPLAY_RESOLUTION_Y / DRAWING_BLOCK_HEIGHT;
 // 20mhz / (1s / 2.5ms) = 50000 clocks
 const DISPLAY_FRAME_EIGHTH_PERIOD: u32 = 50000;
 
+// The VIP displays a frame every 20ms (400000 clocks), whether the display is enabled or not
+pub const DISPLAY_FRAME_PERIOD: u32 = DISPLAY_FRAME_EIGHTH_PERIOD * 8;
+
 const DRAWING_PERIOD: u32 = DISPLAY_FRAME_EIGHTH_PERIOD * 2;
 const DRAWING_BLOCK_PERIOD: u32 = DRAWING_PERIOD / DRAWING_BLOCK_COUNT;
 
 // 20mhz / (1s / 56us) = 1120 clocks
 const DRAWING_SBOUT_PERIOD: u32 = 1120;
 
+// While a world is highlighted, its opaque pixels are drawn with the brightest
+//  color and every other world's with the darkest
+const HIGHLIGHTED_WORLD_PALETTE: u8 = 0xfc;
+const DIMMED_WORLD_PALETTE: u8 = 0x54;
+
 enum DisplayState {
     Idle,
     LeftFramebuffer,
@@ -57,8 +68,19 @@ enum ObjGroup {
     Group3,
 }
 
+impl ObjGroup {
+    fn index(&self) -> usize {
+        match self {
+            &ObjGroup::Group0 => 0,
+            &ObjGroup::Group1 => 1,
+            &ObjGroup::Group2 => 2,
+            &ObjGroup::Group3 => 3,
+        }
+    }
+}
+
 pub struct Vip {
-    _vram: Box<[u8]>,
+    vram: Box<[u8]>,
     vram_ptr: *mut u8,
 
     display_state: DisplayState,
@@ -120,6 +142,12 @@ pub struct Vip {
 
     display_first_framebuffers: bool,
     last_bkcol: u8,
+
+    // Debug rendering settings; these aren't part of save states
+    disabled_worlds: u32,
+    disabled_obj_groups: u8,
+    highlighted_world: Option<u32>,
+    world_palette_override: Option<u8>,
 }
 
 impl Vip {
@@ -128,7 +156,7 @@ impl Vip {
         let vram_ptr = vram.as_mut_ptr();
 
         Vip {
-            _vram: vram,
+            vram: vram,
             vram_ptr: vram_ptr,
 
             display_state: DisplayState::Idle,
@@ -190,9 +218,88 @@ impl Vip {
 
             display_first_framebuffers: false,
             last_bkcol: 0,
+
+            disabled_worlds: 0,
+            disabled_obj_groups: 0,
+            highlighted_world: None,
+            world_palette_override: None,
+        }
+    }
+
+    /// Returns VRAM as laid out at the start of the VIP's address space
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    /// Returns the value of a BG palette register (GPLT0-3)
+    pub fn gplt(&self, index: usize) -> u8 {
+        match index {
+            0 => self.reg_gplt0,
+            1 => self.reg_gplt1,
+            2 => self.reg_gplt2,
+            _ => self.reg_gplt3,
+        }
+    }
+
+    /// Returns the value of an OBJ palette register (JPLT0-3)
+    pub fn jplt(&self, index: usize) -> u8 {
+        match index {
+            0 => self.reg_jplt0,
+            1 => self.reg_jplt1,
+            2 => self.reg_jplt2,
+            _ => self.reg_jplt3,
+        }
+    }
+
+    /// Returns the index of the last OBJ in an OBJ group (SPT0-3)
+    pub fn spt(&self, index: usize) -> u16 {
+        match index {
+            0 => self.reg_spt0,
+            1 => self.reg_spt1,
+            2 => self.reg_spt2,
+            _ => self.reg_spt3,
+        }
+    }
+
+    pub fn is_world_enabled(&self, index: u32) -> bool {
+        (self.disabled_worlds & (1 << (index & 0x1f))) == 0
+    }
+
+    /// Enables or disables drawing a world (0-31). Disabled OBJ worlds still
+    /// use up their OBJ group.
+    pub fn set_world_enabled(&mut self, index: u32, is_enabled: bool) {
+        let mask = 1 << (index & 0x1f);
+        if is_enabled {
+            self.disabled_worlds &= !mask;
+        } else {
+            self.disabled_worlds |= mask;
         }
     }
 
+    pub fn is_obj_group_enabled(&self, index: usize) -> bool {
+        (self.disabled_obj_groups & (1 << (index & 0x03))) == 0
+    }
+
+    /// Enables or disables drawing the OBJs of an OBJ group (0-3)
+    pub fn set_obj_group_enabled(&mut self, index: usize, is_enabled: bool) {
+        let mask = 1 << (index & 0x03);
+        if is_enabled {
+            self.disabled_obj_groups &= !mask;
+        } else {
+            self.disabled_obj_groups |= mask;
+        }
+    }
+
+    pub fn highlighted_world(&self) -> Option<u32> {
+        self.highlighted_world
+    }
+
+    /// Draws a world's pixels at full brightness and dims all the others, or
+    /// draws every world normally again if `index` is `None`
+    pub fn set_highlighted_world(&mut self, index: Option<u32>) {
+        self.highlighted_world = index.map(|index| index & 0x1f);
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         let addr = addr & 0x0007ffff;
         match addr {
@@ -472,6 +579,124 @@ impl Vip {
         }
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+
+        writer.write_u8(match self.display_state {
+            DisplayState::Idle => 0,
+            DisplayState::LeftFramebuffer => 1,
+            DisplayState::RightFramebuffer => 2,
+            DisplayState::Finished => 3,
+        });
+        writer.write_u8(match self.drawing_state {
+            DrawingState::Idle => 0,
+            DrawingState::Drawing => 1,
+        });
+        writer.write_bool(self.reg_intpnd_lfbend);
+        writer.write_bool(self.reg_intpnd_rfbend);
+        writer.write_bool(self.reg_intpnd_gamestart);
+        writer.write_bool(self.reg_intpnd_framestart);
+        writer.write_bool(self.reg_intpnd_sbhit);
+        writer.write_bool(self.reg_intpnd_xpend);
+        writer.write_bool(self.reg_intenb_lfbend);
+        writer.write_bool(self.reg_intenb_rfbend);
+        writer.write_bool(self.reg_intenb_gamestart);
+        writer.write_bool(self.reg_intenb_framestart);
+        writer.write_bool(self.reg_intenb_sbhit);
+        writer.write_bool(self.reg_intenb_xpend);
+        writer.write_bool(self.reg_dpctrl_disp);
+        writer.write_bool(self.reg_dpctrl_synce);
+        writer.write_bool(self.reg_xpctrl_xpen);
+        writer.write_u32(self.reg_xpctrl_sbcount);
+        writer.write_u32(self.reg_xpctrl_sbcmp);
+        writer.write_bool(self.reg_xpctrl_sbout);
+        writer.write_u32(self.reg_frmcyc);
+        writer.write_u8(self.reg_brta);
+        writer.write_u8(self.reg_brtb);
+        writer.write_u8(self.reg_brtc);
+        writer.write_u16(self.reg_spt0);
+        writer.write_u16(self.reg_spt1);
+        writer.write_u16(self.reg_spt2);
+        writer.write_u16(self.reg_spt3);
+        writer.write_u8(self.reg_gplt0);
+        writer.write_u8(self.reg_gplt1);
+        writer.write_u8(self.reg_gplt2);
+        writer.write_u8(self.reg_gplt3);
+        writer.write_u8(self.reg_jplt0);
+        writer.write_u8(self.reg_jplt1);
+        writer.write_u8(self.reg_jplt2);
+        writer.write_u8(self.reg_jplt3);
+        writer.write_u8(self.reg_bkcol);
+        writer.write_u32(self.display_frame_eighth_clock_counter);
+        writer.write_u32(self.display_frame_eighth_counter);
+        writer.write_u32(self.drawing_block_counter);
+        writer.write_u32(self.drawing_sbout_counter);
+        writer.write_u32(self.fclk);
+        writer.write_bool(self.display_first_framebuffers);
+        writer.write_u8(self.last_bkcol);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes(&mut self.vram)?;
+
+        self.display_state = match reader.read_u8()? {
+            0 => DisplayState::Idle,
+            1 => DisplayState::LeftFramebuffer,
+            2 => DisplayState::RightFramebuffer,
+            3 => DisplayState::Finished,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid VIP display state in save state")),
+        };
+        self.drawing_state = match reader.read_u8()? {
+            0 => DrawingState::Idle,
+            1 => DrawingState::Drawing,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid VIP drawing state in save state")),
+        };
+        self.reg_intpnd_lfbend = reader.read_bool()?;
+        self.reg_intpnd_rfbend = reader.read_bool()?;
+        self.reg_intpnd_gamestart = reader.read_bool()?;
+        self.reg_intpnd_framestart = reader.read_bool()?;
+        self.reg_intpnd_sbhit = reader.read_bool()?;
+        self.reg_intpnd_xpend = reader.read_bool()?;
+        self.reg_intenb_lfbend = reader.read_bool()?;
+        self.reg_intenb_rfbend = reader.read_bool()?;
+        self.reg_intenb_gamestart = reader.read_bool()?;
+        self.reg_intenb_framestart = reader.read_bool()?;
+        self.reg_intenb_sbhit = reader.read_bool()?;
+        self.reg_intenb_xpend = reader.read_bool()?;
+        self.reg_dpctrl_disp = reader.read_bool()?;
+        self.reg_dpctrl_synce = reader.read_bool()?;
+        self.reg_xpctrl_xpen = reader.read_bool()?;
+        self.reg_xpctrl_sbcount = reader.read_u32()?;
+        self.reg_xpctrl_sbcmp = reader.read_u32()?;
+        self.reg_xpctrl_sbout = reader.read_bool()?;
+        self.reg_frmcyc = reader.read_u32()?;
+        self.reg_brta = reader.read_u8()?;
+        self.reg_brtb = reader.read_u8()?;
+        self.reg_brtc = reader.read_u8()?;
+        self.reg_spt0 = reader.read_u16()?;
+        self.reg_spt1 = reader.read_u16()?;
+        self.reg_spt2 = reader.read_u16()?;
+        self.reg_spt3 = reader.read_u16()?;
+        self.reg_gplt0 = reader.read_u8()?;
+        self.reg_gplt1 = reader.read_u8()?;
+        self.reg_gplt2 = reader.read_u8()?;
+        self.reg_gplt3 = reader.read_u8()?;
+        self.reg_jplt0 = reader.read_u8()?;
+        self.reg_jplt1 = reader.read_u8()?;
+        self.reg_jplt2 = reader.read_u8()?;
+        self.reg_jplt3 = reader.read_u8()?;
+        self.reg_bkcol = reader.read_u8()?;
+        self.display_frame_eighth_clock_counter = reader.read_u32()?;
+        self.display_frame_eighth_counter = reader.read_u32()?;
+        self.drawing_block_counter = reader.read_u32()?;
+        self.drawing_sbout_counter = reader.read_u32()?;
+        self.fclk = reader.read_u32()?;
+        self.display_first_framebuffers = reader.read_bool()?;
+        self.last_bkcol = reader.read_u8()?;
+
+        Ok(())
+    }
+
     fn read_vram_byte(&self, addr: u32) -> u8 {
         unsafe {
             *self.vram_ptr.offset(addr as _)
@@ -779,12 +1004,23 @@ impl Vip {
                     _ => WindowMode::Obj
                 };
 
+                let world_enabled = self.is_world_enabled(window_index as u32);
+                self.world_palette_override = match self.highlighted_world {
+                    Some(highlighted_world) if highlighted_world == window_index as u32 => Some(HIGHLIGHTED_WORLD_PALETTE),
+                    Some(_) => Some(DIMMED_WORLD_PALETTE),
+                    _ => None,
+                };
+
                 for i in 0..2 {
                     let eye = match i {
                         0 => Eye::Left,
                         _ => Eye::Right,
                     };
 
+                    if !world_enabled {
+                        continue;
+                    }
+
                     match eye {
                         Eye::Left => {
                             if !left_on {
@@ -808,7 +1044,7 @@ impl Vip {
                             //logln!(Log::Vip, "Current obj group: {:?}", current_obj_group);
 
                             match current_obj_group {
-                                Some(obj_group) => {
+                                Some(obj_group) if self.is_obj_group_enabled(obj_group.index()) => {
                                     let starting_obj_index = match obj_group {
                                         ObjGroup::Group0 => self.reg_spt0,
                                         ObjGroup::Group1 => self.reg_spt1,
@@ -892,6 +1128,7 @@ impl Vip {
                                         }
                                     }
                                 }
+                                Some(_) => {}
                                 _ => logln!(Log::Vip, "WARNING: Extra obj window found; all obj groups already drawn")
                             }
                         }
@@ -1008,6 +1245,8 @@ impl Vip {
             window_offset -= WINDOW_ENTRY_LENGTH;
             window_index -= 1;
         }
+
+        self.world_palette_override = None;
     }
 
     #[inline(always)]
@@ -1068,6 +1307,7 @@ impl Vip {
     fn draw_char_pixel(&mut self, framebuffer_offset: u32, pixel_x: u32, pixel_y: u32, offset_x: u32, offset_y: u32, char_index: u32, horizontal_flip: bool, vertical_flip: bool, palette: u8) {
         let offset_x = if horizontal_flip { 7 - offset_x } else { offset_x };
         let offset_y = if vertical_flip { 7 - offset_y } else { offset_y };
+        let palette = self.world_palette_override.unwrap_or(palette);
 
         let char_offset = if char_index < 0x0200 {
             0x00006000 + char_index * 16
@@ -1,8 +1,11 @@
+use std::io;
+
 use sinks::*;
 use rom::*;
 use sram::*;
 use interconnect::*;
 use v810::*;
+use state::*;
 
 pub struct VirtualBoy {
     pub interconnect: Interconnect,
@@ -17,6 +20,23 @@ impl VirtualBoy {
         }
     }
 
+    /// Serializes the complete machine state (CPU, memories, and all peripherals) into a
+    /// versioned, byte-stable buffer. The ROM itself is not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.interconnect.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restores a machine state previously produced by `save_state`. If an error is returned,
+    /// the machine may have been partially restored and should be reset or reloaded.
+    pub fn load_state(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut reader = StateReader::new(bytes)?;
+        self.cpu.load_state(&mut reader)?;
+        self.interconnect.load_state(&mut reader)
+    }
+
     pub fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
         let ret = self.cpu.step(&mut self.interconnect);
 
@@ -27,3 +47,50 @@ impl VirtualBoy {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullSink;
+
+    impl<T> Sink<T> for NullSink {
+        fn append(&mut self, _value: T) {}
+    }
+
+    fn test_virtual_boy() -> VirtualBoy {
+        // An all-zero ROM is a long run of `mov r0, r0`
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        VirtualBoy::new(rom, Sram::new())
+    }
+
+    #[test]
+    fn save_load_save_is_byte_identical() {
+        let mut virtual_boy = test_virtual_boy();
+        for _ in 0..10000 {
+            virtual_boy.step(&mut NullSink, &mut NullSink);
+        }
+        virtual_boy.cpu.set_reg_gpr(7, 0x12345678);
+        virtual_boy.interconnect.write_halfword(0x05000100, 0xbeef);
+        virtual_boy.interconnect.write_byte(0x06000010, 0x42);
+
+        let state = virtual_boy.save_state();
+
+        let mut loaded = test_virtual_boy();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.cpu.reg_gpr(7), 0x12345678);
+        assert_eq!(loaded.interconnect.read_halfword(0x05000100), 0xbeef);
+        assert_eq!(loaded.interconnect.read_byte(0x06000010), 0x42);
+        assert_eq!(loaded.save_state(), state);
+    }
+
+    #[test]
+    fn load_state_rejects_bad_header_and_truncation() {
+        let virtual_boy = test_virtual_boy();
+        let state = virtual_boy.save_state();
+
+        let mut loaded = test_virtual_boy();
+        assert!(loaded.load_state(b"NOPE\x01\x00\x00\x00").is_err());
+        assert!(loaded.load_state(&state[..state.len() / 2]).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 mod mem_map;
 
+use std::io;
+
 use sinks::*;
+use state::*;
 
 use self::mem_map::*;
 
@@ -64,6 +67,22 @@ impl PlayControlReg {
             }
         }
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enable);
+        writer.write_bool(self.use_duration);
+        writer.write_u32(self.duration);
+        writer.write_u32(self.duration_counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.enable = reader.read_bool()?;
+        self.use_duration = reader.read_bool()?;
+        self.duration = reader.read_u32()?;
+        self.duration_counter = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 #[derive(Default)]
@@ -77,6 +96,18 @@ impl VolumeReg {
         self.left = (value >> 4) as _;
         self.right = (value & 0x0f) as _;
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.left);
+        writer.write_u32(self.right);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.left = reader.read_u32()?;
+        self.right = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 #[derive(Default)]
@@ -127,6 +158,28 @@ impl Envelope {
             }
         }
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.reg_data_reload);
+        writer.write_bool(self.reg_data_direction);
+        writer.write_u32(self.reg_data_step_interval);
+        writer.write_bool(self.reg_control_repeat);
+        writer.write_bool(self.reg_control_enable);
+        writer.write_u32(self.level);
+        writer.write_u32(self.envelope_counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.reg_data_reload = reader.read_u32()?;
+        self.reg_data_direction = reader.read_bool()?;
+        self.reg_data_step_interval = reader.read_u32()?;
+        self.reg_control_repeat = reader.read_bool()?;
+        self.reg_control_enable = reader.read_bool()?;
+        self.level = reader.read_u32()?;
+        self.envelope_counter = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 trait Voice {
@@ -204,6 +257,30 @@ impl StandardVoice {
 
         wave_tables[(self.reg_pcm_wave * NUM_WAVE_TABLE_WORDS + self.phase) as usize] as _
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.reg_play_control.save_state(writer);
+        self.reg_volume.save_state(writer);
+        writer.write_u32(self.reg_frequency_low);
+        writer.write_u32(self.reg_frequency_high);
+        self.envelope.save_state(writer);
+        writer.write_u32(self.reg_pcm_wave);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.phase);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.reg_play_control.load_state(reader)?;
+        self.reg_volume.load_state(reader)?;
+        self.reg_frequency_low = reader.read_u32()?;
+        self.reg_frequency_high = reader.read_u32()?;
+        self.envelope.load_state(reader)?;
+        self.reg_pcm_wave = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.phase = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 impl Voice for StandardVoice {
@@ -364,6 +441,56 @@ impl SweepModVoice {
 
         wave_tables[(self.reg_pcm_wave * NUM_WAVE_TABLE_WORDS + self.phase) as usize] as _
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.reg_play_control.save_state(writer);
+        self.reg_volume.save_state(writer);
+        writer.write_u32(self.reg_frequency_low);
+        writer.write_u32(self.reg_frequency_high);
+        writer.write_u32(self.frequency_low);
+        writer.write_u32(self.frequency_high);
+        writer.write_u32(self.next_frequency_low);
+        writer.write_u32(self.next_frequency_high);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.reg_sweep_mod_enable);
+        writer.write_bool(self.reg_mod_repeat);
+        writer.write_bool(self.reg_function);
+        writer.write_bool(self.reg_sweep_mod_base_interval);
+        writer.write_u32(self.reg_sweep_mod_interval);
+        writer.write_bool(self.reg_sweep_direction);
+        writer.write_u32(self.reg_sweep_shift_amount);
+        writer.write_u32(self.reg_pcm_wave);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.phase);
+        writer.write_u32(self.sweep_mod_counter);
+        writer.write_u32(self.mod_phase);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.reg_play_control.load_state(reader)?;
+        self.reg_volume.load_state(reader)?;
+        self.reg_frequency_low = reader.read_u32()?;
+        self.reg_frequency_high = reader.read_u32()?;
+        self.frequency_low = reader.read_u32()?;
+        self.frequency_high = reader.read_u32()?;
+        self.next_frequency_low = reader.read_u32()?;
+        self.next_frequency_high = reader.read_u32()?;
+        self.envelope.load_state(reader)?;
+        self.reg_sweep_mod_enable = reader.read_bool()?;
+        self.reg_mod_repeat = reader.read_bool()?;
+        self.reg_function = reader.read_bool()?;
+        self.reg_sweep_mod_base_interval = reader.read_bool()?;
+        self.reg_sweep_mod_interval = reader.read_u32()?;
+        self.reg_sweep_direction = reader.read_bool()?;
+        self.reg_sweep_shift_amount = reader.read_u32()?;
+        self.reg_pcm_wave = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.phase = reader.read_u32()?;
+        self.sweep_mod_counter = reader.read_u32()?;
+        self.mod_phase = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 impl Voice for SweepModVoice {
@@ -465,6 +592,32 @@ impl NoiseVoice {
     fn output(&self) -> u32 {
         self.output
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.reg_play_control.save_state(writer);
+        self.reg_volume.save_state(writer);
+        writer.write_u32(self.reg_frequency_low);
+        writer.write_u32(self.reg_frequency_high);
+        self.envelope.save_state(writer);
+        writer.write_u32(self.reg_noise_control);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.shift);
+        writer.write_u32(self.output);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.reg_play_control.load_state(reader)?;
+        self.reg_volume.load_state(reader)?;
+        self.reg_frequency_low = reader.read_u32()?;
+        self.reg_frequency_high = reader.read_u32()?;
+        self.envelope.load_state(reader)?;
+        self.reg_noise_control = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.shift = reader.read_u32()?;
+        self.output = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 impl Voice for NoiseVoice {
@@ -735,6 +888,50 @@ impl Vsu {
         audio_frame_sink.append((output_left, output_right));
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.wave_tables);
+        for &value in self.mod_table.iter() {
+            writer.write_u8(value as _);
+        }
+
+        self.voice1.save_state(writer);
+        self.voice2.save_state(writer);
+        self.voice3.save_state(writer);
+        self.voice4.save_state(writer);
+        self.voice5.save_state(writer);
+        self.voice6.save_state(writer);
+
+        writer.write_u32(self.duration_clock_counter);
+        writer.write_u32(self.envelope_clock_counter);
+        writer.write_u32(self.frequency_clock_counter);
+        writer.write_u32(self.sweep_mod_clock_counter);
+        writer.write_u32(self.noise_clock_counter);
+        writer.write_u32(self.sample_clock_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes(&mut self.wave_tables)?;
+        for value in self.mod_table.iter_mut() {
+            *value = reader.read_u8()? as _;
+        }
+
+        self.voice1.load_state(reader)?;
+        self.voice2.load_state(reader)?;
+        self.voice3.load_state(reader)?;
+        self.voice4.load_state(reader)?;
+        self.voice5.load_state(reader)?;
+        self.voice6.load_state(reader)?;
+
+        self.duration_clock_counter = reader.read_u32()?;
+        self.envelope_clock_counter = reader.read_u32()?;
+        self.frequency_clock_counter = reader.read_u32()?;
+        self.sweep_mod_clock_counter = reader.read_u32()?;
+        self.noise_clock_counter = reader.read_u32()?;
+        self.sample_clock_counter = reader.read_u32()?;
+
+        Ok(())
+    }
+
     fn are_channels_active(&self) -> bool {
         self.voice1.reg_play_control.enable ||
         self.voice2.reg_play_control.enable ||
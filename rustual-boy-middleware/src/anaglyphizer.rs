@@ -32,7 +32,7 @@ impl<T: Sink<ColorFrame>> Anaglyphizer<T> {
 
 impl<T: Sink<ColorFrame>> Sink<VideoFrame> for Anaglyphizer<T> {
     fn append(&mut self, frame: VideoFrame) {
-        let mut output = Vec::new();
+        let mut output: Vec<Color> = Vec::new();
         output.reserve_exact(DISPLAY_PIXELS as usize);
         let (ref l_buffer, ref r_buffer) = frame;
 
@@ -0,0 +1,365 @@
+use rustual_boy_core::hooks::AccessSize;
+use rustual_boy_core::interconnect::Interconnect;
+use rustual_boy_core::mem_map::CARTRIDGE_ROM_START;
+use rustual_boy_core::rom::Rom;
+
+use text_parser::{parse_error, parse_hex};
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::path::Path;
+
+const CHEAT_FILE: &'static str = "Cheat file";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatCodeKind {
+    /// Writes the value to memory every frame
+    Freeze,
+    /// Replaces the ROM contents at the address when they're read
+    Patch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheatCode {
+    pub kind: CheatCodeKind,
+    pub addr: u32,
+    pub size: AccessSize,
+    pub value: u32,
+}
+
+impl CheatCode {
+    /// Checks that the address and value make sense for the code's kind and size
+    pub fn validate(&self) -> Result<(), String> {
+        if self.size != AccessSize::Word && self.value >> (self.size.num_bytes() * 8) != 0 {
+            return Err(format!("Value 0x{:x} doesn't fit in the code's size", self.value));
+        }
+        if self.addr % self.size.num_bytes() != 0 {
+            return Err(format!("Address 0x{:08x} isn't aligned to the code's size", self.addr));
+        }
+        if self.kind == CheatCodeKind::Patch && (self.addr & 0x07ffffff) < CARTRIDGE_ROM_START {
+            return Err(format!("Patch address 0x{:08x} isn't in ROM", self.addr));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            CheatCodeKind::Freeze => "freeze",
+            CheatCodeKind::Patch => "patch",
+        };
+        let (size_name, width) = match self.size {
+            AccessSize::Byte => ("b", 2),
+            AccessSize::Halfword => ("h", 4),
+            AccessSize::Word => ("w", 8),
+        };
+        write!(f, "{} {:08x} {} {:0width$x}", kind, self.addr, size_name, self.value, width = width)
+    }
+}
+
+/// A named group of codes that are switched on and off together
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub is_enabled: bool,
+    pub codes: Vec<CheatCode>,
+}
+
+/// The cheats for a game. Cheat files hold a `[name]` line for each cheat,
+/// optionally followed by `on` or `off` (cheats are on by default), and then
+/// one code per line: `freeze` or `patch`, a hexadecimal address, a size
+/// (`b`, `h` or `w`) and a hexadecimal value. For example:
+///
+/// ```text
+/// # Codes found with the debugger's memory search
+/// [Infinite lives] on
+/// freeze 0500a123 b 09
+///
+/// [Skip intro] off
+/// patch 07001234 h 0000
+/// ```
+///
+/// Freeze codes can target any address, while patch codes must target ROM.
+#[derive(Default)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> CheatList {
+        CheatList {
+            cheats: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the cheat file for a ROM: its game code followed
+    /// by its maker code, with a `cht` extension
+    pub fn file_name(rom: &Rom) -> String {
+        let key = format!("{}{}", rom.game_code().unwrap_or_default(), rom.maker_code().unwrap_or_default());
+        let key = key.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>();
+        if key.is_empty() {
+            "unknown.cht".into()
+        } else {
+            format!("{}.cht", key)
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<CheatList> {
+        let mut file = File::open(file_name)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        CheatList::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        write!(file, "{}", self)
+    }
+
+    pub fn parse(text: &str) -> io::Result<CheatList> {
+        let mut ret = CheatList::new();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let name_end = line.rfind(']').ok_or_else(|| parse_error(CHEAT_FILE, line_index, "Missing ] after cheat name".into()))?;
+                let is_enabled = match line[name_end + 1..].trim() {
+                    "" | "on" => true,
+                    "off" => false,
+                    token => return Err(parse_error(CHEAT_FILE, line_index, format!("Expected on or off, found: {}", token))),
+                };
+                ret.push(Cheat {
+                    name: line[1..name_end].trim().into(),
+                    is_enabled: is_enabled,
+                    codes: Vec::new(),
+                });
+                continue;
+            }
+
+            let code = parse_code(line).map_err(|message| parse_error(CHEAT_FILE, line_index, message))?;
+            match ret.cheats.last_mut() {
+                Some(cheat) => cheat.codes.push(code),
+                _ => return Err(parse_error(CHEAT_FILE, line_index, "Code without a [name] line before it".into())),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn push(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Switches a cheat on or off, returning false if it doesn't exist.
+    /// Patches only take effect once they're applied again.
+    pub fn set_enabled(&mut self, index: usize, is_enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.is_enabled = is_enabled;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Replaces the interconnect's ROM patches with the enabled patch codes
+    pub fn apply_patches(&self, interconnect: &mut Interconnect) {
+        interconnect.clear_rom_patches();
+        for code in self.enabled_codes(CheatCodeKind::Patch) {
+            for i in 0..code.size.num_bytes() {
+                interconnect.add_rom_patch(code.addr.wrapping_add(i), (code.value >> (i * 8)) as u8);
+            }
+        }
+    }
+
+    /// Writes the values of the enabled freeze codes; call this every frame
+    pub fn apply_freezes(&self, interconnect: &mut Interconnect) {
+        for code in self.enabled_codes(CheatCodeKind::Freeze) {
+            match code.size {
+                AccessSize::Byte => interconnect.write_byte(code.addr, code.value as u8),
+                AccessSize::Halfword => interconnect.write_halfword(code.addr, code.value as u16),
+                AccessSize::Word => {
+                    interconnect.write_halfword(code.addr, code.value as u16);
+                    interconnect.write_halfword(code.addr.wrapping_add(2), (code.value >> 16) as u16);
+                }
+            }
+        }
+    }
+
+    fn enabled_codes<'a>(&'a self, kind: CheatCodeKind) -> Box<Iterator<Item = &'a CheatCode> + 'a> {
+        Box::new(self.cheats.iter()
+            .filter(|cheat| cheat.is_enabled)
+            .flat_map(|cheat| cheat.codes.iter())
+            .filter(move |code| code.kind == kind))
+    }
+}
+
+impl fmt::Display for CheatList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, cheat) in self.cheats.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}] {}", cheat.name, if cheat.is_enabled { "on" } else { "off" })?;
+            for code in cheat.codes.iter() {
+                writeln!(f, "{}", code)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_code(line: &str) -> Result<CheatCode, String> {
+    let mut tokens = line.split_whitespace();
+
+    let kind = match tokens.next() {
+        Some("freeze") => CheatCodeKind::Freeze,
+        Some("patch") => CheatCodeKind::Patch,
+        Some(token) => return Err(format!("Expected freeze or patch, found: {}", token)),
+        _ => unreachable!(),
+    };
+
+    let addr = match tokens.next() {
+        Some(token) => parse_hex(token).ok_or_else(|| format!("Invalid address: {}", token))?,
+        _ => return Err("Missing address".into()),
+    };
+
+    let size = match tokens.next() {
+        Some("b") => AccessSize::Byte,
+        Some("h") => AccessSize::Halfword,
+        Some("w") => AccessSize::Word,
+        Some(token) => return Err(format!("Expected b, h or w, found: {}", token)),
+        _ => return Err("Missing size".into()),
+    };
+
+    let value = match tokens.next() {
+        Some(token) => parse_hex(token).ok_or_else(|| format!("Invalid value: {}", token))?,
+        _ => return Err("Missing value".into()),
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(format!("Unexpected token: {}", token));
+    }
+
+    let code = CheatCode {
+        kind: kind,
+        addr: addr,
+        size: size,
+        value: value,
+    };
+    code.validate()?;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHEATS: &'static str = "\
+# Codes found with the debugger's memory search
+[Infinite lives] on
+freeze 0500a123 b 09
+
+[Skip intro] off
+patch 0x07001234 h $0000
+  patch 07001238 w 12345678
+
+[Nothing yet]
+";
+
+    #[test]
+    fn parse() {
+        let cheats = CheatList::parse(CHEATS).unwrap();
+        assert_eq!(cheats.len(), 3);
+
+        let cheat = &cheats.cheats()[0];
+        assert_eq!(cheat.name, "Infinite lives");
+        assert!(cheat.is_enabled);
+        assert_eq!(cheat.codes, vec![CheatCode {
+            kind: CheatCodeKind::Freeze,
+            addr: 0x0500a123,
+            size: AccessSize::Byte,
+            value: 0x09,
+        }]);
+
+        let cheat = &cheats.cheats()[1];
+        assert_eq!(cheat.name, "Skip intro");
+        assert!(!cheat.is_enabled);
+        assert_eq!(cheat.codes.len(), 2);
+        assert_eq!(cheat.codes[1], CheatCode {
+            kind: CheatCodeKind::Patch,
+            addr: 0x07001238,
+            size: AccessSize::Word,
+            value: 0x12345678,
+        });
+
+        let cheat = &cheats.cheats()[2];
+        assert_eq!(cheat.name, "Nothing yet");
+        assert!(cheat.is_enabled);
+        assert!(cheat.codes.is_empty());
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let text = CheatList::parse(CHEATS).unwrap().to_string();
+        assert_eq!(text, "\
+[Infinite lives] on
+freeze 0500a123 b 09
+
+[Skip intro] off
+patch 07001234 h 0000
+patch 07001238 w 12345678
+
+[Nothing yet] on
+");
+        assert_eq!(CheatList::parse(&text).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn parse_reports_line_numbers() {
+        for &(text, message) in [
+            ("freeze 0500a123 b 09", "Cheat file line 1: Code without a [name] line before it"),
+            ("[Lives\nfreeze 0500a123 b 09", "Cheat file line 1: Missing ] after cheat name"),
+            ("[Lives] maybe", "Cheat file line 1: Expected on or off, found: maybe"),
+            ("[Lives]\nwrite 0500a123 b 09", "Cheat file line 2: Expected freeze or patch, found: write"),
+            ("[Lives]\nfreeze 0500a12g b 09", "Cheat file line 2: Invalid address: 0500a12g"),
+            ("[Lives]\nfreeze", "Cheat file line 2: Missing address"),
+            ("[Lives]\nfreeze 0500a123 d 09", "Cheat file line 2: Expected b, h or w, found: d"),
+            ("[Lives]\nfreeze 0500a123 b", "Cheat file line 2: Missing value"),
+            ("[Lives]\nfreeze 0500a123 b 09 0a", "Cheat file line 2: Unexpected token: 0a"),
+            ("[Lives]\n\nfreeze 0500a123 b 100", "Cheat file line 3: Value 0x100 doesn't fit in the code's size"),
+            ("[Lives]\nfreeze 0500a123 h 0100", "Cheat file line 2: Address 0x0500a123 isn't aligned to the code's size"),
+            ("[Lives]\npatch 0500a122 h 0100", "Cheat file line 2: Patch address 0x0500a122 isn't in ROM"),
+        ].iter() {
+            let err = CheatList::parse(text).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), message);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use symbols::SymbolTable;
+
+use rustual_boy_core::hooks::{AccessKind, HookAction, HookId, MemoryHook};
+use rustual_boy_core::instruction::DecodedInstruction;
+use rustual_boy_core::mem_map::CARTRIDGE_ROM_START;
+use rustual_boy_core::rom::Rom;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::cell::{Ref, RefCell};
+use std::cmp;
+use std::io::{self, BufWriter, Write};
+use std::fs::File;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Set for ROM bytes where an executed instruction started
+pub const COVERAGE_EXECUTED: u8 = 1 << 0;
+/// Set for ROM bytes the CPU loaded as data
+pub const COVERAGE_READ: u8 = 1 << 1;
+
+// Uncovered gaps up to this size are disassembled in listings, so untaken
+//  branches show up; longer ones are most likely data or padding
+const MAX_DISASSEMBLED_GAP: usize = 256;
+const DATA_BYTES_PER_LINE: usize = 16;
+
+/// Which ROM bytes the CPU executed as instruction starts and which it read
+/// as data. Accesses through any ROM mirror count, including the exception
+/// vectors at the top of the address space.
+#[derive(Clone)]
+pub struct RomCoverage {
+    flags: Vec<u8>,
+}
+
+impl RomCoverage {
+    pub fn new(rom_size: usize) -> RomCoverage {
+        RomCoverage {
+            flags: vec![0; rom_size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.flags.len()
+    }
+
+    /// Returns the `COVERAGE_*` flags for a ROM offset
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags[offset]
+    }
+
+    pub fn mark_executed(&mut self, addr: u32) {
+        if let Some(offset) = self.rom_offset(addr) {
+            self.flags[offset] |= COVERAGE_EXECUTED;
+        }
+    }
+
+    pub fn mark_read(&mut self, addr: u32, len: u32) {
+        for i in 0..len {
+            if let Some(offset) = self.rom_offset(addr.wrapping_add(i)) {
+                self.flags[offset] |= COVERAGE_READ;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for flags in self.flags.iter_mut() {
+            *flags = 0;
+        }
+    }
+
+    pub fn num_instructions(&self) -> usize {
+        self.flags.iter().filter(|&&flags| flags & COVERAGE_EXECUTED != 0).count()
+    }
+
+    pub fn num_read_bytes(&self) -> usize {
+        self.flags.iter().filter(|&&flags| flags & COVERAGE_READ != 0).count()
+    }
+
+    /// Saves the coverage map: one byte of `COVERAGE_*` flags per ROM byte
+    pub fn save_map<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        file.write_all(&self.flags)
+    }
+
+    pub fn save_listing<P: AsRef<Path>>(&self, file_name: P, rom: &Rom, symbols: &SymbolTable) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        self.write_listing(rom, symbols, &mut writer)?;
+        writer.flush()
+    }
+
+    /// Writes a disassembly of the ROM where each line is marked `X` if it
+    /// was executed, `R` if it was read as data, or `-` if it wasn't covered.
+    /// Long uncovered stretches are summarized on a single line.
+    pub fn write_listing<W: Write>(&self, rom: &Rom, symbols: &SymbolTable, w: &mut W) -> io::Result<()> {
+        writeln!(w, "; {} instructions executed, {} of {} bytes read as data", self.num_instructions(), self.num_read_bytes(), self.size())?;
+
+        let mut offset = 0;
+        while offset < self.size() {
+            let addr = CARTRIDGE_ROM_START + offset as u32;
+            for name in symbols.names_at(addr) {
+                writeln!(w, ".{}:", name)?;
+            }
+
+            let flags = self.flags[offset];
+            if flags & COVERAGE_EXECUTED != 0 {
+                let marker = if flags & COVERAGE_READ != 0 { "XR" } else { "X " };
+                offset += self.write_instruction(rom, symbols, addr, marker, w)?;
+                continue;
+            }
+
+            // Lines never cross a label or the start of differently covered bytes
+            let mut end = offset + 1;
+            let next_label_offset = symbols.next(addr).map_or(self.size(), |(_, label_addr)| cmp::min(label_addr.wrapping_sub(CARTRIDGE_ROM_START) as usize, self.size()));
+            while end < next_label_offset && (self.flags[end] & COVERAGE_EXECUTED) == 0 && (self.flags[end] & COVERAGE_READ) == (flags & COVERAGE_READ) {
+                end += 1;
+            }
+
+            if flags & COVERAGE_READ != 0 {
+                offset = self.write_data(rom, offset, end, "R ", w)?;
+            } else if end - offset <= MAX_DISASSEMBLED_GAP {
+                while offset < end {
+                    let instruction = self.decode(rom, CARTRIDGE_ROM_START + offset as u32);
+                    if offset + instruction.size() as usize > end {
+                        offset = self.write_data(rom, offset, end, "- ", w)?;
+                    } else {
+                        offset += self.write_instruction(rom, symbols, CARTRIDGE_ROM_START + offset as u32, "- ", w)?;
+                    }
+                }
+            } else {
+                writeln!(w, "-  {:08x} - {:08x}  ({} bytes not covered)", addr, CARTRIDGE_ROM_START + (end - 1) as u32, end - offset)?;
+                offset = end;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_instruction<W: Write>(&self, rom: &Rom, symbols: &SymbolTable, addr: u32, marker: &str, w: &mut W) -> io::Result<usize> {
+        let instruction = self.decode(rom, addr);
+        write!(w, "{} {:08x}  {:04x} ", marker, addr, instruction.first_halfword)?;
+        match instruction.second_halfword {
+            Some(second_halfword) => write!(w, "{:04x}", second_halfword)?,
+            _ => write!(w, "    ")?,
+        }
+        writeln!(w, "  {}", instruction.format(symbols))?;
+        Ok(instruction.size() as usize)
+    }
+
+    // Writes the bytes in offset..end as data lines, returning end
+    fn write_data<W: Write>(&self, rom: &Rom, offset: usize, end: usize, marker: &str, w: &mut W) -> io::Result<usize> {
+        for line_offset in (offset..end).step_by(DATA_BYTES_PER_LINE) {
+            write!(w, "{} {:08x}  .db", marker, CARTRIDGE_ROM_START + line_offset as u32)?;
+            for byte_offset in line_offset..cmp::min(line_offset + DATA_BYTES_PER_LINE, end) {
+                write!(w, " {:02x}", rom.read_byte(byte_offset as u32))?;
+            }
+            writeln!(w)?;
+        }
+        Ok(end)
+    }
+
+    fn decode(&self, rom: &Rom, addr: u32) -> DecodedInstruction {
+        DecodedInstruction::decode(addr, |addr| rom.read_halfword(addr.wrapping_sub(CARTRIDGE_ROM_START)))
+    }
+
+    fn rom_offset(&self, addr: u32) -> Option<usize> {
+        // The ROM is mirrored throughout 0x07000000-0x07ffffff, and that whole
+        //  region is mirrored every 128MB
+        let addr = addr & 0x07ffffff;
+        if addr < CARTRIDGE_ROM_START {
+            return None;
+        }
+        Some(((addr - CARTRIDGE_ROM_START) as usize) & (self.size() - 1))
+    }
+}
+
+/// Records `RomCoverage` using CPU memory hooks. Recording can be stopped and
+/// restarted any number of times; coverage accumulates until it's cleared.
+pub struct CoverageRecorder {
+    coverage: Rc<RefCell<RomCoverage>>,
+    hook_ids: Vec<HookId>,
+}
+
+impl CoverageRecorder {
+    pub fn new(rom_size: usize) -> CoverageRecorder {
+        CoverageRecorder {
+            coverage: Rc::new(RefCell::new(RomCoverage::new(rom_size))),
+            hook_ids: Vec::new(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.hook_ids.is_empty()
+    }
+
+    pub fn start(&mut self, virtual_boy: &mut VirtualBoy) {
+        if self.is_recording() {
+            return;
+        }
+
+        let coverage = self.coverage.clone();
+        self.hook_ids.push(virtual_boy.cpu.hooks.add(MemoryHook::new(AccessKind::Execute, 0, 0xffffffff, move |access| {
+            coverage.borrow_mut().mark_executed(access.addr);
+            HookAction::Continue
+        })));
+
+        let coverage = self.coverage.clone();
+        self.hook_ids.push(virtual_boy.cpu.hooks.add(MemoryHook::new(AccessKind::Read, 0, 0xffffffff, move |access| {
+            coverage.borrow_mut().mark_read(access.addr, access.size.num_bytes());
+            HookAction::Continue
+        })));
+    }
+
+    pub fn stop(&mut self, virtual_boy: &mut VirtualBoy) {
+        for id in self.hook_ids.drain(..) {
+            virtual_boy.cpu.hooks.remove(id);
+        }
+    }
+
+    pub fn coverage<'a>(&'a self) -> Ref<'a, RomCoverage> {
+        self.coverage.borrow()
+    }
+
+    pub fn clear(&mut self) {
+        self.coverage.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::rom::MIN_ROM_SIZE;
+    use rustual_boy_core::sram::Sram;
+
+    const ROM_SIZE: usize = MIN_ROM_SIZE;
+
+    fn covered(coverage: &RomCoverage, flag: u8) -> Vec<usize> {
+        (0..coverage.size()).filter(|&offset| coverage.flags(offset) & flag != 0).collect()
+    }
+
+    #[test]
+    fn marks_rom_offsets_through_mirrors() {
+        let mut coverage = RomCoverage::new(ROM_SIZE);
+        coverage.mark_executed(CARTRIDGE_ROM_START - 2);
+        coverage.mark_executed(CARTRIDGE_ROM_START);
+        coverage.mark_executed(CARTRIDGE_ROM_START + ROM_SIZE as u32 - 2);
+        // The ROM repeats throughout its region, which repeats every 128MB
+        coverage.mark_executed(CARTRIDGE_ROM_START + ROM_SIZE as u32 + 4);
+        coverage.mark_executed(0x0f000008);
+        coverage.mark_executed(0xfffffff0);
+
+        assert_eq!(covered(&coverage, COVERAGE_EXECUTED), vec![0, 4, 8, ROM_SIZE - 16, ROM_SIZE - 2]);
+        assert_eq!(coverage.num_instructions(), 5);
+        assert_eq!(coverage.num_read_bytes(), 0);
+    }
+
+    #[test]
+    fn reads_straddling_the_rom_region_only_mark_rom_bytes() {
+        let mut coverage = RomCoverage::new(ROM_SIZE);
+        coverage.mark_read(CARTRIDGE_ROM_START - 2, 4);
+        coverage.mark_read(0x07fffffe, 4);
+
+        assert_eq!(covered(&coverage, COVERAGE_READ), vec![0, 1, ROM_SIZE - 2, ROM_SIZE - 1]);
+        assert_eq!(covered(&coverage, COVERAGE_EXECUTED), Vec::<usize>::new());
+
+        coverage.clear();
+        assert_eq!(coverage.num_read_bytes(), 0);
+    }
+
+    #[test]
+    fn recorder_marks_executed_instructions_until_stopped() {
+        let rom = Rom::from_bytes(&vec![0; ROM_SIZE]).unwrap();
+        let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+        let mut recorder = CoverageRecorder::new(ROM_SIZE);
+
+        // Execution starts at the reset vector, 0xfffffff0
+        recorder.start(&mut virtual_boy);
+        assert!(recorder.is_recording());
+        virtual_boy.cpu.step(&mut virtual_boy.interconnect);
+        virtual_boy.cpu.step(&mut virtual_boy.interconnect);
+        recorder.stop(&mut virtual_boy);
+        virtual_boy.cpu.step(&mut virtual_boy.interconnect);
+
+        assert!(!recorder.is_recording());
+        assert!(virtual_boy.cpu.hooks.is_empty());
+        assert_eq!(covered(&recorder.coverage(), COVERAGE_EXECUTED), vec![ROM_SIZE - 16, ROM_SIZE - 14]);
+    }
+}
@@ -0,0 +1,37 @@
+/// A standard (IEEE 802.3) CRC-32 checksum, as used by zip and PNG
+pub struct Crc32 {
+    table: Box<[u32; 256]>,
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = Box::new([0; 256]);
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut value = i as u32;
+            for _ in 0..8 {
+                value = if (value & 1) != 0 {
+                    0xedb88320 ^ (value >> 1)
+                } else {
+                    value >> 1
+                };
+            }
+            *entry = value;
+        }
+
+        Crc32 {
+            table: table,
+            value: 0xffffffff,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.value = self.table[((self.value ^ (byte as u32)) & 0xff) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value ^ 0xffffffff
+    }
+}
@@ -0,0 +1,34 @@
+use rustual_boy_core::sinks::{AudioFrame, Sink, VideoFrame};
+use rustual_boy_core::vip::DISPLAY_FRAME_PERIOD;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+const MAX_FRAME_CYCLES: u64 = DISPLAY_FRAME_PERIOD as u64 * 2;
+
+struct FrameDetectSink<'a> {
+    inner: &'a mut Sink<VideoFrame>,
+    has_frame: bool,
+}
+
+impl<'a> Sink<VideoFrame> for FrameDetectSink<'a> {
+    fn append(&mut self, frame: VideoFrame) {
+        self.inner.append(frame);
+        self.has_frame = true;
+    }
+}
+
+/// Steps the VirtualBoy until it emits a video frame, stopping right after
+/// the step that produced it. This gives frontends a deterministic point at
+/// which to apply input. Returns the number of cycles emulated.
+pub fn run_to_next_frame(virtual_boy: &mut VirtualBoy, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> u64 {
+    let mut frame_detect_sink = FrameDetectSink {
+        inner: video_frame_sink,
+        has_frame: false,
+    };
+
+    let mut cycles = 0;
+    while !frame_detect_sink.has_frame && cycles < MAX_FRAME_CYCLES {
+        cycles += virtual_boy.step(&mut frame_detect_sink, audio_frame_sink).0 as u64;
+    }
+
+    cycles
+}
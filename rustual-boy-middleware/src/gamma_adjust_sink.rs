@@ -1,3 +1,4 @@
+use color::Color;
 use color_frame::ColorFrame;
 use rustual_boy_core::sinks::Sink;
 use rustual_boy_core::vip::DISPLAY_PIXELS;
@@ -41,7 +42,7 @@ impl<T: Sink<ColorFrame>> GammaAdjustSink<T> {
 
 impl<T: Sink<ColorFrame>> Sink<ColorFrame> for GammaAdjustSink<T> {
     fn append(&mut self, frame: ColorFrame) {
-        let mut output = Vec::new();
+        let mut output: Vec<Color> = Vec::new();
         output.reserve_exact(DISPLAY_PIXELS as usize);
 
         unsafe {
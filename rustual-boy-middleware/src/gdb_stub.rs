@@ -0,0 +1,552 @@
+use rustual_boy_core::hooks::{AccessKind, HookAction, HookId, MemoryHook};
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Register numbering follows v810-elf gdb: r0-r31, then the 32 system
+//  registers (numbered 32 + their ldsr/stsr id), then pc.
+const NUM_REGS: usize = 65;
+const REG_SYSTEM_START: usize = 32;
+const REG_EIPC: usize = REG_SYSTEM_START;
+const REG_EIPSW: usize = REG_SYSTEM_START + 1;
+const REG_FEPC: usize = REG_SYSTEM_START + 2;
+const REG_FEPSW: usize = REG_SYSTEM_START + 3;
+const REG_ECR: usize = REG_SYSTEM_START + 4;
+const REG_PSW: usize = REG_SYSTEM_START + 5;
+const REG_PIR: usize = REG_SYSTEM_START + 6;
+const REG_TKCW: usize = REG_SYSTEM_START + 7;
+const REG_CHCW: usize = REG_SYSTEM_START + 24;
+const REG_PC: usize = 64;
+
+// Fixed values of the read-only processor id and task control word registers
+const PIR_VALUE: u32 = 0x00008100;
+const TKCW_VALUE: u32 = 0x000000e0;
+
+const MAX_PACKET_SIZE: usize = 0x4000;
+
+const WRITE_TIMEOUT_MS: u64 = 5000;
+
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum WatchpointKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Stopped,
+    Running,
+    // Running for a single step
+    Stepping,
+    Detached,
+}
+
+/// A GDB remote serial protocol server for debugging the emulated V810 with
+/// a v810-elf gdb (`target remote localhost:<port>`). The stub doesn't own
+/// the emulation loop: frontends call `poll` regularly to service gdb, keep
+/// stepping the VirtualBoy while `is_running` is true, and pass the result
+/// of every step to `check_stop`. Single steps requested by gdb go through
+/// the same path, so frontends see every frame the target produces.
+pub struct GdbStub {
+    stream: TcpStream,
+    receive_buffer: Vec<u8>,
+
+    state: State,
+
+    breakpoints: HashSet<u32>,
+    // Keyed by (addr, len, kind), as sent by gdb
+    watchpoints: HashMap<(u32, u32, WatchpointKind), Vec<HookId>>,
+}
+
+impl GdbStub {
+    /// Listens on `addr` and blocks until gdb connects. The target starts out stopped.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS)))?;
+        stream.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            stream: stream,
+            receive_buffer: Vec::new(),
+
+            state: State::Stopped,
+
+            breakpoints: HashSet::new(),
+            watchpoints: HashMap::new(),
+        })
+    }
+
+    /// Returns true while gdb wants the target to run
+    pub fn is_running(&self) -> bool {
+        self.state == State::Running || self.state == State::Stepping
+    }
+
+    /// Returns true once gdb has detached or the connection was closed
+    pub fn is_detached(&self) -> bool {
+        self.state == State::Detached
+    }
+
+    /// Handles any packets gdb has sent since the last call
+    pub fn poll(&mut self, virtual_boy: &mut VirtualBoy) -> io::Result<()> {
+        if self.state == State::Detached {
+            return Ok(());
+        }
+
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.detach(virtual_boy);
+                    return Ok(());
+                }
+                Ok(len) => self.receive_buffer.extend_from_slice(&buf[..len]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.detach(virtual_boy);
+                    return Err(e);
+                }
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            self.handle_packet(&packet, virtual_boy)?;
+            if self.state == State::Detached {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether execution should stop after a step (breakpoint or
+    /// watchpoint hit), and if so notifies gdb. Returns true if stopped.
+    pub fn check_stop(&mut self, virtual_boy: &VirtualBoy, trigger_watchpoint: bool) -> io::Result<bool> {
+        if !self.is_running() {
+            return Ok(false);
+        }
+
+        if virtual_boy.cpu.fatal_exception().is_some() {
+            // The CPU won't execute anything else until it's reset
+            self.state = State::Stopped;
+            self.send_packet(&format!("S{:02x}", SIGSEGV))?;
+            return Ok(true);
+        }
+
+        if let (true, Some((hook_id, access))) = (trigger_watchpoint, virtual_boy.cpu.triggered_hook()) {
+            let watchpoint = self.watchpoints.iter()
+                .find(|&(_, hook_ids)| hook_ids.contains(&hook_id))
+                .map(|(&(addr, _, kind), _)| (addr, kind));
+            if let Some((addr, kind)) = watchpoint {
+                let reason = match kind {
+                    WatchpointKind::Write => "watch",
+                    WatchpointKind::Read => "rwatch",
+                    WatchpointKind::Access => "awatch",
+                };
+                self.state = State::Stopped;
+                self.send_packet(&format!("T{:02x}{}:{:08x};", SIGTRAP, reason, cmp::max(addr, access.addr)))?;
+                return Ok(true);
+            }
+        }
+
+        if self.state == State::Stepping || self.breakpoints.contains(&virtual_boy.cpu.reg_pc()) {
+            self.stop()?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Stops the target (e.g. because the frontend hit its own breakpoint) and notifies gdb
+    pub fn stop(&mut self) -> io::Result<()> {
+        if !self.is_running() {
+            return Ok(());
+        }
+
+        self.state = State::Stopped;
+        self.send_packet(&format!("S{:02x}", SIGTRAP))
+    }
+
+    fn detach(&mut self, virtual_boy: &mut VirtualBoy) {
+        for (_, hook_ids) in self.watchpoints.drain() {
+            for hook_id in hook_ids {
+                virtual_boy.cpu.hooks.remove(hook_id);
+            }
+        }
+        self.breakpoints.clear();
+
+        self.state = State::Detached;
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.receive_buffer.first().cloned() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    // Ctrl-C from gdb
+                    self.receive_buffer.remove(0);
+                    self.stop()?;
+                }
+                Some(b'$') => {
+                    let end = match self.receive_buffer.iter().position(|&b| b == b'#') {
+                        Some(end) if end + 2 < self.receive_buffer.len() => end,
+                        _ => return Ok(None),
+                    };
+
+                    let packet: Vec<u8> = self.receive_buffer.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = String::from_utf8_lossy(&packet[end + 1..end + 3]).into_owned();
+                    let expected_checksum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+
+                    if u8::from_str_radix(&checksum, 16).ok() != Some(expected_checksum) {
+                        self.write_all(b"-")?;
+                        continue;
+                    }
+
+                    self.write_all(b"+")?;
+                    return Ok(Some(String::from_utf8_lossy(data).into_owned()));
+                }
+                Some(_) => {
+                    // Acks and any stray bytes between packets
+                    self.receive_buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        self.write_all(packet.as_bytes())
+    }
+
+    // The socket is only non-blocking for reads. Writes block until everything
+    //  is sent, up to WRITE_TIMEOUT_MS, so a stalled client can't spin the CPU.
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        let ret = self.stream.write_all(bytes);
+        self.stream.set_nonblocking(true)?;
+        ret
+    }
+
+    fn handle_packet(&mut self, packet: &str, virtual_boy: &mut VirtualBoy) -> io::Result<()> {
+        let (command, args) = packet.split_at(if packet.is_empty() { 0 } else { 1 });
+
+        let response = match command {
+            "?" => format!("S{:02x}", if virtual_boy.cpu.fatal_exception().is_some() { SIGSEGV } else { SIGTRAP }),
+            "g" => {
+                let mut response = String::new();
+                for index in 0..NUM_REGS {
+                    response.push_str(&encode_u32(read_reg(virtual_boy, index)));
+                }
+                response
+            }
+            "G" => {
+                let values = (0..NUM_REGS)
+                    .map(|index| args.get(index * 8..index * 8 + 8).and_then(decode_u32))
+                    .collect::<Option<Vec<_>>>();
+                match values {
+                    Some(values) => {
+                        for (index, &value) in values.iter().enumerate() {
+                            write_reg(virtual_boy, index, value);
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into()
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(index) if index < NUM_REGS => encode_u32(read_reg(virtual_boy, index)),
+                _ => "E01".into()
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let index = parts.next().and_then(|index| usize::from_str_radix(index, 16).ok());
+                let value = parts.next().and_then(decode_u32);
+                match (index, value) {
+                    (Some(index), Some(value)) if index < NUM_REGS => {
+                        write_reg(virtual_boy, index, value);
+                        "OK".into()
+                    }
+                    _ => "E01".into()
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) if len <= MAX_PACKET_SIZE / 2 => {
+                    let mut response = String::new();
+                    for offset in 0..len {
+                        let byte = virtual_boy.interconnect.peek_byte(addr.wrapping_add(offset as u32));
+                        response.push_str(&format!("{:02x}", byte));
+                    }
+                    response
+                }
+                _ => "E01".into()
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match (parts.next().and_then(parse_addr_len), parts.next().and_then(decode_bytes)) {
+                    (Some((addr, len)), Some(ref bytes)) if bytes.len() == len => {
+                        for (offset, &byte) in bytes.iter().enumerate() {
+                            virtual_boy.interconnect.write_byte(addr.wrapping_add(offset as u32), byte);
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into()
+                }
+            }
+            "c" => {
+                if let Ok(addr) = u32::from_str_radix(args, 16) {
+                    virtual_boy.cpu.set_reg_pc(addr);
+                }
+                self.state = State::Running;
+                return Ok(());
+            }
+            "s" => {
+                if let Ok(addr) = u32::from_str_radix(args, 16) {
+                    virtual_boy.cpu.set_reg_pc(addr);
+                }
+                // The frontend runs the step and reports it through check_stop
+                self.state = State::Stepping;
+                return Ok(());
+            }
+            "Z" | "z" => self.handle_breakpoint_packet(command == "Z", args, virtual_boy),
+            "D" => {
+                self.send_packet("OK")?;
+                self.detach(virtual_boy);
+                return Ok(());
+            }
+            "k" => {
+                self.detach(virtual_boy);
+                return Ok(());
+            }
+            "H" | "T" => "OK".into(),
+            "q" => {
+                if args.starts_with("Supported") {
+                    format!("PacketSize={:x}", MAX_PACKET_SIZE)
+                } else if args == "Attached" {
+                    "1".into()
+                } else if args == "fThreadInfo" {
+                    "m1".into()
+                } else if args == "sThreadInfo" {
+                    "l".into()
+                } else if args == "C" {
+                    "QC1".into()
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new()
+        };
+
+        self.send_packet(&response)
+    }
+
+    fn handle_breakpoint_packet(&mut self, insert: bool, args: &str, virtual_boy: &mut VirtualBoy) -> String {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|addr| u32::from_str_radix(addr, 16).ok());
+        let len = parts.next().and_then(|len| u32::from_str_radix(len, 16).ok());
+        let (addr, len) = match (addr, len) {
+            (Some(addr), Some(len)) => (addr, len),
+            _ => return "E01".into()
+        };
+
+        let watchpoint_kind = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return "OK".into();
+            }
+            Some("2") => WatchpointKind::Write,
+            Some("3") => WatchpointKind::Read,
+            Some("4") => WatchpointKind::Access,
+            _ => return String::new()
+        };
+
+        if len == 0 {
+            return "E01".into();
+        }
+
+        let key = (addr, len, watchpoint_kind);
+        if insert {
+            if let Entry::Vacant(entry) = self.watchpoints.entry(key) {
+                let access_kinds: &[AccessKind] = match watchpoint_kind {
+                    WatchpointKind::Write => &[AccessKind::Write],
+                    WatchpointKind::Read => &[AccessKind::Read],
+                    WatchpointKind::Access => &[AccessKind::Read, AccessKind::Write],
+                };
+                let end = addr.wrapping_add(len - 1);
+                let hook_ids = access_kinds.iter()
+                    .map(|&access_kind| virtual_boy.cpu.hooks.add(MemoryHook::new(access_kind, addr, end, |_| HookAction::Break)))
+                    .collect();
+                entry.insert(hook_ids);
+            }
+        } else if let Some(hook_ids) = self.watchpoints.remove(&key) {
+            for hook_id in hook_ids {
+                virtual_boy.cpu.hooks.remove(hook_id);
+            }
+        }
+
+        "OK".into()
+    }
+}
+
+fn read_reg(virtual_boy: &VirtualBoy, index: usize) -> u32 {
+    let cpu = &virtual_boy.cpu;
+    match index {
+        0 ... 31 => cpu.reg_gpr(index),
+        REG_EIPC => cpu.reg_eipc(),
+        REG_EIPSW => cpu.reg_eipsw(),
+        REG_FEPC => cpu.reg_fepc(),
+        REG_FEPSW => cpu.reg_fepsw(),
+        REG_ECR => cpu.reg_ecr() as _,
+        REG_PSW => cpu.reg_psw(),
+        REG_PIR => PIR_VALUE,
+        REG_TKCW => TKCW_VALUE,
+        REG_CHCW => (cpu.cache.is_enabled() as u32) << 1,
+        REG_PC => cpu.reg_pc(),
+        _ => 0
+    }
+}
+
+fn write_reg(virtual_boy: &mut VirtualBoy, index: usize, value: u32) {
+    let cpu = &mut virtual_boy.cpu;
+    match index {
+        0 ... 31 => cpu.set_reg_gpr(index, value),
+        REG_EIPC => cpu.set_reg_eipc(value),
+        REG_EIPSW => cpu.set_reg_eipsw(value),
+        REG_FEPC => cpu.set_reg_fepc(value),
+        REG_FEPSW => cpu.set_reg_fepsw(value),
+        REG_ECR => cpu.set_reg_ecr(value as _),
+        REG_PSW => cpu.set_reg_psw(value),
+        REG_CHCW => cpu.cache.set_is_enabled((value & 0x02) != 0),
+        REG_PC => cpu.set_reg_pc(value),
+        _ => {}
+    }
+}
+
+fn parse_addr_len(args: &str) -> Option<(u32, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = parts.next().and_then(|addr| u32::from_str_radix(addr, 16).ok());
+    let len = parts.next().and_then(|len| usize::from_str_radix(len, 16).ok());
+    match (addr, len) {
+        (Some(addr), Some(len)) => Some((addr, len)),
+        _ => None
+    }
+}
+
+// Registers are sent in target (little-endian) byte order
+fn encode_u32(value: u32) -> String {
+    format!("{:02x}{:02x}{:02x}{:02x}", value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8)
+}
+
+fn decode_u32(hex: &str) -> Option<u32> {
+    decode_bytes(hex).and_then(|bytes| {
+        if bytes.len() != 4 {
+            return None;
+        }
+
+        Some((bytes[0] as u32) | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16) | ((bytes[3] as u32) << 24))
+    })
+}
+
+fn decode_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len() / 2)
+        .map(|index| hex.get(index * 2..index * 2 + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sinks::Sink;
+    use rustual_boy_core::sram::Sram;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    struct NullSink;
+
+    impl<T> Sink<T> for NullSink {
+        fn append(&mut self, _: T) {}
+    }
+
+    fn connect() -> (GdbStub, TcpStream) {
+        // Grab a free port, then hand it to the stub
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            loop {
+                if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)) {
+                    return stream;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let gdb_stub = GdbStub::listen(("127.0.0.1", port)).unwrap();
+        let client = client.join().unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS))).unwrap();
+        (gdb_stub, client)
+    }
+
+    fn send(client: &mut TcpStream, data: &str) {
+        let checksum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        client.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+    }
+
+    fn receive(client: &mut TcpStream, expected: &str) {
+        let mut buf = vec![0; expected.len()];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    fn poll_until_running(gdb_stub: &mut GdbStub, virtual_boy: &mut VirtualBoy) {
+        for _ in 0..1000 {
+            gdb_stub.poll(virtual_boy).unwrap();
+            if gdb_stub.is_running() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("gdb stub never started running");
+    }
+
+    #[test]
+    fn single_step_is_left_to_the_frontend() {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+        let (mut gdb_stub, mut client) = connect();
+
+        send(&mut client, "s");
+        poll_until_running(&mut gdb_stub, &mut virtual_boy);
+        receive(&mut client, "+");
+
+        // Nothing has run yet; the frontend steps with its own sinks
+        let pc = virtual_boy.cpu.reg_pc();
+        let (_, trigger_watchpoint) = virtual_boy.step(&mut NullSink, &mut NullSink);
+        assert_eq!(virtual_boy.cpu.reg_pc(), pc.wrapping_add(2));
+
+        assert!(gdb_stub.check_stop(&virtual_boy, trigger_watchpoint).unwrap());
+        assert!(!gdb_stub.is_running());
+        receive(&mut client, "$S05#b8");
+    }
+}
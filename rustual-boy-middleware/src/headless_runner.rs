@@ -0,0 +1,146 @@
+use crc32::Crc32;
+use input_script::InputScript;
+use most_recent_sink::MostRecentSink;
+use movie::{Movie, MoviePlayer};
+
+use rustual_boy_core::mem_map::WRAM_START;
+use rustual_boy_core::sinks::{AudioFrame, Sink, SinkRef, VideoFrame};
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::wram::WRAM_SIZE;
+
+struct AudioBuffer {
+    inner: Vec<AudioFrame>,
+}
+
+impl Sink<AudioFrame> for AudioBuffer {
+    fn append(&mut self, frame: AudioFrame) {
+        self.inner.push(frame);
+    }
+}
+
+/// Runs a VirtualBoy without any window or audio device, for automated
+/// testing. Input comes from an `InputScript` or a `Movie`, and the most
+/// recent video frame is kept around so it can be inspected or dumped.
+pub struct HeadlessRunner {
+    pub virtual_boy: VirtualBoy,
+
+    input_script: InputScript,
+    movie_player: Option<MoviePlayer>,
+
+    audio_buffer: AudioBuffer,
+    audio_buffer_sink: Option<Box<SinkRef<[AudioFrame]>>>,
+
+    last_video_frame: Option<VideoFrame>,
+
+    emulated_frames: u64,
+    emulated_cycles: u64,
+}
+
+impl HeadlessRunner {
+    pub fn new(virtual_boy: VirtualBoy) -> HeadlessRunner {
+        HeadlessRunner {
+            virtual_boy: virtual_boy,
+
+            input_script: InputScript::new(),
+            movie_player: None,
+
+            audio_buffer: AudioBuffer {
+                inner: Vec::new(),
+            },
+            audio_buffer_sink: None,
+
+            last_video_frame: None,
+
+            emulated_frames: 0,
+            emulated_cycles: 0,
+        }
+    }
+
+    pub fn set_input_script(&mut self, input_script: InputScript) {
+        self.input_script = input_script;
+    }
+
+    /// Drives input from `movie`, taking precedence over the input script while it lasts
+    pub fn set_movie(&mut self, movie: Movie) {
+        self.movie_player = Some(MoviePlayer::new(movie));
+    }
+
+    /// Sets a sink that receives all audio produced from now on (e.g. a `WaveFileBufferSink`)
+    pub fn set_audio_buffer_sink(&mut self, audio_buffer_sink: Box<SinkRef<[AudioFrame]>>) {
+        self.audio_buffer_sink = Some(audio_buffer_sink);
+    }
+
+    pub fn run_frames(&mut self, frames: u64) {
+        let target_frames = self.emulated_frames + frames;
+        while self.emulated_frames < target_frames {
+            self.step();
+        }
+        self.flush_audio();
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) {
+        let target_cycles = self.emulated_cycles + cycles;
+        while self.emulated_cycles < target_cycles {
+            self.step();
+        }
+        self.flush_audio();
+    }
+
+    pub fn emulated_frames(&self) -> u64 {
+        self.emulated_frames
+    }
+
+    pub fn emulated_cycles(&self) -> u64 {
+        self.emulated_cycles
+    }
+
+    pub fn last_video_frame(&self) -> Option<&VideoFrame> {
+        self.last_video_frame.as_ref()
+    }
+
+    /// Returns a CRC-32 of the contents of WRAM followed by the used part of SRAM
+    pub fn ram_hash(&mut self) -> u32 {
+        let mut crc32 = Crc32::new();
+        for offset in 0..(WRAM_SIZE as u32) {
+            crc32.update(&[self.virtual_boy.interconnect.read_byte(WRAM_START + offset)]);
+        }
+        crc32.update(self.virtual_boy.interconnect.sram.bytes());
+        crc32.value()
+    }
+
+    fn step(&mut self) {
+        let mut video_frame_sink = MostRecentSink::new();
+        let (cycles, _) = self.virtual_boy.step(&mut video_frame_sink, &mut self.audio_buffer);
+        self.emulated_cycles += cycles as u64;
+
+        if let Some(frame) = video_frame_sink.into_inner() {
+            self.last_video_frame = Some(frame);
+            self.frame_emitted();
+        }
+    }
+
+    fn frame_emitted(&mut self) {
+        let mut movie_finished = false;
+        if let Some(ref mut movie_player) = self.movie_player {
+            movie_finished = !movie_player.apply_next_frame(&mut self.virtual_boy.interconnect.game_pad);
+        }
+        if movie_finished {
+            self.movie_player = None;
+        }
+
+        if self.movie_player.is_none() {
+            self.input_script.apply(self.emulated_frames, &mut self.virtual_boy.interconnect.game_pad);
+        }
+
+        self.emulated_frames += 1;
+
+        self.flush_audio();
+    }
+
+    fn flush_audio(&mut self) {
+        if let Some(ref mut audio_buffer_sink) = self.audio_buffer_sink {
+            audio_buffer_sink.append(&self.audio_buffer.inner);
+        }
+        self.audio_buffer.inner.clear();
+    }
+}
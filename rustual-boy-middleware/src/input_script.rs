@@ -0,0 +1,107 @@
+use rustual_boy_core::game_pad::{Button, GamePad};
+
+use text_parser::parse_error;
+
+use std::io::{self, Read};
+use std::fs::File;
+use std::path::Path;
+
+const INPUT_SCRIPT: &'static str = "Input script";
+
+/// Scripted game pad input for automated runs. Scripts are text files where
+/// each line holds a frame number followed by the buttons held from that
+/// frame on, for example:
+///
+/// ```text
+/// # Press start after the title screen shows up, then walk right
+/// 120 start
+/// 125
+/// 200 lr a
+/// ```
+///
+/// A line with no buttons releases everything. Input for frame `n` is applied
+/// right after the `n`th video frame is emitted, just like `Movie` input.
+/// Button names are `a`, `b`, `start`, `select`, `l`, `r`, and `lu`, `ld`,
+/// `ll`, `lr`, `ru`, `rd`, `rl`, `rr` for the left/right d-pad directions.
+#[derive(Default)]
+pub struct InputScript {
+    events: Vec<(u64, Vec<Button>)>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript {
+            events: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<InputScript> {
+        let mut file = File::open(file_name)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+
+        InputScript::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<InputScript> {
+        let mut ret = InputScript::new();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                _ => line,
+            };
+
+            let mut tokens = line.split_whitespace();
+            let frame = match tokens.next() {
+                Some(token) => token.parse().map_err(|_| parse_error(INPUT_SCRIPT, line_index, format!("Invalid frame number: {}", token)))?,
+                _ => continue,
+            };
+
+            let mut buttons = Vec::new();
+            for token in tokens {
+                buttons.push(button_from_name(token).ok_or_else(|| parse_error(INPUT_SCRIPT, line_index, format!("Unknown button: {}", token)))?);
+            }
+
+            ret.add_event(frame, buttons);
+        }
+
+        Ok(ret)
+    }
+
+    /// Holds `buttons` (and only `buttons`) from `frame` on
+    pub fn add_event(&mut self, frame: u64, buttons: Vec<Button>) {
+        let index = self.events.iter().position(|&(event_frame, _)| event_frame > frame).unwrap_or(self.events.len());
+        self.events.insert(index, (frame, buttons));
+    }
+
+    /// Applies the input for `frame`, if the script changes it on that frame
+    pub fn apply(&self, frame: u64, game_pad: &mut GamePad) {
+        for (_, buttons) in self.events.iter().filter(|&&(event_frame, _)| event_frame == frame) {
+            game_pad.set_buttons(0);
+            for &button in buttons.iter() {
+                game_pad.set_button_pressed(button, true);
+            }
+        }
+    }
+}
+
+fn button_from_name(name: &str) -> Option<Button> {
+    match name.to_lowercase().as_str() {
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "start" => Some(Button::Start),
+        "select" => Some(Button::Select),
+        "l" => Some(Button::L),
+        "r" => Some(Button::R),
+        "lu" => Some(Button::LeftDPadUp),
+        "ld" => Some(Button::LeftDPadDown),
+        "ll" => Some(Button::LeftDPadLeft),
+        "lr" => Some(Button::LeftDPadRight),
+        "ru" => Some(Button::RightDPadUp),
+        "rd" => Some(Button::RightDPadDown),
+        "rl" => Some(Button::RightDPadLeft),
+        "rr" => Some(Button::RightDPadRight),
+        _ => None
+    }
+}
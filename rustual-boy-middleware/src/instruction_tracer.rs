@@ -0,0 +1,241 @@
+use rustual_boy_core::instruction::DecodedInstruction;
+use rustual_boy_core::v810::V810;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::io::{self, BufWriter, Write};
+use std::fs::File;
+use std::path::Path;
+
+const NUM_TRACED_REGS: usize = 37;
+
+const TRACED_SYSTEM_REG_NAMES: [&'static str; 5] = ["eipc", "eipsw", "fepc", "fepsw", "ecr"];
+
+/// The CPU state captured right before an instruction is executed
+pub struct TraceEntry {
+    instruction: DecodedInstruction,
+    regs: [u32; NUM_TRACED_REGS],
+}
+
+/// Writes executed instructions to a text file, one per line:
+///
+/// ```text
+/// 07000200  ac00 0010  jal 2064 (0x07000a10)            r31=07000204 psw=00008000
+/// ```
+///
+/// Each line holds the pc, the raw instruction halfwords, the disassembly,
+/// every register the instruction changed (including registers changed by
+/// an interrupt raised during the instruction), and the resulting PSW.
+/// Tracing can be limited to a pc range and a range of frames; both ranges
+/// are inclusive. Steps where the CPU is halted aren't traced.
+pub struct InstructionTracer {
+    writer: Box<Write>,
+
+    addr_range: Option<(u32, u32)>,
+    frame_range: Option<(u64, u64)>,
+
+    num_traced_instructions: u64,
+}
+
+impl InstructionTracer {
+    pub fn new<W: Write + 'static>(writer: W) -> InstructionTracer {
+        InstructionTracer {
+            writer: Box::new(writer),
+
+            addr_range: None,
+            frame_range: None,
+
+            num_traced_instructions: 0,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(file_name: P) -> io::Result<InstructionTracer> {
+        let file = File::create(file_name)?;
+        Ok(InstructionTracer::new(BufWriter::new(file)))
+    }
+
+    pub fn addr_range(&self) -> Option<(u32, u32)> {
+        self.addr_range
+    }
+
+    pub fn set_addr_range(&mut self, addr_range: Option<(u32, u32)>) {
+        self.addr_range = addr_range;
+    }
+
+    pub fn frame_range(&self) -> Option<(u64, u64)> {
+        self.frame_range
+    }
+
+    pub fn set_frame_range(&mut self, frame_range: Option<(u64, u64)>) {
+        self.frame_range = frame_range;
+    }
+
+    pub fn num_traced_instructions(&self) -> u64 {
+        self.num_traced_instructions
+    }
+
+    /// Captures the instruction about to be executed, if it passes the
+    /// filters. Pass the returned entry to `end` after stepping the CPU.
+    pub fn begin(&self, virtual_boy: &mut VirtualBoy, frame: u64) -> Option<TraceEntry> {
+        if virtual_boy.cpu.is_halted() {
+            return None;
+        }
+
+        if let Some((first_frame, last_frame)) = self.frame_range {
+            if frame < first_frame || frame > last_frame {
+                return None;
+            }
+        }
+
+        let pc = virtual_boy.cpu.reg_pc();
+        if let Some((start, end)) = self.addr_range {
+            if pc < start || pc > end {
+                return None;
+            }
+        }
+
+        let interconnect = &mut virtual_boy.interconnect;
+        Some(TraceEntry {
+            instruction: DecodedInstruction::decode(pc, |addr| interconnect.read_halfword(addr)),
+            regs: traced_regs(&virtual_boy.cpu),
+        })
+    }
+
+    /// Writes the trace line for `entry`, now that its instruction has executed
+    pub fn end(&mut self, entry: TraceEntry, virtual_boy: &VirtualBoy) -> io::Result<()> {
+        let instruction = &entry.instruction;
+        let mut line = format!("{:08x}  {:04x} ", instruction.addr, instruction.first_halfword);
+        match instruction.second_halfword {
+            Some(second_halfword) => line.push_str(&format!("{:04x}", second_halfword)),
+            _ => line.push_str("    "),
+        }
+        line.push_str(&format!("  {:<32}", instruction.to_string()));
+
+        let regs = traced_regs(&virtual_boy.cpu);
+        for (index, (&old_value, &new_value)) in entry.regs.iter().zip(regs.iter()).enumerate() {
+            if old_value != new_value {
+                if index < 32 {
+                    line.push_str(&format!(" r{}={:08x}", index, new_value));
+                } else {
+                    line.push_str(&format!(" {}={:08x}", TRACED_SYSTEM_REG_NAMES[index - 32], new_value));
+                }
+            }
+        }
+
+        writeln!(self.writer, "{} psw={:08x}", line, virtual_boy.cpu.reg_psw())?;
+
+        self.num_traced_instructions += 1;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn traced_regs(cpu: &V810) -> [u32; NUM_TRACED_REGS] {
+    let mut regs = [0; NUM_TRACED_REGS];
+    for (index, reg) in regs[..32].iter_mut().enumerate() {
+        *reg = cpu.reg_gpr(index);
+    }
+    regs[32] = cpu.reg_eipc();
+    regs[33] = cpu.reg_eipsw();
+    regs[34] = cpu.reg_fepc();
+    regs[35] = cpu.reg_fepsw();
+    regs[36] = cpu.reg_ecr();
+    regs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::instruction::{OPCODE_BITS_HALT, OPCODE_BITS_MOV_IMM};
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sram::Sram;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const CODE_START: u32 = 0x05000000;
+
+    // Lets tests read back what the tracer wrote
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(|line| line.to_string()).collect()
+        }
+    }
+
+    fn test_virtual_boy(code: &[u16]) -> VirtualBoy {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+        for (index, &halfword) in code.iter().enumerate() {
+            virtual_boy.interconnect.write_halfword(CODE_START + (index as u32) * 2, halfword);
+        }
+        virtual_boy.cpu.set_reg_pc(CODE_START);
+        virtual_boy
+    }
+
+    fn trace_step(tracer: &mut InstructionTracer, virtual_boy: &mut VirtualBoy, frame: u64) {
+        let entry = tracer.begin(virtual_boy, frame);
+        virtual_boy.cpu.step(&mut virtual_boy.interconnect);
+        if let Some(entry) = entry {
+            tracer.end(entry, virtual_boy).unwrap();
+        }
+    }
+
+    #[test]
+    fn traces_instruction_and_changed_registers() {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = InstructionTracer::new(buffer.clone());
+        let mov = (OPCODE_BITS_MOV_IMM << 10) | (3 << 5) | 0x05;
+        let mut virtual_boy = test_virtual_boy(&[mov]);
+
+        trace_step(&mut tracer, &mut virtual_boy, 0);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0], format!("05000000  {:04x}       {:<32} r3=00000005 psw={:08x}", mov, "mov 5, r3", virtual_boy.cpu.reg_psw()));
+        assert_eq!(tracer.num_traced_instructions(), 1);
+    }
+
+    #[test]
+    fn skips_filtered_and_halted_steps() {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = InstructionTracer::new(buffer.clone());
+        let mut virtual_boy = test_virtual_boy(&[0, 0, OPCODE_BITS_HALT << 10, 0]);
+
+        // Only the second instruction is in range, and only in frames 1-2
+        tracer.set_addr_range(Some((CODE_START + 2, CODE_START + 3)));
+        tracer.set_frame_range(Some((1, 2)));
+        assert!(tracer.begin(&mut virtual_boy, 1).is_none());
+        virtual_boy.cpu.set_reg_pc(CODE_START + 2);
+        assert!(tracer.begin(&mut virtual_boy, 0).is_none());
+        assert!(tracer.begin(&mut virtual_boy, 3).is_none());
+        trace_step(&mut tracer, &mut virtual_boy, 2);
+
+        // Halting is traced, but waiting while halted isn't
+        tracer.set_addr_range(None);
+        trace_step(&mut tracer, &mut virtual_boy, 2);
+        assert!(virtual_boy.cpu.is_halted());
+        trace_step(&mut tracer, &mut virtual_boy, 2);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("05000002  0000       mov r0, r0"));
+        assert!(lines[1].starts_with("05000004  6800       halt"));
+    }
+}
@@ -1,14 +1,51 @@
+// Keep the 2015-edition idioms used throughout the crate
+#![allow(ellipsis_inclusive_range_patterns, bare_trait_objects)]
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes, clippy::new_without_default)]
+#![allow(clippy::from_over_into, clippy::needless_lifetimes, clippy::toplevel_ref_arg, clippy::manual_is_multiple_of)]
+
 extern crate rustual_boy_core;
 
+mod cheats;
 mod color;
+mod crc32;
 mod color_frame;
+mod coverage;
 mod anaglyphizer;
+mod frame_runner;
+mod gdb_stub;
 mod gamma_adjust_sink;
+mod headless_runner;
+mod input_script;
+mod instruction_tracer;
+mod memory_search;
 mod most_recent_sink;
+mod movie;
+mod png;
+mod profiler;
+mod rewind_buffer;
+mod symbols;
+mod text_parser;
+mod vram_viewer;
+mod wave_file_buffer_sink;
 
 // reexports
+pub use cheats::{Cheat, CheatCode, CheatCodeKind, CheatList};
 pub use color::Color;
 pub use color_frame::ColorFrame;
 pub use anaglyphizer::Anaglyphizer;
+pub use coverage::{COVERAGE_EXECUTED, COVERAGE_READ, CoverageRecorder, RomCoverage};
+pub use frame_runner::run_to_next_frame;
 pub use gamma_adjust_sink::GammaAdjustSink;
+pub use gdb_stub::GdbStub;
+pub use headless_runner::HeadlessRunner;
+pub use input_script::InputScript;
+pub use instruction_tracer::{InstructionTracer, TraceEntry};
+pub use memory_search::{MemorySearch, SearchCandidate, SearchFilter, SearchRegion};
 pub use most_recent_sink::MostRecentSink;
+pub use movie::{Movie, MoviePlayer, RomIdentity};
+pub use png::{PngColorType, write_png, write_png_file};
+pub use profiler::Profiler;
+pub use rewind_buffer::RewindBuffer;
+pub use symbols::SymbolTable;
+pub use vram_viewer::{NUM_BGMAPS, NUM_CHARS, NUM_OBJS, NUM_WORLDS, CharEntry, ObjAttribs, VramImage, VramPalette, VramViewer, WorldAttribs, WorldMode};
+pub use wave_file_buffer_sink::WaveFileBufferSink;
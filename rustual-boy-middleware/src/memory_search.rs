@@ -0,0 +1,224 @@
+use rustual_boy_core::hooks::AccessSize;
+use rustual_boy_core::interconnect::Interconnect;
+use rustual_boy_core::mem_map::{CARTRIDGE_RAM_START, WRAM_START};
+use rustual_boy_core::wram::WRAM_SIZE;
+
+use std::fmt;
+
+/// Memory that can be searched
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchRegion {
+    Wram,
+    Sram,
+}
+
+impl SearchRegion {
+    pub fn start(&self) -> u32 {
+        match *self {
+            SearchRegion::Wram => WRAM_START,
+            SearchRegion::Sram => CARTRIDGE_RAM_START,
+        }
+    }
+
+    pub fn len(&self, interconnect: &Interconnect) -> u32 {
+        match *self {
+            SearchRegion::Wram => WRAM_SIZE as u32,
+            SearchRegion::Sram => interconnect.sram.size() as u32,
+        }
+    }
+}
+
+impl fmt::Display for SearchRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SearchRegion::Wram => write!(f, "WRAM"),
+            SearchRegion::Sram => write!(f, "SRAM"),
+        }
+    }
+}
+
+/// How a candidate's current value has to relate to its value when the
+/// search was started or last filtered in order to stay a candidate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchFilter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u32),
+}
+
+impl SearchFilter {
+    fn matches(&self, old_value: u32, new_value: u32) -> bool {
+        match *self {
+            SearchFilter::Equal => new_value == old_value,
+            SearchFilter::Changed => new_value != old_value,
+            SearchFilter::Increased => new_value > old_value,
+            SearchFilter::Decreased => new_value < old_value,
+            SearchFilter::Value(value) => new_value == value,
+        }
+    }
+}
+
+/// An address still matching every filter, along with the value it held
+/// when the search was started or last filtered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchCandidate {
+    pub addr: u32,
+    pub value: u32,
+}
+
+/// Finds the address of a game variable (such as a lives counter) by
+/// snapshotting every aligned value of the given size in WRAM or SRAM, then
+/// repeatedly narrowing the candidates down by how their values changed as
+/// the game ran. Values are compared unsigned.
+pub struct MemorySearch {
+    region: SearchRegion,
+    size: AccessSize,
+    candidates: Vec<SearchCandidate>,
+}
+
+impl MemorySearch {
+    pub fn new(region: SearchRegion, size: AccessSize, interconnect: &mut Interconnect) -> MemorySearch {
+        let start = region.start();
+        let candidates = (0..region.len(interconnect) / size.num_bytes())
+            .map(|index| {
+                let addr = start + index * size.num_bytes();
+                SearchCandidate {
+                    addr: addr,
+                    value: read_value(interconnect, addr, size),
+                }
+            })
+            .collect();
+
+        MemorySearch {
+            region: region,
+            size: size,
+            candidates: candidates,
+        }
+    }
+
+    pub fn region(&self) -> SearchRegion {
+        self.region
+    }
+
+    pub fn size(&self) -> AccessSize {
+        self.size
+    }
+
+    pub fn candidates(&self) -> &[SearchCandidate] {
+        &self.candidates
+    }
+
+    /// Drops the candidates whose current value doesn't match `filter`, and
+    /// updates the remaining ones to their current values
+    pub fn filter(&mut self, filter: SearchFilter, interconnect: &mut Interconnect) {
+        let size = self.size;
+        self.candidates = self.candidates.iter()
+            .filter_map(|candidate| {
+                let value = read_value(interconnect, candidate.addr, size);
+                if filter.matches(candidate.value, value) {
+                    Some(SearchCandidate {
+                        addr: candidate.addr,
+                        value: value,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Reads the current value of a candidate
+    pub fn read(&self, interconnect: &mut Interconnect, addr: u32) -> u32 {
+        read_value(interconnect, addr, self.size)
+    }
+}
+
+fn read_value(interconnect: &mut Interconnect, addr: u32, size: AccessSize) -> u32 {
+    match size {
+        AccessSize::Byte => interconnect.read_byte(addr) as u32,
+        AccessSize::Halfword => interconnect.read_halfword(addr) as u32,
+        AccessSize::Word => (interconnect.read_halfword(addr) as u32) | ((interconnect.read_halfword(addr + 2) as u32) << 16),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sram::Sram;
+
+    fn test_interconnect() -> Interconnect {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        Interconnect::new(rom, Sram::new())
+    }
+
+    fn candidate_addrs(search: &MemorySearch) -> Vec<u32> {
+        search.candidates().iter().map(|candidate| candidate.addr).collect()
+    }
+
+    #[test]
+    fn snapshots_every_aligned_value() {
+        let mut interconnect = test_interconnect();
+        interconnect.write_halfword(WRAM_START + 2, 0x1234);
+
+        let search = MemorySearch::new(SearchRegion::Wram, AccessSize::Halfword, &mut interconnect);
+        assert_eq!(search.candidates().len(), WRAM_SIZE / 2);
+        assert_eq!(search.candidates()[1], SearchCandidate { addr: WRAM_START + 2, value: 0x1234 });
+
+        // SRAM only covers what's been used so far
+        let search = MemorySearch::new(SearchRegion::Sram, AccessSize::Word, &mut interconnect);
+        assert!(search.candidates().is_empty());
+        interconnect.write_byte(CARTRIDGE_RAM_START + 0x100, 0);
+        let search = MemorySearch::new(SearchRegion::Sram, AccessSize::Word, &mut interconnect);
+        assert_eq!(search.candidates().len(), interconnect.sram.size() / 4);
+    }
+
+    #[test]
+    fn filters_narrow_candidates() {
+        let mut interconnect = test_interconnect();
+        let lives = WRAM_START + 0x10;
+        let timer = WRAM_START + 0x20;
+        interconnect.write_byte(lives, 3);
+        interconnect.write_byte(timer, 3);
+
+        let mut search = MemorySearch::new(SearchRegion::Wram, AccessSize::Byte, &mut interconnect);
+        search.filter(SearchFilter::Equal, &mut interconnect);
+        assert_eq!(search.candidates().len(), WRAM_SIZE);
+
+        interconnect.write_byte(lives, 2);
+        interconnect.write_byte(timer, 4);
+        search.filter(SearchFilter::Changed, &mut interconnect);
+        assert_eq!(candidate_addrs(&search), vec![lives, timer]);
+
+        // Candidates compare against their value as of the last filter
+        interconnect.write_byte(timer, 5);
+        search.filter(SearchFilter::Decreased, &mut interconnect);
+        assert_eq!(search.candidates(), &[]);
+
+        let mut search = MemorySearch::new(SearchRegion::Wram, AccessSize::Byte, &mut interconnect);
+        interconnect.write_byte(lives, 3);
+        interconnect.write_byte(timer, 6);
+        search.filter(SearchFilter::Increased, &mut interconnect);
+        assert_eq!(candidate_addrs(&search), vec![lives, timer]);
+
+        search.filter(SearchFilter::Value(3), &mut interconnect);
+        assert_eq!(search.candidates(), &[SearchCandidate { addr: lives, value: 3 }]);
+        assert_eq!(search.read(&mut interconnect, lives), 3);
+    }
+
+    #[test]
+    fn word_values_are_little_endian_and_unsigned() {
+        let mut interconnect = test_interconnect();
+        let addr = WRAM_START + 0x40;
+        interconnect.write_halfword(addr, 0x0001);
+        interconnect.write_halfword(addr + 2, 0x7fff);
+
+        let mut search = MemorySearch::new(SearchRegion::Wram, AccessSize::Word, &mut interconnect);
+        interconnect.write_halfword(addr + 2, 0x8000);
+        search.filter(SearchFilter::Increased, &mut interconnect);
+        assert_eq!(search.candidates(), &[SearchCandidate { addr: addr, value: 0x80000001 }]);
+    }
+}
@@ -0,0 +1,313 @@
+use crc32::Crc32;
+use frame_runner::run_to_next_frame;
+
+use rustual_boy_core::game_pad::GamePad;
+use rustual_boy_core::rom::Rom;
+use rustual_boy_core::sinks::{AudioFrame, Sink, VideoFrame};
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::io::{self, Read, Write, Error, ErrorKind};
+use std::fs::File;
+use std::path::Path;
+
+const MOVIE_MAGIC: &'static [u8; 4] = b"RBMV";
+const MOVIE_VERSION: u32 = 1;
+
+/// Identifies the exact ROM a movie was recorded with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomIdentity {
+    pub name: String,
+    pub maker_code: String,
+    pub game_code: String,
+    pub game_version_byte: u8,
+    pub size: u32,
+    pub crc32: u32,
+}
+
+impl RomIdentity {
+    pub fn from_rom(rom: &Rom) -> RomIdentity {
+        let mut crc32 = Crc32::new();
+        for addr in 0..rom.size() {
+            crc32.update(&[rom.read_byte(addr as _)]);
+        }
+
+        RomIdentity {
+            name: rom.name().unwrap_or_default(),
+            maker_code: rom.maker_code().unwrap_or_default(),
+            game_code: rom.game_code().unwrap_or_default(),
+            game_version_byte: rom.game_version_byte(),
+            size: rom.size() as _,
+            crc32: crc32.value(),
+        }
+    }
+}
+
+/// A recording of the game pad state for each emulated frame. Movies always
+/// start from power-on with blank SRAM; the input for frame `n` is applied
+/// right after the VIP emits its `n`th video frame (see `run_to_next_frame`).
+pub struct Movie {
+    rom_identity: RomIdentity,
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(rom_identity: RomIdentity) -> Movie {
+        Movie {
+            rom_identity: rom_identity,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<Movie> {
+        let mut file = File::open(file_name)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        Movie::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Movie> {
+        let mut reader = MovieReader {
+            bytes: bytes,
+            pos: 0,
+        };
+
+        if reader.read_bytes(4)? != MOVIE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a movie file"));
+        }
+        let version = reader.read_u32()?;
+        if version != MOVIE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported movie version: {}", version)));
+        }
+
+        let rom_identity = RomIdentity {
+            name: reader.read_string()?,
+            maker_code: reader.read_string()?,
+            game_code: reader.read_string()?,
+            game_version_byte: reader.read_bytes(1)?[0],
+            size: reader.read_u32()?,
+            crc32: reader.read_u32()?,
+        };
+
+        let num_frames = reader.read_u32()? as usize;
+        let mut frames = Vec::with_capacity(num_frames.min(reader.remaining() / 2));
+        for _ in 0..num_frames {
+            let frame_bytes = reader.read_bytes(2)?;
+            frames.push(u16::from_le_bytes([frame_bytes[0], frame_bytes[1]]));
+        }
+
+        Ok(Movie {
+            rom_identity: rom_identity,
+            frames: frames,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        file.write_all(&self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MOVIE_MAGIC);
+        bytes.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+
+        write_string(&mut bytes, &self.rom_identity.name);
+        write_string(&mut bytes, &self.rom_identity.maker_code);
+        write_string(&mut bytes, &self.rom_identity.game_code);
+        bytes.push(self.rom_identity.game_version_byte);
+        bytes.extend_from_slice(&self.rom_identity.size.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_identity.crc32.to_le_bytes());
+
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for &frame in self.frames.iter() {
+            bytes.extend_from_slice(&frame.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn rom_identity(&self) -> &RomIdentity {
+        &self.rom_identity
+    }
+
+    /// Returns an error if the movie wasn't recorded with the given ROM
+    pub fn check_rom_identity(&self, rom_identity: &RomIdentity) -> io::Result<()> {
+        if self.rom_identity != *rom_identity {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Movie was recorded with a different ROM (\"{}\", {}{}, version 1.{:02}, crc32 0x{:08x})",
+                self.rom_identity.name,
+                self.rom_identity.maker_code,
+                self.rom_identity.game_code,
+                self.rom_identity.game_version_byte,
+                self.rom_identity.crc32)));
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frame(&self, index: usize) -> Option<u16> {
+        self.frames.get(index).cloned()
+    }
+
+    /// Records the game pad state for the next frame
+    pub fn record_frame(&mut self, game_pad: &GamePad) {
+        self.frames.push(game_pad.buttons());
+    }
+}
+
+/// Feeds the input recorded in a Movie back into the core
+pub struct MoviePlayer {
+    movie: Movie,
+    frame_index: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer {
+            movie: movie,
+            frame_index: 0,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame_index >= self.movie.len()
+    }
+
+    /// Applies the input for the frame that was just emitted. Returns false
+    /// (and leaves the game pad untouched) once the movie has run out of frames.
+    pub fn apply_next_frame(&mut self, game_pad: &mut GamePad) -> bool {
+        match self.movie.frame(self.frame_index) {
+            Some(buttons) => {
+                game_pad.set_buttons(buttons);
+                self.frame_index += 1;
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Runs the VirtualBoy up to the next video frame and applies that frame's
+    /// input. Returns false once the movie has run out of frames.
+    pub fn run_frame(&mut self, virtual_boy: &mut VirtualBoy, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+
+        run_to_next_frame(virtual_boy, video_frame_sink, audio_frame_sink);
+        self.apply_next_frame(&mut virtual_boy.interconnect.game_pad)
+    }
+}
+
+struct MovieReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MovieReader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn read_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected end of movie file"));
+        }
+
+        let ret = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(ret)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid string in movie file"))
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_movie() -> Movie {
+        let mut movie = Movie::new(RomIdentity {
+            name: "テスト GAME".to_string(),
+            maker_code: "01".to_string(),
+            game_code: "VTST".to_string(),
+            game_version_byte: 2,
+            size: 0x00100000,
+            crc32: 0xdeadbeef,
+        });
+        movie.frames = vec![0x0000, 0x0002, 0x8001, 0xffff];
+        movie
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let movie = test_movie();
+        let bytes = movie.to_bytes();
+
+        let loaded = Movie::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.rom_identity(), movie.rom_identity());
+        assert_eq!(loaded.frames, movie.frames);
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn load_rejects_bad_header() {
+        let mut bytes = test_movie().to_bytes();
+        bytes[0] = b'X';
+        assert!(Movie::from_bytes(&bytes).is_err());
+
+        let mut bytes = test_movie().to_bytes();
+        bytes[4] = 2;
+        assert!(Movie::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn load_rejects_truncated_files() {
+        let bytes = test_movie().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Movie::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn check_rom_identity_rejects_other_roms() {
+        let movie = test_movie();
+        assert!(movie.check_rom_identity(movie.rom_identity()).is_ok());
+
+        let mut other = movie.rom_identity().clone();
+        other.crc32 ^= 1;
+        assert!(movie.check_rom_identity(&other).is_err());
+    }
+}
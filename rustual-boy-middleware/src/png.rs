@@ -0,0 +1,123 @@
+use crc32::Crc32;
+
+use std::io::{self, Write, Error, ErrorKind};
+use std::fs::File;
+use std::path::Path;
+
+const PNG_SIGNATURE: &'static [u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+// Largest block size allowed for stored (uncompressed) deflate blocks
+const MAX_STORED_BLOCK_SIZE: usize = 65535;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PngColorType {
+    /// One byte per pixel
+    Grayscale,
+    /// Three bytes (R, G, B) per pixel
+    Rgb,
+}
+
+impl PngColorType {
+    fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            PngColorType::Grayscale => 1,
+            PngColorType::Rgb => 3,
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match *self {
+            PngColorType::Grayscale => 0,
+            PngColorType::Rgb => 2,
+        }
+    }
+}
+
+/// Writes an 8-bit PNG image. The image data is stored uncompressed, which
+/// keeps this dependency-free; it's meant for debug dumps, not distribution.
+pub fn write_png<W: Write>(writer: &mut W, width: u32, height: u32, color_type: PngColorType, pixels: &[u8]) -> io::Result<()> {
+    let stride = (width as usize) * color_type.bytes_per_pixel();
+    if pixels.len() != stride * (height as usize) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Pixel data doesn't match image dimensions"));
+    }
+
+    writer.write_all(PNG_SIGNATURE)?;
+
+    let mut header = Vec::new();
+    push_u32(&mut header, width);
+    push_u32(&mut header, height);
+    header.push(8); // Bit depth
+    header.push(color_type.code());
+    header.push(0); // Compression method
+    header.push(0); // Filter method
+    header.push(0); // Interlace method
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // Each scanline is prefixed with its filter type (0 = none)
+    let mut scanlines = Vec::with_capacity((stride + 1) * (height as usize));
+    for row in pixels.chunks(stride) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut data = Vec::new();
+    data.push(0x78); // zlib header: deflate, 32k window
+    data.push(0x01);
+    let num_blocks = scanlines.len().div_ceil(MAX_STORED_BLOCK_SIZE);
+    for (i, block) in scanlines.chunks(MAX_STORED_BLOCK_SIZE).enumerate() {
+        data.push(if i == num_blocks - 1 { 1 } else { 0 });
+        let len = block.len() as u16;
+        data.push(len as _);
+        data.push((len >> 8) as _);
+        data.push(!len as _);
+        data.push((!len >> 8) as _);
+        data.extend_from_slice(block);
+    }
+    push_u32(&mut data, adler32(&scanlines));
+    write_chunk(writer, b"IDAT", &data)?;
+
+    write_chunk(writer, b"IEND", &[])
+}
+
+pub fn write_png_file<P: AsRef<Path>>(file_name: P, width: u32, height: u32, color_type: PngColorType, pixels: &[u8]) -> io::Result<()> {
+    let mut file = File::create(file_name)?;
+    write_png(&mut file, width, height, color_type, pixels)
+}
+
+fn write_chunk<W: Write>(writer: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut len = Vec::new();
+    push_u32(&mut len, data.len() as _);
+    writer.write_all(&len)?;
+
+    let mut crc32 = Crc32::new();
+    crc32.update(chunk_type);
+    crc32.update(data);
+
+    writer.write_all(chunk_type)?;
+    writer.write_all(data)?;
+
+    let mut crc = Vec::new();
+    push_u32(&mut crc, crc32.value());
+    writer.write_all(&crc)
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let mut a = 1;
+    let mut b = 0;
+    for &byte in bytes {
+        a = (a + (byte as u32)) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+// PNG uses big-endian integers throughout
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.push((value >> 24) as _);
+    bytes.push((value >> 16) as _);
+    bytes.push((value >> 8) as _);
+    bytes.push(value as _);
+}
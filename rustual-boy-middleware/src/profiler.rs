@@ -0,0 +1,317 @@
+use symbols::SymbolTable;
+
+use rustual_boy_core::v810::V810;
+use rustual_boy_core::vip::DISPLAY_FRAME_PERIOD;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const NUM_HOT_SPOTS: usize = 20;
+
+/// Exact profile of where emulated CPU cycles are spent. Every step's cycles
+/// are attributed to the pc of the instruction that ran and to the CPU's
+/// shadow call stack at the time, where each function is identified by the
+/// address it was called at (or the handler address, for exceptions).
+/// Cycles spent outside of any tracked call are attributed to `(root)`.
+pub struct Profiler {
+    stacks: Vec<Vec<u32>>,
+    stack_ids: HashMap<Vec<u32>, usize>,
+    stack_cycles: Vec<u64>,
+    current_stack: usize,
+
+    current_pc: u32,
+    pc_cycles: HashMap<u32, u64>,
+
+    total_cycles: u64,
+    num_frames: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let mut stack_ids = HashMap::new();
+        stack_ids.insert(Vec::new(), 0);
+
+        Profiler {
+            stacks: vec![Vec::new()],
+            stack_ids: stack_ids,
+            stack_cycles: vec![0],
+            current_stack: 0,
+
+            current_pc: 0,
+            pc_cycles: HashMap::new(),
+
+            total_cycles: 0,
+            num_frames: 0,
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    /// Captures the pc and call stack of the instruction about to be executed
+    pub fn begin_step(&mut self, cpu: &V810) {
+        self.current_pc = cpu.reg_pc();
+
+        let call_stack = cpu.call_stack();
+        let is_current_stack = {
+            let stack = &self.stacks[self.current_stack];
+            stack.len() == call_stack.len() &&
+                stack.iter().zip(call_stack.iter()).all(|(&addr, frame)| addr == frame.target_addr)
+        };
+        if is_current_stack {
+            return;
+        }
+
+        let stack = call_stack.iter().map(|frame| frame.target_addr).collect::<Vec<_>>();
+        self.current_stack = match self.stack_ids.get(&stack) {
+            Some(&id) => id,
+            _ => {
+                let id = self.stacks.len();
+                self.stacks.push(stack.clone());
+                self.stack_ids.insert(stack, id);
+                self.stack_cycles.push(0);
+                id
+            }
+        };
+    }
+
+    /// Attributes the cycles taken by the step started with `begin_step`
+    pub fn end_step(&mut self, cycles: u32) {
+        let cycles = cycles as u64;
+        *self.pc_cycles.entry(self.current_pc).or_insert(0) += cycles;
+        self.stack_cycles[self.current_stack] += cycles;
+        self.total_cycles += cycles;
+    }
+
+    pub fn frame_emitted(&mut self) {
+        self.num_frames += 1;
+    }
+
+    /// Writes each function's self and inclusive cycles, followed by the
+    /// hottest individual instructions
+    pub fn write_flat_profile<W: Write>(&self, symbols: &SymbolTable, w: &mut W) -> io::Result<()> {
+        self.write_summary(w)?;
+
+        let mut functions = HashMap::new();
+        for (stack, cycles) in self.stacks_with_cycles() {
+            functions.entry(stack.last().cloned()).or_insert((0, 0)).0 += cycles;
+
+            let mut seen = Vec::new();
+            for function in Some(None).into_iter().chain(stack.iter().map(|&addr| Some(addr))) {
+                if !seen.contains(&function) {
+                    functions.entry(function).or_insert((0, 0)).1 += cycles;
+                    seen.push(function);
+                }
+            }
+        }
+        let mut functions = functions.into_iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then((b.1).1.cmp(&(a.1).1)));
+
+        writeln!(w)?;
+        writeln!(w, "  self cycles      %   total cycles      %  function")?;
+        for (function, (self_cycles, total_cycles)) in functions {
+            writeln!(w, "{:>13} {:>6} {:>14} {:>6}  {}",
+                self_cycles, self.percent(self_cycles),
+                total_cycles, self.percent(total_cycles),
+                function_name(symbols, function))?;
+        }
+
+        let mut pcs = self.pc_cycles.iter().map(|(&pc, &cycles)| (pc, cycles)).collect::<Vec<_>>();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        writeln!(w)?;
+        writeln!(w, "       cycles      %  pc")?;
+        for (pc, cycles) in pcs.into_iter().take(NUM_HOT_SPOTS) {
+            writeln!(w, "{:>13} {:>6}  {}", cycles, self.percent(cycles), symbols.format_addr(pc))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes, for each function, its inclusive cycles along with the cycles
+    /// spent in it per caller and in each of its callees
+    pub fn write_call_graph<W: Write>(&self, symbols: &SymbolTable, w: &mut W) -> io::Result<()> {
+        self.write_summary(w)?;
+
+        let mut functions = HashMap::new();
+        functions.insert(None, self.total_cycles);
+        let mut edges = HashMap::new();
+        for (stack, cycles) in self.stacks_with_cycles() {
+            let mut seen_functions = Vec::new();
+            let mut seen_edges = Vec::new();
+            for (index, &callee) in stack.iter().enumerate() {
+                if !seen_functions.contains(&callee) {
+                    *functions.entry(Some(callee)).or_insert(0) += cycles;
+                    seen_functions.push(callee);
+                }
+
+                let edge = (if index == 0 { None } else { Some(stack[index - 1]) }, callee);
+                if !seen_edges.contains(&edge) {
+                    *edges.entry(edge).or_insert(0) += cycles;
+                    seen_edges.push(edge);
+                }
+            }
+        }
+        let mut functions = functions.into_iter().collect::<Vec<_>>();
+        functions.sort_by_key(|&(_, cycles)| Reverse(cycles));
+
+        writeln!(w)?;
+        writeln!(w, "       cycles      %  function (<- caller, -> callee)")?;
+        for (function, cycles) in functions {
+            writeln!(w)?;
+            writeln!(w, "{:>13} {:>6}  {}", cycles, self.percent(cycles), function_name(symbols, function))?;
+
+            let mut callers = edges.iter().filter(|&(&(_, callee), _)| Some(callee) == function).collect::<Vec<_>>();
+            callers.sort_by(|a, b| b.1.cmp(a.1));
+            for (&(caller, _), &cycles) in callers {
+                writeln!(w, "{:>13} {:>6}    <- {}", cycles, self.percent(cycles), function_name(symbols, caller))?;
+            }
+
+            let mut callees = edges.iter().filter(|&(&(caller, _), _)| caller == function).collect::<Vec<_>>();
+            callees.sort_by(|a, b| b.1.cmp(a.1));
+            for (&(_, callee), &cycles) in callees {
+                writeln!(w, "{:>13} {:>6}    -> {}", cycles, self.percent(cycles), function_name(symbols, Some(callee)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the profile in the folded stack format used by flamegraph tools,
+    /// one line per call stack with its self cycles
+    pub fn write_folded_stacks<W: Write>(&self, symbols: &SymbolTable, w: &mut W) -> io::Result<()> {
+        let mut lines = self.stacks_with_cycles()
+            .map(|(stack, cycles)| {
+                let names = Some(function_name(symbols, None)).into_iter()
+                    .chain(stack.iter().map(|&addr| function_name(symbols, Some(addr))))
+                    .collect::<Vec<_>>();
+                (names.join(";"), cycles)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+
+        for (stack, cycles) in lines {
+            writeln!(w, "{} {}", stack, cycles)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_summary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "Profiled {} cycles", self.total_cycles)?;
+        if let Some(frame_cycles) = self.total_cycles.checked_div(self.num_frames) {
+            write!(w, " over {} frames ({} cycles per frame, {:.1}% of the frame time)",
+                self.num_frames, frame_cycles, (frame_cycles as f64 / DISPLAY_FRAME_PERIOD as f64) * 100.0)?;
+        }
+        writeln!(w)
+    }
+
+    fn stacks_with_cycles<'a>(&'a self) -> Box<Iterator<Item = (&'a [u32], u64)> + 'a> {
+        Box::new(self.stacks.iter().zip(self.stack_cycles.iter())
+            .filter(|&(_, &cycles)| cycles > 0)
+            .map(|(stack, &cycles)| (&stack[..], cycles)))
+    }
+
+    fn percent(&self, cycles: u64) -> String {
+        if self.total_cycles == 0 {
+            return "-".into();
+        }
+        format!("{:.1}%", (cycles as f64 / self.total_cycles as f64) * 100.0)
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+fn function_name(symbols: &SymbolTable, addr: Option<u32>) -> String {
+    let addr = match addr {
+        Some(addr) => addr,
+        _ => return "(root)".into(),
+    };
+
+    match symbols.nearest(addr) {
+        Some((name, symbol_addr)) if symbol_addr == addr => name.into(),
+        Some((name, symbol_addr)) => format!("{}+0x{:x}", name, addr - symbol_addr),
+        _ => format!("0x{:08x}", addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::instruction::{OPCODE_BITS_JAL, OPCODE_BITS_JMP};
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sram::Sram;
+    use rustual_boy_core::virtual_boy::VirtualBoy;
+
+    const CODE_START: u32 = 0x05000000;
+    const SUBROUTINE: u32 = CODE_START + 0x10;
+
+    // Runs `jal SUBROUTINE; nop` at the root, where the subroutine is `nop; jmp [r31]`,
+    //  and returns the cycles taken by each step
+    fn profile_call(profiler: &mut Profiler) -> Vec<u64> {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+        let code = [(CODE_START, OPCODE_BITS_JAL << 10), (CODE_START + 2, 0x0010), (CODE_START + 4, 0), (SUBROUTINE, 0), (SUBROUTINE + 2, (OPCODE_BITS_JMP << 10) | 31)];
+        for &(addr, halfword) in code.iter() {
+            virtual_boy.interconnect.write_halfword(addr, halfword);
+        }
+        virtual_boy.cpu.set_reg_pc(CODE_START);
+
+        let mut step_cycles = Vec::new();
+        for _ in 0..4 {
+            profiler.begin_step(&virtual_boy.cpu);
+            let (cycles, _) = virtual_boy.cpu.step(&mut virtual_boy.interconnect);
+            profiler.end_step(cycles);
+            step_cycles.push(cycles as u64);
+        }
+        assert_eq!(virtual_boy.cpu.reg_pc(), CODE_START + 6);
+        step_cycles
+    }
+
+    #[test]
+    fn attributes_cycles_to_call_stacks() {
+        let mut profiler = Profiler::new();
+        let step_cycles = profile_call(&mut profiler);
+        assert_eq!(profiler.total_cycles(), step_cycles.iter().sum::<u64>());
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("sub".into(), SUBROUTINE);
+        let mut folded = Vec::new();
+        profiler.write_folded_stacks(&symbols, &mut folded).unwrap();
+
+        // The call itself belongs to the caller, and the return to the callee
+        let root_cycles = step_cycles[0] + step_cycles[3];
+        let sub_cycles = step_cycles[1] + step_cycles[2];
+        assert_eq!(String::from_utf8(folded).unwrap(), format!("(root) {}\n(root);sub {}\n", root_cycles, sub_cycles));
+    }
+
+    #[test]
+    fn flat_profile_lists_self_and_total_cycles() {
+        let mut profiler = Profiler::new();
+        let step_cycles = profile_call(&mut profiler);
+        profiler.frame_emitted();
+        let total_cycles = profiler.total_cycles();
+
+        let mut flat = Vec::new();
+        profiler.write_flat_profile(&SymbolTable::new(), &mut flat).unwrap();
+        let flat = String::from_utf8(flat).unwrap();
+        assert!(flat.starts_with(&format!("Profiled {} cycles over 1 frames ({} cycles per frame", total_cycles, total_cycles)));
+
+        let sub_cycles = step_cycles[1] + step_cycles[2];
+        let sub_line = format!("{:>13} {:>6} {:>14} {:>6}  0x{:08x}", sub_cycles, profiler.percent(sub_cycles), sub_cycles, profiler.percent(sub_cycles), SUBROUTINE);
+        let root_line = format!("{:>13} {:>6} {:>14} {:>6}  (root)", total_cycles - sub_cycles, profiler.percent(total_cycles - sub_cycles), total_cycles, "100.0%");
+        assert!(flat.lines().any(|line| line == sub_line), "{}", flat);
+        assert!(flat.lines().any(|line| line == root_line), "{}", flat);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+
+// Spans of unchanged bytes shorter than this are folded into the surrounding
+//  literal, since each span costs 8 bytes of header in the encoded delta.
+const MIN_SKIP_LENGTH: usize = 16;
+
+/// A rolling history of save states (as produced by `VirtualBoy::save_state`).
+/// Only the most recent state is kept in full; every older state is stored
+/// as a delta against the state that came after it, so memory that stays
+/// unchanged between snapshots (most of VRAM and SRAM) costs next to nothing.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Box<[u8]>>,
+}
+
+impl RewindBuffer {
+    /// Create a new RewindBuffer that holds at most `capacity` states.
+    /// Once full, the oldest states are discarded.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of states currently held
+    pub fn len(&self) -> usize {
+        if self.latest.is_some() {
+            self.deltas.len() + 1
+        } else {
+            0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Returns the total number of bytes used by the stored states
+    pub fn size_in_bytes(&self) -> usize {
+        self.latest.as_ref().map_or(0, |latest| latest.len()) +
+        self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Record a new state, making it the most recent one
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    /// Remove and return the most recent state. The state before it (if any)
+    /// becomes the most recent one.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let ret = self.latest.take();
+
+        if let Some(ref latest) = ret {
+            if let Some(delta) = self.deltas.pop_back() {
+                self.latest = Some(apply_delta(latest, &delta));
+            }
+        }
+
+        ret
+    }
+}
+
+// A delta is the target length followed by a list of (skip, length, bytes)
+//  records. Each record skips `skip` unchanged bytes, then XOR's `length`
+//  bytes from the record into the base. All lengths are little-endian u32's.
+fn encode_delta(base: &[u8], target: &[u8]) -> Box<[u8]> {
+    const CHUNK_SIZE: usize = 64;
+
+    let mut ret = Vec::new();
+    ret.extend_from_slice(&(target.len() as u32).to_le_bytes());
+
+    // Bytes past the end of the base are treated as zero
+    let byte_at = |index: usize| if index < base.len() { base[index] } else { 0 };
+    let common_len = if base.len() < target.len() { base.len() } else { target.len() };
+
+    let len = target.len();
+    let mut pos = 0;
+    while pos < len {
+        let skip_start = pos;
+        while pos + CHUNK_SIZE <= common_len && base[pos..pos + CHUNK_SIZE] == target[pos..pos + CHUNK_SIZE] {
+            pos += CHUNK_SIZE;
+        }
+        while pos < len && byte_at(pos) == target[pos] {
+            pos += 1;
+        }
+        if pos == len {
+            break;
+        }
+
+        let literal_start = pos;
+        let mut literal_end = pos;
+        while pos < len && pos - literal_end < MIN_SKIP_LENGTH {
+            if byte_at(pos) != target[pos] {
+                literal_end = pos + 1;
+            }
+            pos += 1;
+        }
+        pos = literal_end;
+
+        ret.extend_from_slice(&((literal_start - skip_start) as u32).to_le_bytes());
+        ret.extend_from_slice(&((literal_end - literal_start) as u32).to_le_bytes());
+        for (index, &byte) in (literal_start..literal_end).zip(target[literal_start..literal_end].iter()) {
+            ret.push(byte_at(index) ^ byte);
+        }
+    }
+
+    ret.into_boxed_slice()
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut ret = base.to_vec();
+
+    let target_len = read_u32(delta, 0) as usize;
+    ret.resize(target_len, 0);
+
+    let mut delta_pos = 4;
+    let mut pos = 0;
+    while delta_pos < delta.len() {
+        pos += read_u32(delta, delta_pos) as usize;
+        let len = read_u32(delta, delta_pos + 4) as usize;
+        delta_pos += 8;
+
+        for (output, input) in ret[pos..pos + len].iter_mut().zip(delta[delta_pos..delta_pos + len].iter()) {
+            *output ^= *input;
+        }
+        pos += len;
+        delta_pos += len;
+    }
+
+    ret
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[pos..pos + 4]);
+    u32::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], target: &[u8]) {
+        let delta = encode_delta(base, target);
+        assert_eq!(apply_delta(base, &delta), target);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut target = base.clone();
+        target[3] = 0xff;
+        target[500] ^= 0x55;
+        target[501] ^= 0x55;
+        target[999] = 0;
+
+        round_trip(&base, &target);
+        round_trip(&target, &base);
+        round_trip(&base, &base);
+        round_trip(&base, &[]);
+        round_trip(&[], &base);
+        round_trip(&base, &base[..700]);
+        round_trip(&base[..700], &target);
+    }
+
+    #[test]
+    fn unchanged_bytes_are_not_stored() {
+        let base = vec![0xaa; 64 * 1024];
+        let mut target = base.clone();
+        target[1234] = 0;
+
+        assert!(encode_delta(&base, &target).len() < 32);
+    }
+
+    #[test]
+    fn pop_returns_states_newest_first() {
+        let states = [vec![1, 2, 3], vec![1, 2, 4], vec![9; 40], vec![]];
+
+        let mut buffer = RewindBuffer::new(10);
+        for state in states.iter() {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), states.len());
+
+        for state in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn oldest_states_are_discarded_at_capacity() {
+        let mut buffer = RewindBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(vec![i; 8]);
+        }
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.pop(), Some(vec![4; 8]));
+        assert_eq!(buffer.pop(), Some(vec![3; 8]));
+        assert_eq!(buffer.pop(), Some(vec![2; 8]));
+        assert_eq!(buffer.pop(), None);
+    }
+}
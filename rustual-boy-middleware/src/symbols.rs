@@ -0,0 +1,427 @@
+use rustual_boy_core::instruction::InstructionFormatter;
+
+use text_parser::{parse_error, parse_hex};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write, Error, ErrorKind};
+use std::fs::File;
+use std::path::Path;
+
+const SYMBOL_FILE: &'static str = "Symbol file";
+
+const ELF_MAGIC: &'static [u8] = b"\x7fELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+
+const SECTION_TYPE_SYMTAB: u32 = 2;
+const SECTION_HEADER_SIZE: usize = 40;
+
+const SYMBOL_SIZE: usize = 16;
+const SYMBOL_TYPE_SECTION: u8 = 3;
+const SYMBOL_TYPE_FILE: u8 = 4;
+const SECTION_INDEX_UNDEFINED: u16 = 0;
+
+/// Named addresses used by the debugger. Symbols can be loaded from ELF files
+/// produced by the v810 gcc toolchain, or from text map files where each line
+/// holds a name followed by a hexadecimal address, for example:
+///
+/// ```text
+/// # Game entry points
+/// main 0x07000200
+/// vblank_handler 0x07000a10
+/// ```
+///
+/// Names are identifiers (letters, digits and underscores, not starting with a
+/// digit). Several names can refer to the same address.
+#[derive(Default)]
+pub struct SymbolTable {
+    addrs: HashMap<String, u32>,
+    names: BTreeMap<u32, Vec<String>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            addrs: HashMap::new(),
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Loads symbols from an ELF or map file, detected from its contents
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<SymbolTable> {
+        let mut file = File::open(file_name)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        if bytes.starts_with(ELF_MAGIC) {
+            SymbolTable::parse_elf(&bytes)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidData, "Symbol file is neither ELF nor text"))?;
+            SymbolTable::parse_map(&text)
+        }
+    }
+
+    /// Saves symbols as a map file, sorted by address
+    pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        for (name, addr) in self.iter() {
+            writeln!(file, "{} 0x{:08x}", name, addr)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_map(text: &str) -> io::Result<SymbolTable> {
+        let mut ret = SymbolTable::new();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                _ => line,
+            };
+
+            let mut tokens = line.split_whitespace();
+            let name = match tokens.next() {
+                Some(token) if token.starts_with('.') => &token[1..],
+                Some(token) => token,
+                _ => continue,
+            };
+            if !is_valid_name(name) {
+                return Err(parse_error(SYMBOL_FILE, line_index, format!("Invalid symbol name: {}", name)));
+            }
+
+            let addr = match tokens.next() {
+                Some(token) => parse_hex(token).ok_or_else(|| parse_error(SYMBOL_FILE, line_index, format!("Invalid address: {}", token)))?,
+                _ => return Err(parse_error(SYMBOL_FILE, line_index, format!("Missing address for symbol: {}", name))),
+            };
+
+            if let Some(token) = tokens.next() {
+                return Err(parse_error(SYMBOL_FILE, line_index, format!("Unexpected token: {}", token)));
+            }
+
+            ret.insert(name.into(), addr);
+        }
+
+        Ok(ret)
+    }
+
+    /// Reads the symbol tables of a 32-bit little endian ELF file. Section,
+    /// file and undefined symbols are skipped, as are names that aren't valid
+    /// identifiers (such as compiler-generated local labels).
+    pub fn parse_elf(bytes: &[u8]) -> io::Result<SymbolTable> {
+        if !bytes.starts_with(ELF_MAGIC) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not an ELF file"));
+        }
+        if bytes.len() < 0x34 || bytes[4] != ELF_CLASS_32 || bytes[5] != ELF_DATA_LITTLE_ENDIAN {
+            return Err(Error::new(ErrorKind::InvalidData, "Unsupported ELF file (expected 32-bit little endian)"));
+        }
+
+        let section_headers_offset = read_u32(bytes, 0x20)? as usize;
+        let section_header_size = read_u16(bytes, 0x2e)? as usize;
+        let num_sections = read_u16(bytes, 0x30)? as usize;
+        if num_sections > 0 && section_header_size < SECTION_HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid ELF section header size"));
+        }
+
+        let section = |index: usize| -> io::Result<&[u8]> {
+            let offset = section_headers_offset + index * section_header_size;
+            slice(bytes, offset, SECTION_HEADER_SIZE)
+        };
+        let section_contents = |header: &[u8]| -> io::Result<&[u8]> {
+            slice(bytes, read_u32(header, 16)? as usize, read_u32(header, 20)? as usize)
+        };
+
+        let mut ret = SymbolTable::new();
+
+        for index in 0..num_sections {
+            let header = section(index)?;
+            if read_u32(header, 4)? != SECTION_TYPE_SYMTAB {
+                continue;
+            }
+
+            let symbols = section_contents(header)?;
+            let string_table_index = read_u32(header, 24)? as usize;
+            if string_table_index >= num_sections {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid ELF string table index"));
+            }
+            let strings = section_contents(section(string_table_index)?)?;
+
+            for symbol in symbols.chunks(SYMBOL_SIZE).filter(|symbol| symbol.len() == SYMBOL_SIZE) {
+                let symbol_type = symbol[12] & 0x0f;
+                let section_index = read_u16(symbol, 14)?;
+                if symbol_type == SYMBOL_TYPE_SECTION || symbol_type == SYMBOL_TYPE_FILE || section_index == SECTION_INDEX_UNDEFINED {
+                    continue;
+                }
+
+                let name_offset = read_u32(symbol, 0)? as usize;
+                let name = match strings.get(name_offset..) {
+                    Some(name) => &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())],
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid ELF symbol name offset")),
+                };
+                let name = match String::from_utf8(name.to_vec()) {
+                    Ok(ref name) if is_valid_name(name) => name.clone(),
+                    _ => continue,
+                };
+
+                ret.insert(name, read_u32(symbol, 4)?);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Adds or moves a symbol
+    pub fn insert(&mut self, name: String, addr: u32) {
+        self.remove(&name);
+
+        {
+            let names = self.names.entry(addr).or_default();
+            let index = names.binary_search(&name).unwrap_or_else(|index| index);
+            names.insert(index, name.clone());
+        }
+        self.addrs.insert(name, addr);
+    }
+
+    /// Removes a symbol, returning its address if it existed
+    pub fn remove(&mut self, name: &str) -> Option<u32> {
+        let addr = self.addrs.remove(name)?;
+
+        let is_empty = {
+            let names = self.names.get_mut(&addr).unwrap();
+            names.retain(|other| other != name);
+            names.is_empty()
+        };
+        if is_empty {
+            self.names.remove(&addr);
+        }
+
+        Some(addr)
+    }
+
+    /// Adds all symbols from `other`, replacing existing ones with the same name
+    pub fn extend(&mut self, other: SymbolTable) {
+        for (addr, names) in other.names.into_iter() {
+            for name in names.into_iter() {
+                self.insert(name, addr);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.addrs.get(name).cloned()
+    }
+
+    /// Returns the names of the symbols at exactly `addr`
+    pub fn names_at(&self, addr: u32) -> &[String] {
+        self.names.get(&addr).map_or(&[], |names| &names[..])
+    }
+
+    /// Returns the closest symbol at or before `addr`
+    pub fn nearest(&self, addr: u32) -> Option<(&str, u32)> {
+        self.names.range(..=addr).next_back().map(|(&symbol_addr, names)| (names[0].as_str(), symbol_addr))
+    }
+
+    /// Returns the closest symbol after `addr`
+    pub fn next(&self, addr: u32) -> Option<(&str, u32)> {
+        if addr == 0xffffffff {
+            return None;
+        }
+        self.names.range(addr + 1..).next().map(|(&symbol_addr, names)| (names[0].as_str(), symbol_addr))
+    }
+
+    /// Iterates over all symbols, sorted by address
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = (&'a str, u32)> + 'a> {
+        Box::new(self.names.iter().flat_map(|(&addr, names)| names.iter().map(move |name| (name.as_str(), addr))))
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.addrs.clear();
+        self.names.clear();
+    }
+
+    /// Formats an address along with the closest symbol at or before it, eg.
+    /// `0x07000210 .main+0x10`
+    pub fn format_addr(&self, addr: u32) -> String {
+        match self.nearest(addr) {
+            Some((name, symbol_addr)) if symbol_addr == addr => format!("0x{:08x} .{}", addr, name),
+            Some((name, symbol_addr)) => format!("0x{:08x} .{}+0x{:x}", addr, name, addr - symbol_addr),
+            _ => format!("0x{:08x}", addr),
+        }
+    }
+}
+
+impl InstructionFormatter for SymbolTable {
+    fn format_target(&self, f: &mut fmt::Formatter, target: u32) -> fmt::Result {
+        write!(f, "{}", self.format_addr(target))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    match offset.checked_add(len) {
+        Some(end) if end <= bytes.len() => Ok(&bytes[offset..end]),
+        _ => Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected end of ELF file")),
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    let bytes = slice(bytes, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(slice(bytes, offset, 4)?);
+    Ok(u32::from_le_bytes(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL_TYPE_FUNC: u8 = 2;
+
+    fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Builds an ELF file with a null section, a symbol table and its string table
+    fn build_elf(symbols: &[(&str, u32, u8, u16)]) -> Vec<u8> {
+        let mut strings = vec![0];
+        let mut symbol_table = vec![0; SYMBOL_SIZE];
+        for &(name, value, symbol_type, section_index) in symbols {
+            push_u32(&mut symbol_table, strings.len() as _);
+            push_u32(&mut symbol_table, value);
+            push_u32(&mut symbol_table, 0);
+            symbol_table.push(symbol_type);
+            symbol_table.push(0);
+            push_u16(&mut symbol_table, section_index);
+
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+        }
+
+        let mut ret = Vec::new();
+        ret.extend_from_slice(ELF_MAGIC);
+        ret.extend_from_slice(&[ELF_CLASS_32, ELF_DATA_LITTLE_ENDIAN, 1]);
+        ret.resize(0x34, 0);
+
+        let symbol_table_offset = ret.len();
+        ret.extend_from_slice(&symbol_table);
+        let strings_offset = ret.len();
+        ret.extend_from_slice(&strings);
+
+        let section_headers_offset = ret.len();
+        ret[0x20] = section_headers_offset as _;
+        ret[0x21] = (section_headers_offset >> 8) as _;
+        ret[0x2e] = SECTION_HEADER_SIZE as _;
+        ret[0x30] = 3;
+
+        let sections = [
+            (0, 0, 0, 0),
+            (SECTION_TYPE_SYMTAB, symbol_table_offset, symbol_table.len(), 2),
+            (3, strings_offset, strings.len(), 0),
+        ];
+        for &(section_type, offset, size, link) in sections.iter() {
+            push_u32(&mut ret, 0);
+            push_u32(&mut ret, section_type);
+            push_u32(&mut ret, 0);
+            push_u32(&mut ret, 0);
+            push_u32(&mut ret, offset as _);
+            push_u32(&mut ret, size as _);
+            push_u32(&mut ret, link);
+            ret.resize(ret.len() + SECTION_HEADER_SIZE - 28, 0);
+        }
+
+        ret
+    }
+
+    #[test]
+    fn parse_map() {
+        let symbols = SymbolTable::parse_map("# Entry points\nmain 0x07000200\n\n.vblank $07000a10 # handler\n  _start 7000000\nalias 0x07000200\n").unwrap();
+
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.get("main"), Some(0x07000200));
+        assert_eq!(symbols.get("vblank"), Some(0x07000a10));
+        assert_eq!(symbols.get("_start"), Some(0x07000000));
+        assert_eq!(symbols.names_at(0x07000200), &["alias".to_string(), "main".to_string()]);
+        assert_eq!(symbols.format_addr(0x07000a14), "0x07000a14 .vblank+0x4");
+    }
+
+    #[test]
+    fn parse_map_reports_line_numbers() {
+        for &(text, message) in [
+            ("main 0x07000200\n1st 0x07000000", "Symbol file line 2: Invalid symbol name: 1st"),
+            ("main 0x0700020g", "Symbol file line 1: Invalid address: 0x0700020g"),
+            ("main", "Symbol file line 1: Missing address for symbol: main"),
+            ("\n\nmain 0x07000200 extra", "Symbol file line 3: Unexpected token: extra"),
+        ].iter() {
+            let err = SymbolTable::parse_map(text).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("main".into(), 0x07000200);
+        symbols.insert("alias".into(), 0x07000200);
+        symbols.insert("counter".into(), 0x0500fff4);
+
+        let text = symbols.iter().map(|(name, addr)| format!("{} 0x{:08x}\n", name, addr)).collect::<String>();
+        assert_eq!(text, "counter 0x0500fff4\nalias 0x07000200\nmain 0x07000200\n");
+
+        let loaded = SymbolTable::parse_map(&text).unwrap();
+        assert_eq!(loaded.iter().collect::<Vec<_>>(), symbols.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parse_elf() {
+        let bytes = build_elf(&[
+            ("main", 0x07000200, SYMBOL_TYPE_FUNC, 1),
+            ("counter", 0x0500fff4, 0, 2),
+            (".text", 0x07000000, SYMBOL_TYPE_SECTION, 1),
+            ("main.c", 0, SYMBOL_TYPE_FILE, 0xfff1),
+            ("printf", 0, SYMBOL_TYPE_FUNC, SECTION_INDEX_UNDEFINED),
+            (".L12", 0x07000230, 0, 1),
+        ]);
+
+        let symbols = SymbolTable::parse_elf(&bytes).unwrap();
+        assert_eq!(symbols.iter().collect::<Vec<_>>(), vec![("counter", 0x0500fff4), ("main", 0x07000200)]);
+    }
+
+    #[test]
+    fn parse_elf_rejects_bad_files() {
+        assert!(SymbolTable::parse_elf(b"main 0x07000200").is_err());
+
+        let bytes = build_elf(&[("main", 0x07000200, SYMBOL_TYPE_FUNC, 1)]);
+
+        let mut big_endian = bytes.clone();
+        big_endian[5] = 2;
+        assert!(SymbolTable::parse_elf(&big_endian).is_err());
+
+        for len in 0..bytes.len() {
+            assert!(SymbolTable::parse_elf(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use std::io::{Error, ErrorKind};
+
+/// Parses a hex number, optionally prefixed with 0x or $
+pub fn parse_hex(token: &str) -> Option<u32> {
+    let digits = token.strip_prefix("0x")
+        .or_else(|| token.strip_prefix('$'))
+        .unwrap_or(token);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Builds an error for a line in a text file, eg. "Cheat file line 3: ..."
+pub fn parse_error(file_kind: &str, line_index: usize, message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{} line {}: {}", file_kind, line_index + 1, message))
+}
@@ -0,0 +1,462 @@
+use png::{PngColorType, write_png_file};
+
+use rustual_boy_core::vip::Vip;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+pub const NUM_CHARS: u32 = 2048;
+pub const NUM_BGMAPS: u32 = 14;
+pub const NUM_OBJS: u32 = 1024;
+pub const NUM_WORLDS: u32 = 32;
+
+const CHAR_TABLE_OFFSETS: [u32; 4] = [0x00006000, 0x0000e000, 0x00016000, 0x0001e000];
+const CHARS_PER_TABLE: u32 = 512;
+const CHAR_LENGTH: u32 = 16;
+
+const BGMAP_OFFSET: u32 = 0x00020000;
+const BGMAP_LENGTH: u32 = 0x00002000;
+const BGMAP_CHARS: u32 = 64;
+
+const OBJ_ATTRIBS_OFFSET: u32 = 0x0003e000;
+const OBJ_ATTRIBS_LENGTH: u32 = 8;
+
+const WORLD_ATTRIBS_OFFSET: u32 = 0x0003d800;
+const WORLD_ATTRIBS_LENGTH: u32 = 32;
+
+// Images are laid out this many chars (or OBJs) wide
+const IMAGE_COLUMNS: u32 = 32;
+
+// Colors 0-3 are shown as evenly spaced gray levels rather than through the
+//  brightness registers, so dark palettes stay readable
+const GRAY_LEVELS: [u8; 4] = [0x00, 0x55, 0xaa, 0xff];
+
+/// One of the palette registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VramPalette {
+    /// BG palette (GPLT0-3)
+    Bg(usize),
+    /// OBJ palette (JPLT0-3)
+    Obj(usize),
+}
+
+impl VramPalette {
+    pub fn value(&self, vip: &Vip) -> u8 {
+        match *self {
+            VramPalette::Bg(index) => vip.gplt(index),
+            VramPalette::Obj(index) => vip.jplt(index),
+        }
+    }
+}
+
+impl fmt::Display for VramPalette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VramPalette::Bg(index) => write!(f, "GPLT{}", index),
+            VramPalette::Obj(index) => write!(f, "JPLT{}", index),
+        }
+    }
+}
+
+/// A BGMap cell: a char along with its palette and flips
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CharEntry {
+    pub palette: usize,
+    pub horizontal_flip: bool,
+    pub vertical_flip: bool,
+    pub char_index: u32,
+}
+
+impl CharEntry {
+    pub fn decode(value: u16) -> CharEntry {
+        CharEntry {
+            palette: (value >> 14) as usize,
+            horizontal_flip: (value & 0x2000) != 0,
+            vertical_flip: (value & 0x1000) != 0,
+            char_index: (value & 0x07ff) as u32,
+        }
+    }
+}
+
+impl fmt::Display for CharEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "char {:4} pal {}", self.char_index, self.palette)?;
+        if self.horizontal_flip {
+            write!(f, " hflip")?;
+        }
+        if self.vertical_flip {
+            write!(f, " vflip")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjAttribs {
+    pub x: i16,
+    pub left_on: bool,
+    pub right_on: bool,
+    pub parallax: i16,
+    pub y: i16,
+    /// The char, whose palette is a JPLT rather than a GPLT
+    pub char_entry: CharEntry,
+}
+
+impl fmt::Display for ObjAttribs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x {:4} y {:4} parallax {:4} {}{} {}",
+            self.x,
+            self.y,
+            self.parallax,
+            if self.left_on { "L" } else { "-" },
+            if self.right_on { "R" } else { "-" },
+            self.char_entry)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorldMode {
+    Normal,
+    LineShift,
+    Affine,
+    Obj,
+}
+
+impl fmt::Display for WorldMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WorldMode::Normal => write!(f, "normal"),
+            WorldMode::LineShift => write!(f, "line shift"),
+            WorldMode::Affine => write!(f, "affine"),
+            WorldMode::Obj => write!(f, "obj"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldAttribs {
+    pub header: u16,
+    pub left_on: bool,
+    pub right_on: bool,
+    pub mode: WorldMode,
+    /// Number of BGMaps across and down, as powers of two
+    pub bg_width: u32,
+    pub bg_height: u32,
+    pub overplane: bool,
+    /// Set for the END world, which stops drawing
+    pub stop: bool,
+    /// Index of the first BGMap
+    pub base: u32,
+    pub x: i16,
+    pub parallax: i16,
+    pub y: i16,
+    pub bg_x: i16,
+    pub bg_parallax: i16,
+    pub bg_y: i16,
+    pub width: u32,
+    pub height: u32,
+    pub param_base: u32,
+    pub overplane_char: u32,
+}
+
+impl WorldAttribs {
+    /// Returns true for worlds that are skipped without being drawn
+    pub fn is_dummy(&self) -> bool {
+        self.header == 0
+    }
+}
+
+impl fmt::Display for WorldAttribs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_dummy() {
+            return write!(f, "dummy");
+        }
+        if self.stop {
+            return write!(f, "end");
+        }
+
+        write!(f, "{} {}{}", self.mode, if self.left_on { "L" } else { "-" }, if self.right_on { "R" } else { "-" })?;
+        if self.mode == WorldMode::Obj {
+            return Ok(());
+        }
+        write!(f, " bgmap {} ({}x{}) at {},{} size {}x{} parallax {} bg {},{} bg parallax {}",
+            self.base,
+            1 << self.bg_width,
+            1 << self.bg_height,
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            self.parallax,
+            self.bg_x,
+            self.bg_y,
+            self.bg_parallax)?;
+        if self.mode != WorldMode::Normal {
+            write!(f, " params 0x{:04x}", self.param_base)?;
+        }
+        if self.overplane {
+            write!(f, " overplane {}", self.overplane_char)?;
+        }
+        Ok(())
+    }
+}
+
+/// A grayscale image decoded from VRAM
+pub struct VramImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl VramImage {
+    fn new(width: u32, height: u32) -> VramImage {
+        VramImage {
+            width: width,
+            height: height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        write_png_file(file_name, self.width, self.height, PngColorType::Grayscale, &self.pixels)
+    }
+}
+
+/// Decodes the VIP's chars, BGMaps, OBJ attributes and World attributes from
+/// VRAM, and renders them into images. Transparent pixels (palette index 0)
+/// are drawn black.
+pub struct VramViewer<'a> {
+    vip: &'a Vip,
+}
+
+impl<'a> VramViewer<'a> {
+    pub fn new(vip: &'a Vip) -> VramViewer<'a> {
+        VramViewer {
+            vip: vip,
+        }
+    }
+
+    /// Returns the palette indices of a char's pixels, row by row
+    pub fn char_pixels(&self, char_index: u32) -> [u8; 64] {
+        let char_index = char_index % NUM_CHARS;
+        let table_offset = CHAR_TABLE_OFFSETS[(char_index / CHARS_PER_TABLE) as usize];
+        let char_offset = table_offset + (char_index % CHARS_PER_TABLE) * CHAR_LENGTH;
+
+        let mut ret = [0; 64];
+        for y in 0..8 {
+            let row = self.read_halfword(char_offset + y * 2);
+            for x in 0..8 {
+                ret[(y * 8 + x) as usize] = ((row >> (x * 2)) & 0x03) as u8;
+            }
+        }
+        ret
+    }
+
+    /// Returns the char entry at a cell of a BGMap, which is 64 cells square
+    pub fn bgmap_entry(&self, bgmap_index: u32, x: u32, y: u32) -> CharEntry {
+        let offset = BGMAP_OFFSET + bgmap_index * BGMAP_LENGTH + (y * BGMAP_CHARS + x) * 2;
+        CharEntry::decode(self.read_halfword(offset))
+    }
+
+    pub fn obj(&self, obj_index: u32) -> ObjAttribs {
+        let offset = OBJ_ATTRIBS_OFFSET + obj_index * OBJ_ATTRIBS_LENGTH;
+        let l_r_parallax = self.read_halfword(offset + 2);
+        ObjAttribs {
+            x: self.read_halfword(offset) as i16,
+            left_on: (l_r_parallax & 0x8000) != 0,
+            right_on: (l_r_parallax & 0x4000) != 0,
+            parallax: ((l_r_parallax << 2) as i16) >> 2,
+            y: self.read_halfword(offset + 4) as i16,
+            char_entry: CharEntry::decode(self.read_halfword(offset + 6)),
+        }
+    }
+
+    pub fn world(&self, world_index: u32) -> WorldAttribs {
+        let offset = WORLD_ATTRIBS_OFFSET + world_index * WORLD_ATTRIBS_LENGTH;
+        let header = self.read_halfword(offset);
+        WorldAttribs {
+            header: header,
+            left_on: (header & 0x8000) != 0,
+            right_on: (header & 0x4000) != 0,
+            mode: match (header >> 12) & 0x03 {
+                0 => WorldMode::Normal,
+                1 => WorldMode::LineShift,
+                2 => WorldMode::Affine,
+                _ => WorldMode::Obj,
+            },
+            bg_width: ((header >> 10) & 0x03) as u32,
+            bg_height: ((header >> 8) & 0x03) as u32,
+            overplane: (header & 0x0080) != 0,
+            stop: (header & 0x0040) != 0,
+            base: (header & 0x000f) as u32,
+            x: self.read_halfword(offset + 2) as i16,
+            parallax: self.read_halfword(offset + 4) as i16,
+            y: self.read_halfword(offset + 6) as i16,
+            bg_x: self.read_halfword(offset + 8) as i16,
+            bg_parallax: self.read_halfword(offset + 10) as i16,
+            bg_y: self.read_halfword(offset + 12) as i16,
+            width: (self.read_halfword(offset + 14) as u32) + 1,
+            height: (self.read_halfword(offset + 16) as u32) + 1,
+            param_base: self.read_halfword(offset + 18) as u32,
+            overplane_char: self.read_halfword(offset + 20) as u32,
+        }
+    }
+
+    /// Renders all chars under one palette, 32 chars across
+    pub fn render_chars(&self, palette: VramPalette) -> VramImage {
+        let palette = palette.value(self.vip);
+        let mut image = VramImage::new(IMAGE_COLUMNS * 8, NUM_CHARS / IMAGE_COLUMNS * 8);
+        for char_index in 0..NUM_CHARS {
+            let entry = CharEntry {
+                palette: 0,
+                horizontal_flip: false,
+                vertical_flip: false,
+                char_index: char_index,
+            };
+            self.draw_char(&mut image, char_index % IMAGE_COLUMNS * 8, char_index / IMAGE_COLUMNS * 8, entry, palette);
+        }
+        image
+    }
+
+    /// Renders a BGMap (512x512 pixels) with each cell's own GPLT palette
+    pub fn render_bgmap(&self, bgmap_index: u32) -> VramImage {
+        let mut image = VramImage::new(BGMAP_CHARS * 8, BGMAP_CHARS * 8);
+        for y in 0..BGMAP_CHARS {
+            for x in 0..BGMAP_CHARS {
+                let entry = self.bgmap_entry(bgmap_index, x, y);
+                let palette = self.vip.gplt(entry.palette);
+                self.draw_char(&mut image, x * 8, y * 8, entry, palette);
+            }
+        }
+        image
+    }
+
+    /// Renders the char of every OBJ with its JPLT palette, 32 OBJs across
+    pub fn render_objs(&self) -> VramImage {
+        let mut image = VramImage::new(IMAGE_COLUMNS * 8, NUM_OBJS / IMAGE_COLUMNS * 8);
+        for obj_index in 0..NUM_OBJS {
+            let entry = self.obj(obj_index).char_entry;
+            let palette = self.vip.jplt(entry.palette);
+            self.draw_char(&mut image, obj_index % IMAGE_COLUMNS * 8, obj_index / IMAGE_COLUMNS * 8, entry, palette);
+        }
+        image
+    }
+
+    fn draw_char(&self, image: &mut VramImage, image_x: u32, image_y: u32, entry: CharEntry, palette: u8) {
+        let pixels = self.char_pixels(entry.char_index);
+        for y in 0..8 {
+            for x in 0..8 {
+                let char_x = if entry.horizontal_flip { 7 - x } else { x };
+                let char_y = if entry.vertical_flip { 7 - y } else { y };
+                let palette_index = pixels[(char_y * 8 + char_x) as usize];
+                let color = if palette_index == 0 { 0 } else { (palette >> (palette_index * 2)) & 0x03 };
+                image.pixels[((image_y + y) * image.width + image_x + x) as usize] = GRAY_LEVELS[color as usize];
+            }
+        }
+    }
+
+    fn read_halfword(&self, offset: u32) -> u16 {
+        let vram = self.vip.vram();
+        (vram[offset as usize] as u16) | ((vram[(offset + 1) as usize] as u16) << 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::interconnect::Interconnect;
+    use rustual_boy_core::rom::{Rom, MIN_ROM_SIZE};
+    use rustual_boy_core::sram::Sram;
+
+    const GPLT1: u32 = 0x0005f862;
+
+    // Char 513 is the second char of the second char table
+    const CHAR_INDEX: u32 = 513;
+    const CHAR_OFFSET: u32 = 0x0000e010;
+
+    fn test_interconnect() -> Interconnect {
+        let rom = Rom::from_bytes(&vec![0; MIN_ROM_SIZE]).unwrap();
+        let mut interconnect = Interconnect::new(rom, Sram::new());
+
+        // Top row: palette indices 1, 2, 0, 0, 0, 0, 0, 3; bottom row: 3, 0, ...
+        interconnect.write_halfword(CHAR_OFFSET, 0xc009);
+        interconnect.write_halfword(CHAR_OFFSET + 14, 0x0003);
+        // Map indices 1-3 straight to colors 1-3
+        interconnect.write_halfword(GPLT1, 0xe4);
+
+        interconnect
+    }
+
+    fn pixel(image: &VramImage, x: u32, y: u32) -> u8 {
+        image.pixels[(y * image.width + x) as usize]
+    }
+
+    #[test]
+    fn decodes_char_pixels() {
+        let interconnect = test_interconnect();
+        let viewer = VramViewer::new(interconnect.vip());
+
+        let pixels = viewer.char_pixels(CHAR_INDEX);
+        let mut expected = [0; 64];
+        expected[0] = 1;
+        expected[1] = 2;
+        expected[7] = 3;
+        expected[56] = 3;
+        assert_eq!(&pixels[..], &expected[..]);
+        assert_eq!(&viewer.char_pixels(CHAR_INDEX + NUM_CHARS)[..], &expected[..]);
+    }
+
+    #[test]
+    fn renders_chars_through_palettes() {
+        let mut interconnect = test_interconnect();
+        {
+            let image = VramViewer::new(interconnect.vip()).render_chars(VramPalette::Bg(1));
+            assert_eq!((image.width, image.height), (256, 512));
+            let (x, y) = (CHAR_INDEX % 32 * 8, CHAR_INDEX / 32 * 8);
+            assert_eq!([pixel(&image, x, y), pixel(&image, x + 1, y), pixel(&image, x + 2, y), pixel(&image, x + 7, y)], [0x55, 0xaa, 0x00, 0xff]);
+        }
+
+        // Index 0 stays transparent even when the palette maps it to a color
+        interconnect.write_halfword(GPLT1, 0x1b);
+        let image = VramViewer::new(interconnect.vip()).render_chars(VramPalette::Bg(1));
+        let (x, y) = (CHAR_INDEX % 32 * 8, CHAR_INDEX / 32 * 8);
+        assert_eq!([pixel(&image, x, y), pixel(&image, x + 1, y), pixel(&image, x + 2, y), pixel(&image, x + 7, y)], [0xaa, 0x55, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn decodes_and_renders_bgmap_entries() {
+        let mut interconnect = test_interconnect();
+        // BGMap 1, cell (2, 3): GPLT1, horizontally flipped
+        interconnect.write_halfword(BGMAP_OFFSET + BGMAP_LENGTH + (3 * 64 + 2) * 2, 0x6000 | CHAR_INDEX as u16);
+        let viewer = VramViewer::new(interconnect.vip());
+
+        let entry = viewer.bgmap_entry(1, 2, 3);
+        assert_eq!(entry, CharEntry { palette: 1, horizontal_flip: true, vertical_flip: false, char_index: CHAR_INDEX });
+        assert_eq!(entry.to_string(), "char  513 pal 1 hflip");
+
+        let image = viewer.render_bgmap(1);
+        assert_eq!([pixel(&image, 16, 24), pixel(&image, 22, 24), pixel(&image, 23, 24), pixel(&image, 23, 31)], [0xff, 0xaa, 0x55, 0xff]);
+    }
+
+    #[test]
+    fn decodes_obj_attribs() {
+        let mut interconnect = test_interconnect();
+        let offset = OBJ_ATTRIBS_OFFSET + 5 * OBJ_ATTRIBS_LENGTH;
+        interconnect.write_halfword(offset, (-3i16) as u16);
+        interconnect.write_halfword(offset + 2, 0x8000 | 0x3ffe);
+        interconnect.write_halfword(offset + 4, 10);
+        interconnect.write_halfword(offset + 6, 0x9007);
+
+        let obj = VramViewer::new(interconnect.vip()).obj(5);
+        assert_eq!(obj, ObjAttribs {
+            x: -3,
+            left_on: true,
+            right_on: false,
+            parallax: -2,
+            y: 10,
+            char_entry: CharEntry { palette: 2, horizontal_flip: false, vertical_flip: true, char_index: 7 },
+        });
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use rustual_boy_core::sinks::{AudioFrame, SinkRef};
 
 use std::io::{self, Write, Seek, SeekFrom, BufWriter};
@@ -9,6 +7,8 @@ use std::path::Path;
 const NUM_CHANNELS: u32 = 2;
 const BITS_PER_SAMPLE: u32 = 16;
 
+/// A buffer sink that writes audio frames to a 16-bit stereo WAV file.
+/// The header is finalized when the sink is dropped.
 pub struct WaveFileBufferSink {
     writer: BufWriter<File>,
     num_frames: u32,